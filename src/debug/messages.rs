@@ -0,0 +1,21 @@
+//! A small ui element showing how many messages the message bus has had to drop.
+
+use crate::prelude::*;
+use crate::td_mode::MessageStats;
+use bevy_egui::{egui, EguiContext};
+
+pub struct MessageStatsPlugin;
+
+impl Plugin for MessageStatsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(draw_message_stats_ui.run_in_state(GameState::TDMode));
+    }
+}
+
+fn draw_message_stats_ui(mut egui_context: ResMut<EguiContext>, stats: Res<MessageStats>) {
+    egui::Window::new("Messages").show(egui_context.ctx_mut(), |ui| {
+        ui.label(format!("Dropped: {}", stats.dropped));
+        ui.label(format!("Orphaned: {}", stats.orphaned));
+        ui.label(format!("Unhandled: {}", stats.unhandled));
+    });
+}
@@ -8,6 +8,7 @@ use crate::prelude::*;
 use bevy_inspector_egui;
 
 mod fps;
+mod messages;
 
 /*
 const VERTICAL_MARKER_HEIGHT: f32 = 1.0;
@@ -23,6 +24,7 @@ pub struct TKDebugPlugin;
 impl Plugin for TKDebugPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugin(fps::FPSTrackerPlugin)
+            .add_plugin(messages::MessageStatsPlugin)
             .add_plugin(bevy_inspector_egui::WorldInspectorPlugin::new());
 
         //   .add_startup_system(initialize_debug_models)
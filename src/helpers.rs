@@ -57,6 +57,13 @@ impl Mul<Vec3> for Coordinate {
     }
 }
 
+impl From<Coordinate> for Vec2 {
+    /// Converts a coordinate to a point on the map's plane in tile units.
+    fn from(coord: Coordinate) -> Self {
+        Vec2::new(coord.x as f32, coord.y as f32)
+    }
+}
+
 impl std::fmt::Display for Coordinate {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "({}, {})", self.x, self.y)
@@ -68,26 +75,108 @@ impl Coordinate {
         (self.x.abs_diff(other.x) + self.y.abs_diff(other.y)) as u32
     }
 
-    pub fn cardinals(&self) -> Vec<Self> {
-        let mut result = vec![
-            *self + Coordinate::from((1, 0)),
-            *self + Coordinate::from((0, 1)),
-        ];
+    /// The tile one step away in `direction`, if it's inside a map of `dimensions`.
+    pub fn step(&self, direction: GridDirection, dimensions: (usize, usize)) -> Option<Self> {
+        let (dx, dy) = direction.offset();
+        let x = self.x.checked_add_signed(dx)?;
+        let y = self.y.checked_add_signed(dy)?;
 
-        if self.x > 0 {
-            result.push(Coordinate::from((self.x - 1, self.y)));
+        if x < dimensions.0 && y < dimensions.1 {
+            Some((x, y).into())
+        } else {
+            None
         }
+    }
 
-        if self.y > 0 {
-            result.push(Coordinate::from((self.x, self.y - 1)));
-        }
+    /// The neighbours of this tile in each of `directions` that are inside a map of
+    /// `dimensions`, along with the direction each one is in.
+    pub fn neighbours(
+        self,
+        directions: &[GridDirection],
+        dimensions: (usize, usize),
+    ) -> impl Iterator<Item = (GridDirection, Self)> + '_ {
+        directions
+            .iter()
+            .filter_map(move |&d| Some((d, self.step(d, dimensions)?)))
+    }
+
+    /// The direction `other` is in, or None if it isn't next to this tile.
+    pub fn direction_to(&self, other: &Self) -> Option<GridDirection> {
+        let offset = (
+            other.x as isize - self.x as isize,
+            other.y as isize - self.y as isize,
+        );
 
-        result
+        GridDirection::ALL
+            .into_iter()
+            .find(|d| d.offset() == offset)
     }
 
     pub const ZERO: Self = Self { x: 0, y: 0 };
 }
 
+/// The directions from a tile to its eight neighbours. North is towards y = 0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GridDirection {
+    East,
+    South,
+    West,
+    North,
+    SouthEast,
+    SouthWest,
+    NorthWest,
+    NorthEast,
+}
+
+impl GridDirection {
+    /// The four directions sharing an edge with a tile
+    pub const CARDINALS: [Self; 4] = [Self::East, Self::South, Self::West, Self::North];
+
+    /// Every direction, cardinals first
+    pub const ALL: [Self; 8] = [
+        Self::East,
+        Self::South,
+        Self::West,
+        Self::North,
+        Self::SouthEast,
+        Self::SouthWest,
+        Self::NorthWest,
+        Self::NorthEast,
+    ];
+
+    /// How far a step in this direction moves along x and y.
+    pub fn offset(self) -> (isize, isize) {
+        match self {
+            Self::East => (1, 0),
+            Self::South => (0, 1),
+            Self::West => (-1, 0),
+            Self::North => (0, -1),
+            Self::SouthEast => (1, 1),
+            Self::SouthWest => (-1, 1),
+            Self::NorthWest => (-1, -1),
+            Self::NorthEast => (1, -1),
+        }
+    }
+
+    pub fn opposite(self) -> Self {
+        match self {
+            Self::East => Self::West,
+            Self::South => Self::North,
+            Self::West => Self::East,
+            Self::North => Self::South,
+            Self::SouthEast => Self::NorthWest,
+            Self::SouthWest => Self::NorthEast,
+            Self::NorthWest => Self::SouthEast,
+            Self::NorthEast => Self::SouthWest,
+        }
+    }
+
+    pub fn is_diagonal(self) -> bool {
+        let (dx, dy) = self.offset();
+        dx != 0 && dy != 0
+    }
+}
+
 /// A pointer to the base of a scene containing models loaded from a gltf format.
 #[derive(Component)]
 pub struct ModelRoot(pub Entity);
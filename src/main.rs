@@ -43,6 +43,17 @@ use crate::prelude::*;
 use bevy_egui::EguiPlugin;
 
 fn main() {
+    // `simulate` steps the chemistry on a map file and prints the result without opening a
+    // window, for tuning the reaction table.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("simulate") {
+        if let Err(e) = td_mode::run_simulation(&args[1..]) {
+            eprintln!("{e:#}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let mut app = App::new();
 
     app.insert_resource(WindowDescriptor {
@@ -51,6 +62,11 @@ fn main() {
         height: 720.0,
         ..default()
     })
+    // Lets data files like the chemistry reaction table be edited while the game is running.
+    .insert_resource(bevy::asset::AssetServerSettings {
+        watch_for_changes: true,
+        ..default()
+    })
     .add_plugins(DefaultPlugins)
     .add_plugin(EguiPlugin);
 
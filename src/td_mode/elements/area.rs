@@ -0,0 +1,473 @@
+//! Element messages that cover an area of the map
+//!
+//! An area message names a tile and a shape around it instead of a single target. Before the
+//! element messages are consolidated, each area message is resolved against the map into one
+//! ordinary message per tile it covers, so everything downstream only ever sees single-target
+//! messages.
+
+use super::*;
+use map::MapRoot;
+
+/// How many passes of the message bus an area message can wait for the map's tiles to be spawned.
+pub const AREA_MESSAGE_MAX_AGE: u32 = 3;
+
+/// The tiles an area message covers, relative to its origin tile.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AreaShape {
+    /// Every tile within `radius` steps of the origin, which makes a diamond on a four-way map and
+    /// a square on an eight-way one
+    Radius(u32),
+    /// Tiles within `range` steps of the origin that are no more than `half_angle` radians off
+    /// `direction`
+    Cone {
+        direction: Vec2,
+        half_angle: f32,
+        range: u32,
+    },
+    /// A one tile wide line running `length` tiles from the origin along `direction`
+    Line { direction: Vec2, length: u32 },
+    /// A rectangle centred on the origin reaching `half_width` tiles either side along X and
+    /// `half_height` tiles either side along Y
+    Rectangle { half_width: u32, half_height: u32 },
+}
+
+impl AreaShape {
+    /// Whether a tile `offset` away from the origin and `steps` steps from it under the map's
+    /// neighbourhood is inside the shape.
+    fn covers(&self, offset: Vec2, steps: u32) -> bool {
+        match *self {
+            Self::Radius(radius) => steps <= radius,
+            Self::Cone {
+                direction,
+                half_angle,
+                range,
+            } => {
+                steps <= range
+                    && (offset == Vec2::ZERO || direction.angle_between(offset).abs() <= half_angle)
+            }
+            Self::Line { direction, length } => {
+                let direction = direction.normalize_or_zero();
+                let along = offset.dot(direction);
+
+                along >= 0.0 && along <= length as f32 && direction.perp_dot(offset).abs() <= 0.5
+            }
+            Self::Rectangle {
+                half_width,
+                half_height,
+            } => offset.x.abs() <= half_width as f32 && offset.y.abs() <= half_height as f32,
+        }
+    }
+
+    /// How far the shape can reach from its origin along either axis.
+    fn reach(&self) -> u32 {
+        match *self {
+            Self::Radius(radius) => radius,
+            Self::Cone { range, .. } => range,
+            Self::Line { length, .. } => length,
+            Self::Rectangle {
+                half_width,
+                half_height,
+            } => half_width.max(half_height),
+        }
+    }
+
+    /// Every tile on the map the shape covers when placed on `origin`, in index order.
+    pub fn footprint(&self, map: &Map, origin: Coordinate) -> Vec<Coordinate> {
+        let reach = self.reach() as usize;
+        let min_x = origin.x.saturating_sub(reach);
+        let min_y = origin.y.saturating_sub(reach);
+        let max_x = (origin.x + reach).min(map.dimensions.0.saturating_sub(1));
+        let max_y = (origin.y + reach).min(map.dimensions.1.saturating_sub(1));
+
+        (min_y..=max_y)
+            .flat_map(|y| (min_x..=max_x).map(move |x| Coordinate::from((x, y))))
+            .filter(|&coord| coord.x < map.dimensions.0 && coord.y < map.dimensions.1)
+            .filter(|&coord| {
+                let steps = map.movement_rules.neighbourhood.distance(coord, origin);
+                self.covers(Vec2::from(coord) - Vec2::from(origin), steps)
+            })
+            .collect()
+    }
+}
+
+/// How the elements in an area message thin out away from its origin.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Falloff {
+    /// Every tile gets the full amount
+    None,
+    /// Each tile gets this many percent less than the full amount for every step it is from the
+    /// origin
+    Linear(u32),
+}
+
+impl Falloff {
+    /// The elements a tile `distance` away from the origin gets out of `elements`.
+    pub fn apply(self, elements: &ElementalAffliction, distance: u32) -> ElementalAffliction {
+        match self {
+            Self::None => *elements,
+            Self::Linear(percent) => {
+                let kept = 100u32.saturating_sub(percent.saturating_mul(distance));
+                let mut result = ElementalAffliction::empty();
+                elements.iter().for_each(|(element, amount)| {
+                    // Widened so huge amounts don't overflow before they're scaled back down.
+                    let amount = (u64::from(amount) * u64::from(kept) / 100) as u32;
+                    if amount > 0 {
+                        result.add_element(element, amount);
+                    }
+                });
+
+                result
+            }
+        }
+    }
+}
+
+/// Component for messages aimed at an area of the map rather than a single entity.
+///
+/// Area messages carry the same `ApplyElement` or `RemoveElements` tag and `ElementalAffliction`
+/// as single-target messages, but a `TargetArea` in place of a `Target`.
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct TargetArea {
+    pub origin: Coordinate,
+    pub shape: AreaShape,
+    pub falloff: Falloff,
+}
+
+#[derive(Bundle)]
+pub struct ApplyAreaElementMessage {
+    element: ElementalAffliction,
+    area: TargetArea,
+    message: Message,
+    apply_element: ApplyElement,
+}
+
+impl ApplyAreaElementMessage {
+    pub fn new(element: ElementalAffliction, area: TargetArea) -> Self {
+        Self {
+            element,
+            area,
+            message: Message,
+            apply_element: ApplyElement,
+        }
+    }
+}
+
+/// Split every area message into one message for each tile it covers. While the map's tiles are
+/// still being spawned the messages are left for a later pass, so their elements aren't lost.
+pub fn resolve_area_messages(
+    message_query: Query<
+        (
+            Entity,
+            &TargetArea,
+            &ElementalAffliction,
+            Option<&ApplyElement>,
+            Option<&RemoveElements>,
+        ),
+        (With<Message>, Without<Handled>),
+    >,
+    map_root_query: Query<&MapRoot>,
+    map: Res<Map>,
+    mut commands: Commands,
+) {
+    let tile_entities = match map_root_query.get_single() {
+        Ok(root) if root.tile_entities.len() == map.tile_count() => &root.tile_entities,
+        _ => return,
+    };
+
+    message_query
+        .iter()
+        .for_each(|(message_entity, area, elements, apply, remove)| {
+            area.shape
+                .footprint(&map, area.origin)
+                .into_iter()
+                .map(|coord| {
+                    let tile = tile_entities[map.coord_to_idx(coord)];
+                    (
+                        tile,
+                        area.falloff.apply(
+                            elements,
+                            map.movement_rules
+                                .neighbourhood
+                                .distance(coord, area.origin),
+                        ),
+                    )
+                })
+                .filter(|(_, elements)| !elements.is_empty())
+                .for_each(|(tile, elements)| {
+                    let mut tile_message = commands.spawn();
+                    tile_message
+                        .insert(Message)
+                        .insert(Target(tile))
+                        .insert(elements);
+                    if apply.is_some() {
+                        tile_message.insert(ApplyElement);
+                    }
+                    if remove.is_some() {
+                        tile_message.insert(RemoveElements);
+                    }
+                });
+
+            commands.entity(message_entity).insert(Handled);
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Draw a shape's footprint on a 7x7 map with its origin in the middle, one row per line.
+    fn draw(shape: AreaShape) -> Vec<String> {
+        draw_with(shape, Neighbourhood::FourWay)
+    }
+
+    fn draw_with(shape: AreaShape, neighbourhood: Neighbourhood) -> Vec<String> {
+        let mut map = Map::new((7, 7));
+        map.movement_rules.neighbourhood = neighbourhood;
+        let footprint = shape.footprint(&map, (3, 3).into());
+
+        (0..7)
+            .map(|y| {
+                (0..7)
+                    .map(|x| {
+                        if footprint.contains(&(x, y).into()) {
+                            '#'
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn radius_footprint() {
+        assert_eq!(
+            draw(AreaShape::Radius(2)),
+            [
+                ".......", //
+                "...#...", //
+                "..###..", //
+                ".#####.", //
+                "..###..", //
+                "...#...", //
+                ".......",
+            ]
+        );
+    }
+
+    #[test]
+    fn radius_footprint_is_square_on_eight_way_maps() {
+        assert_eq!(
+            draw_with(AreaShape::Radius(1), Neighbourhood::EightWay),
+            [
+                ".......", //
+                ".......", //
+                "..###..", //
+                "..###..", //
+                "..###..", //
+                ".......", //
+                ".......",
+            ]
+        );
+    }
+
+    #[test]
+    fn cone_footprint() {
+        assert_eq!(
+            draw(AreaShape::Cone {
+                direction: Vec2::X,
+                half_angle: std::f32::consts::FRAC_PI_3,
+                range: 3,
+            }),
+            [
+                ".......", //
+                ".......", //
+                "....##.", //
+                "...####", //
+                "....##.", //
+                ".......", //
+                ".......",
+            ]
+        );
+    }
+
+    #[test]
+    fn line_footprint() {
+        assert_eq!(
+            draw(AreaShape::Line {
+                direction: Vec2::new(0.0, -1.0),
+                length: 5,
+            }),
+            [
+                "...#...", //
+                "...#...", //
+                "...#...", //
+                "...#...", //
+                ".......", //
+                ".......", //
+                ".......",
+            ]
+        );
+        assert_eq!(
+            draw(AreaShape::Line {
+                direction: Vec2::ONE,
+                length: 3,
+            }),
+            [
+                ".......", //
+                ".......", //
+                ".......", //
+                "...#...", //
+                "....#..", //
+                ".....#.", //
+                ".......",
+            ]
+        );
+    }
+
+    #[test]
+    fn rectangle_footprint_is_clipped_to_the_map() {
+        assert_eq!(
+            draw(AreaShape::Rectangle {
+                half_width: 4,
+                half_height: 1,
+            }),
+            [
+                ".......", //
+                ".......", //
+                "#######", //
+                "#######", //
+                "#######", //
+                ".......", //
+                ".......",
+            ]
+        );
+    }
+
+    #[test]
+    fn falloff_thins_elements_out_with_distance() {
+        let elements = ElementalAffliction::single(Element::Fire, 50);
+
+        assert_eq!(Falloff::None.apply(&elements, 3), elements);
+        assert_eq!(
+            Falloff::Linear(25).apply(&elements, 1),
+            ElementalAffliction::single(Element::Fire, 37)
+        );
+        assert!(Falloff::Linear(25).apply(&elements, 4).is_empty());
+
+        // Neither far away tiles nor huge amounts overflow.
+        assert!(Falloff::Linear(50).apply(&elements, u32::MAX).is_empty());
+        let huge = ElementalAffliction::single(Element::Fire, u32::MAX);
+        assert_eq!(
+            Falloff::Linear(50).apply(&huge, 1),
+            ElementalAffliction::single(Element::Fire, u32::MAX / 2)
+        );
+    }
+
+    #[test]
+    fn area_messages_become_tile_messages() {
+        let map = Map::new((3, 3));
+        let mut world = World::new();
+        let tiles: Vec<Entity> = (0..map.tile_count()).map(|_| world.spawn().id()).collect();
+        let map_root = world
+            .spawn()
+            .insert(MapRoot {
+                tile_entities: Vec::new(),
+            })
+            .id();
+        world.insert_resource(map);
+
+        world.spawn().insert_bundle(ApplyAreaElementMessage::new(
+            ElementalAffliction::single(Element::Water, 40),
+            TargetArea {
+                origin: (0, 0).into(),
+                shape: AreaShape::Radius(1),
+                falloff: Falloff::Linear(50),
+            },
+        ));
+
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(resolve_area_messages);
+
+        // The message waits while the map has no tiles.
+        stage.run(&mut world);
+        assert_eq!(world.query::<&Target>().iter(&world).count(), 0);
+        assert_eq!(world.query::<&Handled>().iter(&world).count(), 0);
+
+        world.get_mut::<MapRoot>(map_root).unwrap().tile_entities = tiles.clone();
+        stage.run(&mut world);
+
+        let mut resolved: Vec<(Entity, u32)> = world
+            .query_filtered::<(&Target, &ElementalAffliction), With<ApplyElement>>()
+            .iter(&world)
+            .map(|(target, elements)| (target.0, elements.get_element_amount(Element::Water)))
+            .collect();
+        resolved.sort();
+
+        assert_eq!(
+            resolved,
+            vec![(tiles[0], 40), (tiles[1], 20), (tiles[3], 20)]
+        );
+
+        // Running again doesn't resolve the handled area message a second time.
+        stage.run(&mut world);
+        assert_eq!(
+            world
+                .query_filtered::<&Target, With<ApplyElement>>()
+                .iter(&world)
+                .count(),
+            3
+        );
+    }
+
+    #[test]
+    fn area_messages_wait_for_tiles_on_the_message_bus() {
+        let map = Map::new((3, 3));
+        let mut world = World::new();
+        let tiles: Vec<Entity> = (0..map.tile_count()).map(|_| world.spawn().id()).collect();
+        let map_root = world
+            .spawn()
+            .insert(MapRoot {
+                tile_entities: Vec::new(),
+            })
+            .id();
+        world.insert_resource(map);
+        world.insert_resource(CurrentState(GameState::TDMode));
+        world.insert_resource(MessageStats::default());
+        world.insert_resource(Reactions(Vec::new()));
+        world.insert_resource(CompoundRecipes(Vec::new()));
+        world.insert_resource(DecayRates::none());
+
+        // The element messages are registered the same way the game registers them, so the
+        // area message is an `ApplyElement` message as well.
+        let mut schedule = Schedule::default();
+        schedule
+            .add_stage(CoreStage::Update, SystemStage::single_threaded())
+            .add_stage_before(CoreStage::Update, MESSAGE_STAGE, message_bus());
+        add_element_stages(&mut schedule, CoreStage::Update, |_, stage| stage);
+
+        world.spawn().insert_bundle(ApplyAreaElementMessage::new(
+            ElementalAffliction::single(Element::Water, 40),
+            TargetArea {
+                origin: (0, 0).into(),
+                shape: AreaShape::Radius(0),
+                falloff: Falloff::None,
+            },
+        ));
+
+        // Every pass but the last one the message can wait for goes by without any tiles.
+        (1..AREA_MESSAGE_MAX_AGE).for_each(|_| schedule.run(&mut world));
+        assert_eq!(world.query::<&TargetArea>().iter(&world).count(), 1);
+
+        world.get_mut::<MapRoot>(map_root).unwrap().tile_entities = tiles.clone();
+        schedule.run(&mut world);
+
+        assert_eq!(
+            world.get::<ElementalAffliction>(tiles[0]),
+            Some(&ElementalAffliction::single(Element::Water, 40))
+        );
+        assert_eq!(world.query::<&Message>().iter(&world).count(), 0);
+        assert_no_leaked_messages(&world);
+    }
+}
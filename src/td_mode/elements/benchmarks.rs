@@ -0,0 +1,176 @@
+//! Timing comparisons for `ElementalAffliction`
+//!
+//! These are ignored by default since they're slow in debug builds and only print timings.
+//! Run them with:
+//!
+//! ```sh
+//! cargo test --release benchmarks -- --ignored --nocapture
+//! ```
+//!
+//! Each benchmark runs a frame's worth of element work on a 64x64 map where every tile is
+//! afflicted: summing up the messages sent to each tile like the message bus
+//! does, adding the sums onto each tile like `handle_apply_element_messages` does, and then
+//! checking each tile against a reaction's prerequisites.
+
+use super::*;
+use std::time::{Duration, Instant};
+
+const MAP_SIZE: usize = 64;
+const FRAMES: u32 = 50;
+/// Messages sent to every tile each frame, like a tower's hit plus spreading from a neighbour
+const MESSAGES_PER_TILE: usize = 2;
+
+/// `ElementalAffliction` as it was before it moved to a fixed array, kept for comparison.
+#[derive(PartialEq, Debug, Clone)]
+struct HashMapAffliction(HashMap<Element, u32>);
+
+impl HashMapAffliction {
+    fn empty() -> Self {
+        Self(HashMap::new())
+    }
+
+    fn add_element(&mut self, element: Element, amount: u32) {
+        let initial = self.0.get(&element).copied().unwrap_or(0);
+        self.0.insert(element, initial + amount);
+    }
+
+    fn get_element_amount(&self, element: Element) -> u32 {
+        self.0.get(&element).copied().unwrap_or(0)
+    }
+
+    fn contains(&self, other: &Self) -> bool {
+        other
+            .0
+            .iter()
+            .all(|(&element, &amount)| self.get_element_amount(element) >= amount)
+    }
+}
+
+impl Add<&HashMapAffliction> for &HashMapAffliction {
+    type Output = HashMapAffliction;
+
+    fn add(self, other: &HashMapAffliction) -> Self::Output {
+        let mut result = self.clone();
+        other.0.iter().for_each(|(&element, &amount)| {
+            result.add_element(element, amount);
+        });
+
+        result
+    }
+}
+
+/// The parts of the affliction API the benchmark frame uses.
+trait Affliction: Sized {
+    fn make(elements: &[(Element, u32)]) -> Self;
+    fn sum(&self, other: &Self) -> Self;
+    fn has(&self, other: &Self) -> bool;
+    fn total(&self) -> u32;
+}
+
+impl Affliction for HashMapAffliction {
+    fn make(elements: &[(Element, u32)]) -> Self {
+        let mut result = Self::empty();
+        elements.iter().for_each(|&(e, a)| result.add_element(e, a));
+        result
+    }
+
+    fn sum(&self, other: &Self) -> Self {
+        self + other
+    }
+
+    fn has(&self, other: &Self) -> bool {
+        self.contains(other)
+    }
+
+    fn total(&self) -> u32 {
+        self.0.values().sum()
+    }
+}
+
+impl Affliction for ElementalAffliction {
+    fn make(elements: &[(Element, u32)]) -> Self {
+        let mut result = Self::empty();
+        elements.iter().for_each(|&(e, a)| result.add_element(e, a));
+        result
+    }
+
+    fn sum(&self, other: &Self) -> Self {
+        self + other
+    }
+
+    fn has(&self, other: &Self) -> bool {
+        self.contains(other)
+    }
+
+    fn total(&self) -> u32 {
+        self.iter().map(|(_, amount)| amount).sum()
+    }
+}
+
+/// Run the benchmark frames for one representation, returning how long they took and the
+/// total amount of elements on the map afterwards.
+fn run_frames<A: Affliction + Clone>() -> (Duration, u32) {
+    let tile_count = MAP_SIZE * MAP_SIZE;
+    let mut tiles: Vec<A> = (0..tile_count)
+        .map(|idx| {
+            A::make(&[
+                (Element::Fire, idx as u32 % 50),
+                (Element::Water, 10),
+                (Element::Earth, 3),
+            ])
+        })
+        .collect();
+    let messages: Vec<(usize, A)> = (0..tile_count * MESSAGES_PER_TILE)
+        .map(|idx| {
+            (
+                idx % tile_count,
+                A::make(&[(Element::Fire, 1), (Element::Lightning, 2)]),
+            )
+        })
+        .collect();
+    let prerequisites = A::make(&[(Element::Fire, 50), (Element::Water, 5)]);
+
+    let start = Instant::now();
+    let mut reactions = 0;
+    for _ in 0..FRAMES {
+        let mut sums: HashMap<usize, A> = HashMap::new();
+        messages.iter().for_each(|(target, elements)| {
+            let total = match sums.remove(target) {
+                Some(existing) => existing.sum(elements),
+                None => elements.clone(),
+            };
+            sums.insert(*target, total);
+        });
+
+        sums.iter().for_each(|(&target, elements)| {
+            tiles[target] = tiles[target].sum(elements);
+        });
+
+        reactions += tiles.iter().filter(|t| t.has(&prerequisites)).count();
+    }
+    let elapsed = start.elapsed();
+
+    // Keep the work from being optimised away.
+    assert!(reactions > 0);
+
+    (elapsed, tiles.iter().map(A::total).sum())
+}
+
+#[test]
+#[ignore = "slow in debug builds and only prints timings"]
+fn compare_affliction_representations() {
+    let (hash_map_time, hash_map_total) = run_frames::<HashMapAffliction>();
+    let (array_time, array_total) = run_frames::<ElementalAffliction>();
+
+    assert_eq!(hash_map_total, array_total);
+
+    let per_frame = |time: Duration| time / FRAMES;
+    println!(
+        "{FRAMES} frames on a {MAP_SIZE}x{MAP_SIZE} map:\n  \
+         HashMap: {:?} per frame\n  \
+         Array:   {:?} per frame ({:.1}x faster)",
+        per_frame(hash_map_time),
+        per_frame(array_time),
+        hash_map_time.as_secs_f64() / array_time.as_secs_f64(),
+    );
+}
@@ -1,90 +1,173 @@
 use super::*;
-use map::{Tile, TileType};
+use map::{MapRoot, Tile, TileType};
+use reaction_file::{ReactionTable, ReactionTableLoader};
 use std::ops::ControlFlow;
 
-struct ChemicalReaction {
-    tile_type: TileType,
-    prerequisites: ElementalAffliction,
-    prereq_type: PrerequisiteType,
-    subtract_prerequisites: bool,
-    new_tile_type: Option<TileType>,
-    // TODO: OPTIONAL MESSAGE TO SEND
+/// A rule for what happens to a tile of a certain type when the right elements are applied to it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChemicalReaction {
+    /// Shown in logs and errors so designers can tell reactions apart
+    pub name: String,
+    pub tile_type: TileType,
+    pub prerequisites: ElementalAffliction,
+    pub prereq_type: PrerequisiteType,
+    pub subtract_prerequisites: bool,
+    pub new_tile_type: Option<TileType>,
+    pub effects: Vec<ReactionEffect>,
 }
 
-enum PrerequisiteType {
+/// Something a reaction does besides changing the type of its tile.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReactionEffect {
+    /// Apply elements to each of the tile's neighbours under the map's movement rules
+    ApplyToNeighbours(ElementalAffliction),
+    /// Apply elements to an area around the tile
+    ApplyToArea {
+        elements: ElementalAffliction,
+        shape: AreaShape,
+        falloff: Falloff,
+    },
+    /// Damage every enemy standing on the tile
+    DamageEnemies(Damage),
+    /// Replace the structure on the tile. `Structure::None` destroys whatever was there.
+    SetStructure(Structure),
+    /// Send a `ReactionEvent` message with this name
+    Event(String),
+}
+
+/// Tag component for messages announcing a reaction's event. The message's Source is the tile
+/// the reaction happened on.
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct ReactionEvent(pub String);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PrerequisiteType {
     Contains,
     ExactMatch,
 }
 
+impl PrerequisiteType {
+    pub fn all() -> [Self; 2] {
+        [Self::Contains, Self::ExactMatch]
+    }
+
+    fn display_name(&self) -> &str {
+        match *self {
+            Self::Contains => "Contains",
+            Self::ExactMatch => "ExactMatch",
+        }
+    }
+
+    /// Look up a prerequisite type by the name it is displayed with.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::all().into_iter().find(|p| p.display_name() == name)
+    }
+}
+
+impl std::fmt::Display for PrerequisiteType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.display_name())
+    }
+}
+
 impl ChemicalReaction {
     fn check_prereqs_against(&self, other: &ElementalAffliction) -> bool {
         match self.prereq_type {
-            PrerequisiteType::Contains => other.contains(&self.prerequisites),
+            // A zero amount means the element has to have run out, like for exact matches.
+            PrerequisiteType::Contains => self.prerequisites.entries().all(|(element, amount)| {
+                match other.get_element_amount(element) {
+                    found if amount == 0 => found == 0,
+                    found => found >= amount,
+                }
+            }),
             PrerequisiteType::ExactMatch => other.contains_exactly(&self.prerequisites),
         }
     }
 }
 
-struct Reactions(Vec<ChemicalReaction>);
+/// The reactions currently in play, checked in order. The first one that matches a tile wins.
+pub struct Reactions(pub Vec<ChemicalReaction>);
+
+/// Where the reaction table is loaded from, relative to the assets folder.
+const REACTION_TABLE_PATH: &str = "data/chemistry.reactions.json";
+
+/// Keeps the reaction table loaded so edits to the file are picked up.
+struct ReactionTableHandle(Handle<ReactionTable>);
 
 pub struct ChemistryPlugin;
 
 impl Plugin for ChemistryPlugin {
     fn build(&self, app: &mut App) {
-        let fire_to_rock_prereq = ElementalAffliction::single(Element::Water, 20);
-        let fire_to_rock = ChemicalReaction {
-            tile_type: TileType::Fire,
-            prerequisites: fire_to_rock_prereq,
-            prereq_type: PrerequisiteType::Contains,
-            subtract_prerequisites: true,
-            new_tile_type: Some(TileType::Rock),
-        };
-
-        let rock_to_fire_prereq = ElementalAffliction::single(Element::Fire, 50);
-        let rock_to_fire = ChemicalReaction {
-            tile_type: TileType::Rock,
-            prerequisites: rock_to_fire_prereq,
-            prereq_type: PrerequisiteType::Contains,
-            subtract_prerequisites: false,
-            new_tile_type: Some(TileType::Fire),
-        };
-
-        let fire_cooling_prereq = ElementalAffliction::single(Element::Fire, 0);
-        let fire_cooling = ChemicalReaction {
-            tile_type: TileType::Fire,
-            prerequisites: fire_cooling_prereq,
-            prereq_type: PrerequisiteType::ExactMatch,
-            subtract_prerequisites: false,
-            new_tile_type: Some(TileType::Rock),
-        };
+        app.insert_resource(Reactions(Vec::new()))
+            .add_asset::<ReactionTable>()
+            .init_asset_loader::<ReactionTableLoader>()
+            .add_startup_system(load_reaction_table)
+            .add_system(update_reactions);
+    }
+}
 
-        let reactions = Reactions(vec![rock_to_fire, fire_to_rock, fire_cooling]);
-        app.insert_resource(reactions);
+fn load_reaction_table(assets: Res<AssetServer>, mut commands: Commands) {
+    commands.insert_resource(ReactionTableHandle(assets.load(REACTION_TABLE_PATH)));
+}
 
-        app.add_system(trigger_reactions.run_in_state(GameState::TDMode));
-    }
+/// Swap in the reaction table whenever it finishes loading or the file is edited.
+fn update_reactions(
+    mut events: EventReader<AssetEvent<ReactionTable>>,
+    table_handle: Res<ReactionTableHandle>,
+    tables: Res<Assets<ReactionTable>>,
+    mut reactions: ResMut<Reactions>,
+) {
+    events.iter().for_each(|event| match event {
+        AssetEvent::Created { handle } | AssetEvent::Modified { handle }
+            if *handle == table_handle.0 =>
+        {
+            if let Some(table) = tables.get(handle) {
+                info!("Loaded {} chemical reactions", table.0.len());
+                reactions.0.clone_from(&table.0);
+            }
+        }
+        _ => {}
+    });
 }
 
-fn trigger_reactions(
+pub(super) fn trigger_reactions(
     mut tile_query: Query<
-        (&Coordinate, &TileType, &mut ElementalAffliction),
+        (Entity, &Coordinate, &TileType, &mut ElementalAffliction),
         (With<Tile>, Changed<ElementalAffliction>),
     >,
+    map_root_query: Query<&MapRoot>,
     reactions: Res<Reactions>,
     mut map: ResMut<Map>,
+    mut commands: Commands,
 ) {
+    let tile_entities = map_root_query
+        .get_single()
+        .map_or(&[][..], |root| &root.tile_entities);
+
     tile_query
         .iter_mut()
-        .for_each(|(coord, tile_type, mut affliction)| {
+        .for_each(|(tile, coord, tile_type, mut affliction)| {
             reactions.0.iter().try_for_each(|reaction| {
                 // CHECK IF AFFLICTION MEETS REQS
                 if *tile_type == reaction.tile_type && reaction.check_prereqs_against(&affliction) {
+                    trace!("'{}' triggered at {coord}", reaction.name);
+
                     // CHANGE TILETYPE IF NECESSARY
                     if let Some(new_tile_type) = reaction.new_tile_type {
                         map.set_tile(*coord, Some(new_tile_type), None);
                     }
 
-                    // SPAWN AN EVENT MESSAGE IF NECESSARY
+                    // SEND OUT ANY SIDE EFFECTS
+                    reaction.effects.iter().for_each(|effect| {
+                        send_reaction_effect(
+                            effect,
+                            tile,
+                            *coord,
+                            &mut map,
+                            tile_entities,
+                            &mut commands,
+                        );
+                    });
 
                     // Subtract the elements used in the reaction
                     if reaction.subtract_prerequisites {
@@ -97,3 +180,203 @@ fn trigger_reactions(
             });
         });
 }
+
+/// Carry out one of a reaction's side effects for the tile at `coord`.
+///
+/// Effects on other entities are sent as messages so they're handled by whatever owns those
+/// entities.
+fn send_reaction_effect(
+    effect: &ReactionEffect,
+    tile: Entity,
+    coord: Coordinate,
+    map: &mut Map,
+    tile_entities: &[Entity],
+    commands: &mut Commands,
+) {
+    match effect {
+        ReactionEffect::ApplyToNeighbours(elements) => {
+            map.coord_neighbour_indices(coord)
+                .into_iter()
+                .filter_map(|idx| tile_entities.get(idx))
+                .for_each(|&neighbour| {
+                    commands
+                        .spawn_bundle(ApplyElementMessage::new(*elements, neighbour))
+                        .insert(Source(tile));
+                });
+        }
+        ReactionEffect::ApplyToArea {
+            elements,
+            shape,
+            falloff,
+        } => {
+            commands
+                .spawn_bundle(ApplyAreaElementMessage::new(
+                    *elements,
+                    TargetArea {
+                        origin: coord,
+                        shape: *shape,
+                        falloff: *falloff,
+                    },
+                ))
+                .insert(Source(tile));
+        }
+        ReactionEffect::DamageEnemies(damage) => {
+            commands
+                .spawn()
+                .insert(Message)
+                .insert(*damage)
+                .insert(Source(tile))
+                .insert(Target(tile));
+        }
+        ReactionEffect::SetStructure(structure) => {
+            // Reactions follow the same rules as the player, so they can't wall off the exit.
+            if map.can_place_structure(coord, *structure) {
+                map.set_tile(coord, None, Some(*structure));
+            } else {
+                debug!("Not placing {structure} at {coord}, it would cut off the wave exit");
+            }
+        }
+        ReactionEffect::Event(name) => {
+            commands
+                .spawn()
+                .insert(Message)
+                .insert(ReactionEvent(name.clone()))
+                .insert(Source(tile));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::enemies::{
+        resolve_damage_messages, DamageTotals, EnemyBundle, EnemyKind, Health,
+    };
+    use super::super::reaction_file::reactions_from_json;
+    use super::*;
+    use map::update_changed_tiles;
+
+    #[test]
+    fn explosions_chain_along_a_row_of_rock() {
+        // Four rock tiles in a row with barren ground around them, and a grunt on the third.
+        let mut map = Map::new((5, 2));
+        for x in 0..4 {
+            map.set_tile((x, 0).into(), Some(TileType::Rock), None);
+        }
+        map.wave_entry_coord = (2, 0).into();
+        map.wave_exit_coord = (2, 1).into();
+
+        let mut flow_fields = FlowFields::default();
+        flow_fields.update(&map);
+
+        let mut world = World::new();
+        let tiles: Vec<Entity> = (0..map.tile_count())
+            .map(|idx| {
+                world
+                    .spawn()
+                    .insert(Tile)
+                    .insert(map.idx_to_coord(idx))
+                    .insert(*map.tile_type_at_index(idx).unwrap())
+                    .id()
+            })
+            .collect();
+        world.spawn().insert(MapRoot {
+            tile_entities: tiles.clone(),
+        });
+        let enemy = world
+            .spawn()
+            .insert_bundle(EnemyBundle::at_wave_entry(
+                &map,
+                &flow_fields,
+                EnemyKind::Grunt,
+            ))
+            .id();
+
+        let contents = std::fs::read_to_string(REACTION_FILE_PATH).unwrap();
+        let reactions = reactions_from_json(&serde_json::from_str(&contents).unwrap()).unwrap();
+        world.insert_resource(Reactions(reactions));
+        world.insert_resource(map);
+        world.insert_resource(DamageTotals::default());
+
+        // Handled messages are cleared in their own stage, like they are in the game.
+        let mut clear_stage = SystemStage::single_threaded();
+        clear_stage.add_system(clear_handled_messages);
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(trigger_reactions);
+        stage.add_system(handle_apply_element_messages.after(trigger_reactions));
+        stage.add_system(resolve_damage_messages.after(trigger_reactions));
+        stage.add_system(update_changed_tiles.after(trigger_reactions));
+
+        // Set off the first rock.
+        let mut charge = ElementalAffliction::single(Element::Fire, 40);
+        charge.add_element(Element::Lightning, 30);
+        world.entity_mut(tiles[0]).insert(charge);
+        for _ in 0..20 {
+            clear_stage.run(&mut world);
+            stage.run(&mut world);
+        }
+
+        let map = world.resource::<Map>();
+        assert!((0..4).all(|x| map.tile_type_at_coord((x, 0).into()) != Some(&TileType::Rock)));
+
+        let explosions = world
+            .query::<(&ReactionEvent, &Source)>()
+            .iter(&world)
+            .filter(|(event, _)| event.0 == "Explosion")
+            .map(|(_, source)| source.0)
+            .collect::<Vec<_>>();
+        assert_eq!(explosions, tiles[..4].to_vec());
+
+        let health = world.get::<Health>(enemy).unwrap();
+        assert!((health.current - (health.max - 40.0)).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn reactions_cannot_wall_off_the_exit() {
+        // Two rows of rock between the entry and exit, so one barricade still leaves a way round.
+        let mut map = Map::new((3, 2));
+        (0..map.tile_count()).for_each(|idx| {
+            map.set_tile(map.idx_to_coord(idx), Some(TileType::Rock), None);
+        });
+        map.wave_entry_coord = (0, 0).into();
+        map.wave_exit_coord = (2, 0).into();
+
+        let mut world = World::new();
+        let tiles: Vec<Entity> = (0..map.tile_count())
+            .map(|idx| {
+                world
+                    .spawn()
+                    .insert(Tile)
+                    .insert(map.idx_to_coord(idx))
+                    .insert(TileType::Rock)
+                    .id()
+            })
+            .collect();
+        world.insert_resource(Reactions(vec![ChemicalReaction {
+            name: "Rock grows a wall".to_string(),
+            tile_type: TileType::Rock,
+            prerequisites: ElementalAffliction::single(Element::Earth, 10),
+            prereq_type: PrerequisiteType::Contains,
+            subtract_prerequisites: true,
+            new_tile_type: None,
+            effects: vec![ReactionEffect::SetStructure(Structure::Barricade)],
+        }]));
+        world.insert_resource(map);
+
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(trigger_reactions);
+        let mut grow_wall = |coord: (usize, usize)| {
+            let idx = world.resource::<Map>().coord_to_idx(coord.into());
+            world
+                .entity_mut(tiles[idx])
+                .insert(ElementalAffliction::single(Element::Earth, 10));
+            stage.run(&mut world);
+            *world
+                .resource::<Map>()
+                .structure_at_coord(coord.into())
+                .unwrap()
+        };
+
+        assert_eq!(grow_wall((1, 0)), Structure::Barricade);
+        assert_eq!(grow_wall((1, 1)), Structure::None);
+    }
+}
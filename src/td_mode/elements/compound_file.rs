@@ -0,0 +1,193 @@
+//! Reading compound recipes from disk
+//!
+//! Recipes are stored as JSON in the following shape, and run in the order they're listed:
+//!
+//! ```json
+//! {
+//!     "format_version": 1,
+//!     "recipes": [
+//!         {
+//!             "name": "Steam",
+//!             "inputs": { "Fire": 1, "Water": 1 },
+//!             "outputs": { "Air": 1 }
+//!         }
+//!     ]
+//! }
+//! ```
+//!
+//! The amounts are ratios: a recipe with 2 Water and 1 Earth as inputs runs once for every 2
+//! Water and 1 Earth on an entity.
+
+use super::compounds::CompoundRecipe;
+use super::*;
+use serde_json::Value;
+use std::path::Path;
+
+/// The version of compound file this build understands.
+pub const COMPOUND_FORMAT_VERSION: u64 = 1;
+
+/// Everything that can go wrong reading a compound file.
+#[derive(Debug)]
+pub enum CompoundFileError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    UnsupportedVersion(u64),
+    MissingField(&'static str),
+    InvalidRecipe { index: usize, reason: &'static str },
+    UnknownElement { index: usize, name: String },
+}
+
+impl std::fmt::Display for CompoundFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "Could not access compound file: {e}"),
+            Self::Json(e) => write!(f, "Compound file is not valid JSON: {e}"),
+            Self::UnsupportedVersion(v) => write!(
+                f,
+                "Compound format version {v} is not supported (expected {COMPOUND_FORMAT_VERSION})"
+            ),
+            Self::MissingField(field) => {
+                write!(f, "Compound file is missing the field '{field}'")
+            }
+            Self::InvalidRecipe { index, reason } => write!(f, "Recipe {index}: {reason}"),
+            Self::UnknownElement { index, name } => {
+                write!(f, "Recipe {index}: unknown element '{name}'")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CompoundFileError {}
+
+impl From<std::io::Error> for CompoundFileError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for CompoundFileError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+pub fn load_compound_recipes(
+    path: impl AsRef<Path>,
+) -> Result<Vec<CompoundRecipe>, CompoundFileError> {
+    let contents = std::fs::read_to_string(path)?;
+    let value: Value = serde_json::from_str(&contents)?;
+
+    compound_recipes_from_json(&value)
+}
+
+pub fn compound_recipes_from_json(value: &Value) -> Result<Vec<CompoundRecipe>, CompoundFileError> {
+    let version = value
+        .get("format_version")
+        .and_then(Value::as_u64)
+        .ok_or(CompoundFileError::MissingField("format_version"))?;
+    if version != COMPOUND_FORMAT_VERSION {
+        return Err(CompoundFileError::UnsupportedVersion(version));
+    }
+
+    value
+        .get("recipes")
+        .and_then(Value::as_array)
+        .ok_or(CompoundFileError::MissingField("recipes"))?
+        .iter()
+        .enumerate()
+        .map(|(index, recipe)| recipe_from_json(index, recipe))
+        .collect()
+}
+
+fn recipe_from_json(index: usize, recipe: &Value) -> Result<CompoundRecipe, CompoundFileError> {
+    let invalid = |reason| CompoundFileError::InvalidRecipe { index, reason };
+
+    let name = recipe
+        .get("name")
+        .and_then(Value::as_str)
+        .ok_or_else(|| invalid("missing 'name'"))?;
+    let inputs = recipe
+        .get("inputs")
+        .ok_or_else(|| invalid("missing 'inputs'"))
+        .and_then(|inputs| elements_from_json(index, inputs))?;
+    let outputs = recipe
+        .get("outputs")
+        .ok_or_else(|| invalid("missing 'outputs'"))
+        .and_then(|outputs| elements_from_json(index, outputs))?;
+
+    if inputs.is_empty() {
+        return Err(invalid("recipes need at least one input"));
+    }
+
+    Ok(CompoundRecipe {
+        name: name.to_string(),
+        inputs,
+        outputs,
+    })
+}
+
+fn elements_from_json(
+    index: usize,
+    elements: &Value,
+) -> Result<ElementalAffliction, CompoundFileError> {
+    let invalid = |reason| CompoundFileError::InvalidRecipe { index, reason };
+
+    let mut affliction = ElementalAffliction::empty();
+    let elements = elements
+        .as_object()
+        .ok_or_else(|| invalid("elements must be an object"))?;
+    for (element_name, amount) in elements {
+        let element =
+            Element::from_name(element_name).ok_or_else(|| CompoundFileError::UnknownElement {
+                index,
+                name: element_name.clone(),
+            })?;
+        let amount = amount
+            .as_u64()
+            .and_then(|a| u32::try_from(a).ok())
+            .filter(|&a| a > 0)
+            .ok_or_else(|| invalid("element amounts must be integers of at least 1"))?;
+
+        affliction.add_element(element, amount);
+    }
+
+    Ok(affliction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn compound_file_errors_name_the_bad_recipe() {
+        let file = |inputs: Value| {
+            json!({
+                "format_version": 1,
+                "recipes": [
+                    { "name": "Steam", "inputs": { "Fire": 1, "Water": 1 }, "outputs": { "Air": 1 } },
+                    { "name": "Broken", "inputs": inputs, "outputs": { "Earth": 1 } },
+                ],
+            })
+        };
+
+        assert_eq!(
+            compound_recipes_from_json(&file(json!({ "Water": 2 })))
+                .unwrap()
+                .len(),
+            2
+        );
+        assert!(matches!(
+            compound_recipes_from_json(&file(json!({ "Mud": 2 }))),
+            Err(CompoundFileError::UnknownElement { index: 1, name }) if name == "Mud"
+        ));
+        assert!(matches!(
+            compound_recipes_from_json(&file(json!({ "Water": 0 }))),
+            Err(CompoundFileError::InvalidRecipe { index: 1, .. })
+        ));
+        assert!(matches!(
+            compound_recipes_from_json(&file(json!({}))),
+            Err(CompoundFileError::InvalidRecipe { index: 1, .. })
+        ));
+    }
+}
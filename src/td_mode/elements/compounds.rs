@@ -0,0 +1,231 @@
+//! Elements combining with each other
+//!
+//! Whenever the elements on an entity change, the compound recipes are run over them in the
+//! order they're listed. Each recipe runs as many whole times as its inputs allow, turning its
+//! inputs into its outputs, before the next recipe gets a look. Recipes only run once per
+//! change, so a recipe's outputs can feed a later recipe but never an earlier one.
+//!
+//! This works on anything with elements on it: tiles, enemies, and the tiles towers stand on.
+
+use super::*;
+use compound_file::load_compound_recipes;
+
+/// Where the compound recipes are read from on startup. The file is read once, not hot reloaded
+/// like the reaction table.
+pub const COMPOUND_FILE_PATH: &str = "assets/data/compounds.json";
+
+pub struct CompoundPlugin;
+
+impl Plugin for CompoundPlugin {
+    fn build(&self, app: &mut App) {
+        let recipes = match load_compound_recipes(COMPOUND_FILE_PATH) {
+            Ok(recipes) => recipes,
+            Err(e) => {
+                error!("Failed to load compound recipes from {COMPOUND_FILE_PATH}: {e}");
+                Vec::new()
+            }
+        };
+
+        app.insert_resource(CompoundRecipes(recipes));
+    }
+}
+
+/// A rule for turning some elements into others, like Fire and Water into steam.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompoundRecipe {
+    /// Shown in logs and errors so designers can tell recipes apart
+    pub name: String,
+    /// The elements used up each time the recipe runs. Every amount is at least 1.
+    pub inputs: ElementalAffliction,
+    /// The elements added each time the recipe runs
+    pub outputs: ElementalAffliction,
+}
+
+impl CompoundRecipe {
+    /// How many whole times the recipe can run on `affliction`.
+    pub fn batches(&self, affliction: &ElementalAffliction) -> u32 {
+        self.inputs
+            .iter()
+            .map(|(element, amount)| affliction.get_element_amount(element) / amount)
+            .min()
+            .unwrap_or(0)
+    }
+}
+
+/// The compound recipes in play, run in order.
+pub struct CompoundRecipes(pub Vec<CompoundRecipe>);
+
+/// Run every recipe over `affliction` in order. Returns None if no recipe could run.
+pub fn combine(
+    recipes: &[CompoundRecipe],
+    affliction: &ElementalAffliction,
+) -> Option<ElementalAffliction> {
+    let mut combined = *affliction;
+    let mut any_ran = false;
+
+    recipes.iter().for_each(|recipe| {
+        let batches = recipe.batches(&combined);
+        if batches > 0 {
+            trace!("'{}' ran {batches} times", recipe.name);
+            recipe.inputs.iter().for_each(|(element, amount)| {
+                combined.subtract_element(element, amount.saturating_mul(batches));
+            });
+            recipe.outputs.iter().for_each(|(element, amount)| {
+                combined.add_element(element, amount.saturating_mul(batches));
+            });
+            any_ran = true;
+        }
+    });
+
+    if any_ran {
+        Some(combined)
+    } else {
+        None
+    }
+}
+
+pub(super) fn combine_elements(
+    mut affliction_query: Query<
+        &mut ElementalAffliction,
+        (Changed<ElementalAffliction>, Without<Message>),
+    >,
+    recipes: Res<CompoundRecipes>,
+) {
+    affliction_query.iter_mut().for_each(|mut affliction| {
+        // Only write back when something combined so unchanged elements don't look changed.
+        if let Some(combined) = combine(&recipes.0, &affliction) {
+            *affliction = combined;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recipe(name: &str, inputs: &[(Element, u32)], outputs: &[(Element, u32)]) -> CompoundRecipe {
+        let elements = |list: &[(Element, u32)]| {
+            let mut affliction = ElementalAffliction::empty();
+            list.iter()
+                .for_each(|&(element, amount)| affliction.add_element(element, amount));
+            affliction
+        };
+
+        CompoundRecipe {
+            name: name.to_string(),
+            inputs: elements(inputs),
+            outputs: elements(outputs),
+        }
+    }
+
+    fn steam() -> CompoundRecipe {
+        recipe(
+            "Steam",
+            &[(Element::Fire, 1), (Element::Water, 1)],
+            &[(Element::Air, 1)],
+        )
+    }
+
+    fn mud() -> CompoundRecipe {
+        recipe(
+            "Mud",
+            &[(Element::Water, 2), (Element::Earth, 1)],
+            &[(Element::Earth, 2)],
+        )
+    }
+
+    #[test]
+    fn recipes_use_whole_ratios_and_leave_the_rest() {
+        let mut affliction = ElementalAffliction::single(Element::Water, 7);
+        affliction.add_element(Element::Earth, 5);
+
+        // 7 Water only covers three batches of mud, leaving 1 Water and 2 Earth behind.
+        let mut expected = ElementalAffliction::single(Element::Water, 1);
+        expected.add_element(Element::Earth, 2 + 3 * 2);
+        assert_eq!(mud().batches(&affliction), 3);
+        assert_eq!(combine(&[mud()], &affliction), Some(expected));
+
+        // Inputs that run out are dropped entirely.
+        let mut affliction = ElementalAffliction::single(Element::Fire, 5);
+        affliction.add_element(Element::Water, 3);
+        let mut expected = ElementalAffliction::single(Element::Fire, 2);
+        expected.add_element(Element::Air, 3);
+        assert_eq!(combine(&[steam()], &affliction), Some(expected));
+
+        // Not enough for a single batch
+        assert_eq!(
+            combine(&[mud()], &ElementalAffliction::single(Element::Water, 50)),
+            None
+        );
+    }
+
+    #[test]
+    fn recipes_run_in_order() {
+        let mut affliction = ElementalAffliction::single(Element::Fire, 4);
+        affliction.add_element(Element::Water, 4);
+        affliction.add_element(Element::Earth, 2);
+
+        // Steam boils all the water away before mud can form.
+        let mut expected = ElementalAffliction::single(Element::Air, 4);
+        expected.add_element(Element::Earth, 2);
+        assert_eq!(combine(&[steam(), mud()], &affliction), Some(expected));
+
+        // Mud soaks up all the water before it can boil.
+        let mut expected = ElementalAffliction::single(Element::Fire, 4);
+        expected.add_element(Element::Earth, 4);
+        assert_eq!(combine(&[mud(), steam()], &affliction), Some(expected));
+    }
+
+    #[test]
+    fn subtracting_saturates_at_zero() {
+        let mut affliction = ElementalAffliction::single(Element::Water, 3);
+        affliction.subtract_element(Element::Water, 5);
+        affliction.subtract_element(Element::Fire, 1);
+
+        assert_eq!(affliction, ElementalAffliction::empty());
+    }
+
+    #[test]
+    fn outputs_saturate_instead_of_overflowing() {
+        let amplify = recipe(
+            "Amplify",
+            &[(Element::Air, 1)],
+            &[(Element::Lightning, 1000)],
+        );
+        let mut affliction = ElementalAffliction::single(Element::Air, 10_000_000);
+        affliction.add_element(Element::Lightning, u32::MAX - 5);
+
+        let expected = ElementalAffliction::single(Element::Lightning, u32::MAX);
+        assert_eq!(combine(&[amplify], &affliction), Some(expected));
+    }
+
+    #[test]
+    fn compounds_form_on_any_entity_but_not_on_messages() {
+        let mut world = World::new();
+        world.insert_resource(CompoundRecipes(vec![steam()]));
+
+        let mut mixed = ElementalAffliction::single(Element::Fire, 2);
+        mixed.add_element(Element::Water, 2);
+        let enemy = world.spawn().insert(mixed).id();
+        let message = world
+            .spawn()
+            .insert_bundle(ApplyElementMessage::new(mixed, enemy))
+            .id();
+
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(combine_elements);
+        stage.run(&mut world);
+
+        assert_eq!(
+            world.get::<ElementalAffliction>(enemy),
+            Some(&ElementalAffliction::single(Element::Air, 2))
+        );
+        assert_eq!(world.get::<ElementalAffliction>(message), Some(&mixed));
+    }
+
+    #[test]
+    fn shipped_compound_file_loads() {
+        let recipes = load_compound_recipes(COMPOUND_FILE_PATH).unwrap();
+        assert!(recipes.iter().any(|r| r.name == "Steam"));
+    }
+}
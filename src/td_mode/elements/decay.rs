@@ -0,0 +1,124 @@
+//! Elements fading from tiles over time
+//!
+//! Every element on a tile loses a set amount each second, depending on the element and the
+//! type of the tile it's on. Fire dies down quickly on water and slowly on a fire tile, water
+//! dries up fastest on fire, and so on. The rates are read from `assets/data/decay.json`.
+
+use super::*;
+use decay_file::load_decay_rates;
+use map::Tile;
+
+/// Where the decay rates are read from on startup. Unlike the reaction table they don't go
+/// through the asset server, so edits only take effect after a restart.
+pub const DECAY_FILE_PATH: &str = "assets/data/decay.json";
+
+/// Decay runs once a second so the rates can stay whole amounts per second.
+pub(super) const DECAY_STEP_MS: u64 = 1000;
+
+pub struct DecayPlugin;
+
+impl Plugin for DecayPlugin {
+    fn build(&self, app: &mut App) {
+        let rates = match load_decay_rates(DECAY_FILE_PATH) {
+            Ok(rates) => rates,
+            Err(e) => {
+                error!("Failed to load decay rates from {DECAY_FILE_PATH}: {e}");
+                DecayRates::none()
+            }
+        };
+
+        app.insert_resource(rates);
+    }
+}
+
+/// Resource holding how much of each element every type of tile loses per second.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecayRates(HashMap<Element, [u32; TILE_TYPE_COUNT]>);
+
+impl DecayRates {
+    /// Takes the rates for each element, indexed by tile type.
+    pub fn new(rates: HashMap<Element, [u32; TILE_TYPE_COUNT]>) -> Self {
+        Self(rates)
+    }
+
+    /// Rates for a world where elements never fade.
+    pub fn none() -> Self {
+        Self(HashMap::new())
+    }
+
+    /// How much of `element` a tile of `tile_type` loses per second.
+    pub fn rate(&self, tile_type: TileType, element: Element) -> u32 {
+        self.0
+            .get(&element)
+            .map_or(0, |rates| rates[tile_type as usize])
+    }
+
+    /// The elements a tile loses over one second of decay.
+    pub fn decay_step(
+        &self,
+        tile_type: TileType,
+        affliction: &ElementalAffliction,
+    ) -> ElementalAffliction {
+        let mut lost = ElementalAffliction::empty();
+        affliction.iter().for_each(|(element, amount)| {
+            let decay = self.rate(tile_type, element).min(amount);
+            if decay > 0 {
+                lost.add_element(element, decay);
+            }
+        });
+
+        lost
+    }
+}
+
+pub(super) fn decay_elements(
+    tile_query: Query<(Entity, &TileType, &ElementalAffliction), With<Tile>>,
+    rates: Res<DecayRates>,
+    mut commands: Commands,
+) {
+    tile_query
+        .iter()
+        .map(|(e, tile_type, affliction)| (e, rates.decay_step(*tile_type, affliction)))
+        .filter(|(_, lost)| !lost.is_empty())
+        .for_each(|(e, lost)| {
+            commands
+                .spawn()
+                .insert(Message)
+                .insert(Target(e))
+                .insert(RemoveElements)
+                .insert(lost);
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fire_cools_off_and_disappears() {
+        let rates = load_decay_rates(DECAY_FILE_PATH).unwrap();
+        let rock_rate = rates.rate(TileType::Rock, Element::Fire);
+        assert!(rates.rate(TileType::Water, Element::Fire) > rock_rate);
+
+        let mut affliction = ElementalAffliction::single(Element::Fire, 3 * rock_rate + 1);
+        affliction.add_element(Element::Earth, 50);
+
+        for expected in [2 * rock_rate + 1, rock_rate + 1, 1, 0] {
+            affliction -= &rates.decay_step(TileType::Rock, &affliction);
+            assert_eq!(affliction.get_element_amount(Element::Fire), expected);
+        }
+
+        // The fire is gone rather than left at zero, so it no longer shows up anywhere.
+        assert!(affliction
+            .iter()
+            .all(|(element, _)| element != Element::Fire));
+        assert!(affliction.contains_exactly(&ElementalAffliction::single(Element::Fire, 0)));
+        assert_eq!(
+            affliction,
+            ElementalAffliction::single(
+                Element::Earth,
+                50 - 4 * rates.rate(TileType::Rock, Element::Earth)
+            )
+        );
+    }
+}
@@ -0,0 +1,162 @@
+//! Reading element decay rates from disk
+//!
+//! Decay rates are stored as JSON in the following shape, in amounts lost per second:
+//!
+//! ```json
+//! {
+//!     "format_version": 1,
+//!     "decay": {
+//!         "Fire": { "default": 4, "Water": 25, "Fire": 2 },
+//!         "Earth": { "default": 1 }
+//!     }
+//! }
+//! ```
+//!
+//! `default` covers every tile type not listed for that element, and is 0 if left out. Elements
+//! left out of the file never decay.
+
+use super::decay::DecayRates;
+use super::*;
+use serde_json::Value;
+use std::path::Path;
+
+/// The version of decay file this build understands.
+pub const DECAY_FORMAT_VERSION: u64 = 1;
+
+/// Everything that can go wrong reading a decay file.
+#[derive(Debug)]
+pub enum DecayFileError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    UnsupportedVersion(u64),
+    MissingField(&'static str),
+    UnknownElement(String),
+    UnknownTileType { element: Element, name: String },
+    InvalidRate(Element),
+}
+
+impl std::fmt::Display for DecayFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "Could not access decay file: {e}"),
+            Self::Json(e) => write!(f, "Decay file is not valid JSON: {e}"),
+            Self::UnsupportedVersion(v) => write!(
+                f,
+                "Decay format version {v} is not supported (expected {DECAY_FORMAT_VERSION})"
+            ),
+            Self::MissingField(field) => write!(f, "Decay file is missing the field '{field}'"),
+            Self::UnknownElement(name) => write!(f, "Unknown element '{name}'"),
+            Self::UnknownTileType { element, name } => {
+                write!(f, "{element} decay: unknown tile type '{name}'")
+            }
+            Self::InvalidRate(element) => write!(
+                f,
+                "{element} decay: rates must be objects of positive integers"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DecayFileError {}
+
+impl From<std::io::Error> for DecayFileError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for DecayFileError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+pub fn load_decay_rates(path: impl AsRef<Path>) -> Result<DecayRates, DecayFileError> {
+    let contents = std::fs::read_to_string(path)?;
+    let value: Value = serde_json::from_str(&contents)?;
+
+    decay_rates_from_json(&value)
+}
+
+pub fn decay_rates_from_json(value: &Value) -> Result<DecayRates, DecayFileError> {
+    let version = value
+        .get("format_version")
+        .and_then(Value::as_u64)
+        .ok_or(DecayFileError::MissingField("format_version"))?;
+    if version != DECAY_FORMAT_VERSION {
+        return Err(DecayFileError::UnsupportedVersion(version));
+    }
+
+    let mut rates = HashMap::new();
+    let elements = value
+        .get("decay")
+        .and_then(Value::as_object)
+        .ok_or(DecayFileError::MissingField("decay"))?;
+    for (element_name, element_rates) in elements {
+        let element = Element::from_name(element_name)
+            .ok_or_else(|| DecayFileError::UnknownElement(element_name.clone()))?;
+        rates.insert(element, element_rates_from_json(element, element_rates)?);
+    }
+
+    Ok(DecayRates::new(rates))
+}
+
+fn element_rates_from_json(
+    element: Element,
+    rates: &Value,
+) -> Result<[u32; TILE_TYPE_COUNT], DecayFileError> {
+    let rates = rates
+        .as_object()
+        .ok_or(DecayFileError::InvalidRate(element))?;
+    let rate = |value: &Value| {
+        value
+            .as_u64()
+            .and_then(|r| u32::try_from(r).ok())
+            .ok_or(DecayFileError::InvalidRate(element))
+    };
+
+    let default = rates.get("default").map(rate).transpose()?.unwrap_or(0);
+    let mut by_tile_type = [default; TILE_TYPE_COUNT];
+    for (tile_name, value) in rates.iter().filter(|(name, _)| *name != "default") {
+        let tile_type =
+            TileType::from_name(tile_name).ok_or_else(|| DecayFileError::UnknownTileType {
+                element,
+                name: tile_name.clone(),
+            })?;
+        by_tile_type[tile_type as usize] = rate(value)?;
+    }
+
+    Ok(by_tile_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn decay_file_fills_in_defaults() {
+        let rates = decay_rates_from_json(&json!({
+            "format_version": 1,
+            "decay": {
+                "Water": { "default": 3, "Fire": 20 },
+                "Void": { "Air": 1 },
+            },
+        }))
+        .unwrap();
+
+        assert_eq!(rates.rate(TileType::Rock, Element::Water), 3);
+        assert_eq!(rates.rate(TileType::Fire, Element::Water), 20);
+        assert_eq!(rates.rate(TileType::Rock, Element::Void), 0);
+        assert_eq!(rates.rate(TileType::Air, Element::Void), 1);
+        assert_eq!(rates.rate(TileType::Rock, Element::Fire), 0);
+
+        assert!(matches!(
+            decay_rates_from_json(&json!({
+                "format_version": 1,
+                "decay": { "Water": { "Lava": 3 } },
+            })),
+            Err(DecayFileError::UnknownTileType { element: Element::Water, name }) if name == "Lava"
+        ));
+    }
+}
@@ -1,20 +1,50 @@
+mod area;
+#[cfg(test)]
+mod benchmarks;
 mod chemistry;
+mod compound_file;
+mod compounds;
+mod decay;
+mod decay_file;
+mod reaction_file;
+mod spreading;
 
 use super::*;
 use std::collections::HashMap;
 use std::ops::{Add, Sub, SubAssign};
 
+pub use area::*;
+pub use chemistry::{ChemicalReaction, ReactionEvent, Reactions};
+pub use compound_file::load_compound_recipes;
+pub use compounds::{CompoundRecipe, CompoundRecipes, COMPOUND_FILE_PATH};
+pub use decay::{DecayRates, DECAY_FILE_PATH};
+pub use decay_file::load_decay_rates;
+pub use reaction_file::{load_reactions, REACTION_FILE_PATH};
+
 #[derive(PartialEq, Debug, Eq, Hash, Clone, Copy)]
 pub enum Element {
     Fire,
     Water,
     Earth,
     Air,
+    Ice,
+    Lightning,
+    Nature,
+    Void,
 }
 
 impl Element {
-    pub fn all() -> [Self; 4] {
-        [Self::Fire, Self::Water, Self::Earth, Self::Air]
+    pub fn all() -> [Self; 8] {
+        [
+            Self::Fire,
+            Self::Water,
+            Self::Earth,
+            Self::Air,
+            Self::Ice,
+            Self::Lightning,
+            Self::Nature,
+            Self::Void,
+        ]
     }
 
     fn display_name(&self) -> &str {
@@ -23,8 +53,22 @@ impl Element {
             Self::Water => "Water",
             Self::Earth => "Earth",
             Self::Air => "Air",
+            Self::Ice => "Ice",
+            Self::Lightning => "Lightning",
+            Self::Nature => "Nature",
+            Self::Void => "Void",
         }
     }
+
+    /// Look up an element by the name it is displayed with.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::all().into_iter().find(|e| e.display_name() == name)
+    }
+
+    /// Whether the element leaks from tiles into their neighbours over time.
+    pub fn spreads(&self) -> bool {
+        matches!(*self, Self::Fire | Self::Water)
+    }
 }
 
 impl std::fmt::Display for Element {
@@ -33,32 +77,41 @@ impl std::fmt::Display for Element {
     }
 }
 
-#[derive(Component, PartialEq, Debug, Clone)]
-pub struct ElementalAffliction(HashMap<Element, u32>);
+/// How much of each element is on an entity.
+///
+/// Amounts are kept in a fixed array indexed by `Element`, so afflictions can be copied around
+/// freely without allocating. An element can be present with an amount of zero, which lets
+/// reaction prerequisites check for an element running out.
+#[derive(Component, PartialEq, Eq, Debug, Clone, Copy)]
+pub struct ElementalAffliction {
+    amounts: [u32; 8],
+    /// One bit per element, set while the element is present even if its amount is zero
+    present: u8,
+}
 
 impl ElementalAffliction {
     pub fn empty() -> Self {
-        ElementalAffliction(HashMap::new())
+        Self {
+            amounts: [0; 8],
+            present: 0,
+        }
     }
 
+    /// Adds to an element, saturating rather than overflowing.
     pub fn add_element(&mut self, element: Element, amount: u32) {
-        let initial = if let Some(&existing_val) = self.0.get(&element) {
-            existing_val
-        } else {
-            0
-        };
-
-        self.0.insert(element, initial + amount);
+        let idx = element as usize;
+        self.amounts[idx] = self.amounts[idx].saturating_add(amount);
+        self.present |= 1 << element as usize;
     }
 
     pub fn subtract_element(&mut self, element: Element, amount: u32) {
-        let initial = if let Some(&existing_val) = self.0.get(&element) {
-            existing_val
-        } else {
-            0
-        };
+        let idx = element as usize;
+        self.amounts[idx] = self.amounts[idx].saturating_sub(amount);
 
-        self.0.insert(element, initial.saturating_sub(amount));
+        // Elements that run out are dropped rather than kept at zero.
+        if self.amounts[idx] == 0 {
+            self.present &= !(1 << idx);
+        }
     }
 
     pub fn single(element: Element, amount: u32) -> Self {
@@ -69,27 +122,38 @@ impl ElementalAffliction {
     }
 
     pub fn get_element_amount(&self, element: Element) -> u32 {
-        if let Some(&val) = self.0.get(&element) {
-            val
-        } else {
-            0
-        }
+        self.amounts[element as usize]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.amounts.iter().all(|&amount| amount == 0)
+    }
+
+    /// Iterate over every element with a nonzero amount
+    pub fn iter(&self) -> impl Iterator<Item = (Element, u32)> + '_ {
+        self.entries().filter(|&(_, amount)| amount > 0)
+    }
+
+    /// Iterate over every element present, including ones with an amount of zero
+    fn entries(&self) -> impl Iterator<Item = (Element, u32)> + '_ {
+        Element::all()
+            .into_iter()
+            .filter(|&element| self.present & (1 << element as usize) != 0)
+            .map(|element| (element, self.get_element_amount(element)))
     }
 
     /// Checks if all the elements in other are present in self
     pub fn contains(&self, other: &ElementalAffliction) -> bool {
         other
-            .0
-            .iter()
-            .all(|(&element, &amount)| self.get_element_amount(element) >= amount)
+            .entries()
+            .all(|(element, amount)| self.get_element_amount(element) >= amount)
     }
 
     /// Checks if all the elements in other have the exact same values as self.
     pub fn contains_exactly(&self, other: &ElementalAffliction) -> bool {
         other
-            .0
-            .iter()
-            .all(|(&element, &amount)| self.get_element_amount(element) == amount)
+            .entries()
+            .all(|(element, amount)| self.get_element_amount(element) == amount)
     }
 }
 
@@ -97,9 +161,9 @@ impl Add<&ElementalAffliction> for &ElementalAffliction {
     type Output = ElementalAffliction;
 
     fn add(self, other: &ElementalAffliction) -> Self::Output {
-        let mut result = self.clone();
+        let mut result = *self;
 
-        other.0.iter().for_each(|(&element, &amount)| {
+        other.entries().for_each(|(element, amount)| {
             result.add_element(element, amount);
         });
 
@@ -111,9 +175,9 @@ impl Sub<&ElementalAffliction> for &ElementalAffliction {
     type Output = ElementalAffliction;
 
     fn sub(self, other: &ElementalAffliction) -> Self::Output {
-        let mut result = self.clone();
+        let mut result = *self;
 
-        other.0.iter().for_each(|(&element, &amount)| {
+        other.entries().for_each(|(element, amount)| {
             result.subtract_element(element, amount);
         });
 
@@ -123,7 +187,7 @@ impl Sub<&ElementalAffliction> for &ElementalAffliction {
 
 impl SubAssign<&ElementalAffliction> for ElementalAffliction {
     fn sub_assign(&mut self, other: &ElementalAffliction) {
-        other.0.iter().for_each(|(&element, &amount)| {
+        other.entries().for_each(|(element, amount)| {
             self.subtract_element(element, amount);
         });
     }
@@ -131,29 +195,30 @@ impl SubAssign<&ElementalAffliction> for ElementalAffliction {
 
 impl std::fmt::Display for ElementalAffliction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let element_count = self.0.keys().len();
-
-        self.0
-            .iter()
+        self.entries()
             .enumerate()
             .try_for_each(|(idx, (element, amount))| {
-                write!(f, "{element}: {amount}")?;
-
-                if idx < element_count - 1 {
-                    write!(f, "\n")?;
+                if idx > 0 {
+                    writeln!(f)?;
                 }
 
-                Ok(())
+                write!(f, "{element}: {amount}")
             })
     }
 }
 
+impl Consolidate for ElementalAffliction {
+    fn consolidate(&mut self, other: &Self) {
+        *self = &*self + other;
+    }
+}
+
 /// Tag component for applying elements
-#[derive(Component)]
+#[derive(Component, Clone)]
 pub struct ApplyElement;
 
 /// Tag component for reducing elements
-#[derive(Component)]
+#[derive(Component, Clone)]
 pub struct RemoveElements;
 
 #[derive(Bundle)]
@@ -165,6 +230,15 @@ pub struct ApplyElementMessage {
 }
 
 impl ApplyElementMessage {
+    pub fn new(element: ElementalAffliction, target: Entity) -> Self {
+        Self {
+            element,
+            target: Target(target),
+            message: Message,
+            apply_element: ApplyElement,
+        }
+    }
+
     pub fn single_element(element: Element, amount: u32, target: Entity) -> Self {
         Self {
             element: ElementalAffliction::single(element, amount),
@@ -178,47 +252,90 @@ impl ApplyElementMessage {
 /// This plugin handles all elemental interactions
 pub struct ElementPlugin;
 
-const CONSOLIDATE_MESSAGE_STAGE: &str = "consolidate_messages";
-const APPLY_ELEMENT_STAGE: &str = "apply_elements";
-const REMOVE_ELEMENT_STAGE: &str = "remove_elements";
-
 impl Plugin for ElementPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugin(chemistry::ChemistryPlugin)
-            .add_stage_before(
-                CoreStage::Update,
-                APPLY_ELEMENT_STAGE,
-                SystemStage::parallel(),
-            )
-            .add_stage_before(
-                APPLY_ELEMENT_STAGE,
-                REMOVE_ELEMENT_STAGE,
-                SystemStage::parallel(),
-            )
-            .add_stage_before(
-                REMOVE_ELEMENT_STAGE,
-                CONSOLIDATE_MESSAGE_STAGE,
-                SystemStage::parallel(),
-            )
-            .add_system_to_stage(
-                CONSOLIDATE_MESSAGE_STAGE,
-                consolidate_element_messages.run_in_state(GameState::TDMode),
+            .add_plugin(compounds::CompoundPlugin)
+            .add_plugin(decay::DecayPlugin);
+
+        add_element_stages(&mut app.schedule, CoreStage::Update, |step, stage| {
+            FixedTimestepStage::new(step).with_stage(stage)
+        });
+    }
+}
+
+/// Add the stages and systems that move elements around to `schedule`, which needs to have a
+/// `MESSAGE_STAGE` already.
+///
+/// Element messages are registered with the message bus, and tiles react to what was applied
+/// in `update_stage`. Spreading and decay only run every so often, so their stages are wrapped
+/// by `timed_stage` in whatever keeps time for the schedule, and go in ahead of the message bus
+/// so the messages they send are handled the same frame.
+///
+/// The game and the headless simulation both build their schedules with this, so chemistry
+/// plays out the same way in each.
+pub fn add_element_stages<S: Stage>(
+    schedule: &mut Schedule,
+    update_stage: impl StageLabel + Clone,
+    timed_stage: impl Fn(Duration, SystemStage) -> S,
+) {
+    let mut spreading_stage = SystemStage::parallel();
+    spreading_stage.add_system(spreading::spread_elements.run_in_state(GameState::TDMode));
+    let mut decay_stage = SystemStage::parallel();
+    decay_stage.add_system(decay::decay_elements.run_in_state(GameState::TDMode));
+
+    // Area messages are split up into tile messages first so those get consolidated with the
+    // rest, and elements are removed before new ones are applied.
+    schedule
+        .add_stage_before(
+            MESSAGE_STAGE,
+            "element_spreading",
+            timed_stage(
+                Duration::from_millis(spreading::SPREAD_STEP_MS),
+                spreading_stage,
+            ),
+        )
+        .add_stage_before(
+            MESSAGE_STAGE,
+            "element_decay",
+            timed_stage(Duration::from_millis(decay::DECAY_STEP_MS), decay_stage),
+        )
+        .add_message(
+            MessageKind::<TargetArea>::handled_by(
+                resolve_area_messages.run_in_state(GameState::TDMode),
             )
-            .add_system_to_stage(
-                REMOVE_ELEMENT_STAGE,
+            .max_age(AREA_MESSAGE_MAX_AGE),
+        )
+        .add_message(
+            MessageKind::<RemoveElements>::handled_by(
                 handle_remove_element_messages.run_in_state(GameState::TDMode),
             )
-            .add_system_to_stage(
-                APPLY_ELEMENT_STAGE,
+            .consolidate::<ElementalAffliction>(),
+        )
+        .add_message(
+            MessageKind::<ApplyElement>::handled_by(
                 handle_apply_element_messages.run_in_state(GameState::TDMode),
-            );
-    }
+            )
+            .consolidate::<ElementalAffliction>(),
+        )
+        .add_system_to_stage(
+            update_stage.clone(),
+            chemistry::trigger_reactions.run_in_state(GameState::TDMode),
+        )
+        // Tiles react to the elements applied to them before those elements can combine, so
+        // water still puts out a burning tile rather than boiling off into steam first.
+        .add_system_to_stage(
+            update_stage,
+            compounds::combine_elements
+                .run_in_state(GameState::TDMode)
+                .after(chemistry::trigger_reactions),
+        );
 }
 
 fn handle_apply_element_messages(
     message_query: Query<
         (Entity, &Target, &ElementalAffliction),
-        (With<Message>, With<ApplyElement>),
+        (With<Message>, With<ApplyElement>, Without<Handled>),
     >,
     affliction_query: Query<&ElementalAffliction>,
     mut commands: Commands,
@@ -234,7 +351,7 @@ fn handle_apply_element_messages(
                     .insert(existing_affliction + affliction);
             } else {
                 trace!("Creating new affliction.");
-                commands.entity(target.0).insert(affliction.clone());
+                commands.entity(target.0).insert(*affliction);
             }
 
             // Message is handled
@@ -245,7 +362,7 @@ fn handle_apply_element_messages(
 fn handle_remove_element_messages(
     message_query: Query<
         (Entity, &Target, &ElementalAffliction),
-        (With<Message>, With<RemoveElements>),
+        (With<Message>, With<RemoveElements>, Without<Handled>),
     >,
     affliction_query: Query<&ElementalAffliction>,
     mut commands: Commands,
@@ -264,73 +381,6 @@ fn handle_remove_element_messages(
         });
 }
 
-fn consolidate_element_messages(
-    message_query: Query<
-        (
-            Entity,
-            &Target,
-            &ElementalAffliction,
-            Option<&ApplyElement>,
-            Option<&RemoveElements>,
-        ),
-        (
-            With<Message>,
-            Or<(With<ApplyElement>, With<RemoveElements>)>,
-        ),
-    >,
-    mut commands: Commands,
-) {
-    let mut apply_sums = HashMap::new();
-    let mut remove_sums = HashMap::new();
-
-    let mut all_added = ElementalAffliction::empty();
-    let mut all_subtracted = ElementalAffliction::empty();
-    // Sum up existing messages and despawn them
-    message_query
-        .iter()
-        .for_each(|(entity, target, elements, apply, remove)| {
-            if apply.is_some() {
-                all_added = elements + &all_added;
-                if let Some(existing_elements) = apply_sums.remove(&target.0) {
-                    apply_sums.insert(target.0, &existing_elements + elements);
-                } else {
-                    apply_sums.insert(target.0, elements.clone());
-                }
-            } else if remove.is_some() {
-                all_subtracted = &all_subtracted + elements;
-                if let Some(existing_elements) = remove_sums.remove(&target.0) {
-                    remove_sums.insert(target.0, &existing_elements + elements);
-                } else {
-                    remove_sums.insert(target.0, elements.clone());
-                }
-            }
-
-            commands.entity(entity).despawn_recursive();
-        });
-
-    // Spawn new message for each target with totals
-
-    apply_sums.iter().for_each(|(target, elements)| {
-        trace!("Adding {elements} to {target:?}");
-        commands
-            .spawn()
-            .insert(Message)
-            .insert(Target(*target))
-            .insert(ApplyElement)
-            .insert(elements.clone());
-    });
-
-    remove_sums.iter().for_each(|(target, elements)| {
-        trace!("Removing {elements} from {target:?}");
-        commands
-            .spawn()
-            .insert(Message)
-            .insert(Target(*target))
-            .insert(RemoveElements)
-            .insert(elements.clone());
-    });
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,603 @@
+//! Loading the chemistry reaction table from the assets folder
+//!
+//! Reactions are stored as JSON in the following shape, and are checked in the order they're
+//! listed:
+//!
+//! ```json
+//! {
+//!     "format_version": 1,
+//!     "reactions": [
+//!         {
+//!             "name": "Rock catches fire",
+//!             "tile_type": "Rock",
+//!             "prerequisites": { "Fire": 50 },
+//!             "prerequisite_type": "Contains",
+//!             "subtract_prerequisites": false,
+//!             "new_tile_type": "Fire"
+//!         }
+//!     ]
+//! }
+//! ```
+//!
+//! `new_tile_type` can be left out or set to `null` for reactions that don't change the tile.
+//! A prerequisite amount of 0 means the tile must have none of that element left.
+//!
+//! Reactions can also list `effects` to carry out when they happen, each with a `type`:
+//!
+//! ```json
+//! "effects": [
+//!     { "type": "ApplyToNeighbours", "elements": { "Fire": 10 } },
+//!     {
+//!         "type": "ApplyToArea",
+//!         "elements": { "Water": 30 },
+//!         "shape": { "type": "Radius", "radius": 2 },
+//!         "falloff": 25
+//!     },
+//!     { "type": "DamageEnemies", "amount": 25.0, "element": "Fire" },
+//!     { "type": "SetStructure", "structure": "Barricade" },
+//!     { "type": "Event", "name": "Explosion" }
+//! ]
+//! ```
+//!
+//! Damage without an `"element"` only goes through enemies' armour. Setting the structure to
+//! `"None"` destroys whatever was on the tile.
+//!
+//! Area shapes are one of:
+//!
+//! - `{ "type": "Radius", "radius": 2 }`
+//! - `{ "type": "Cone", "direction": [1.0, 0.0], "half_angle": 45.0, "range": 3 }`, with the
+//!   angle in degrees
+//! - `{ "type": "Line", "direction": [0.0, 1.0], "length": 4 }`
+//! - `{ "type": "Rectangle", "half_width": 2, "half_height": 1 }`
+//!
+//! `falloff` is how many percent less each tile gets per tile away from the reacting tile, up to
+//! 100. It can be left out for no falloff.
+
+use super::chemistry::{ChemicalReaction, PrerequisiteType, ReactionEffect};
+use super::*;
+use bevy::asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset};
+use bevy::reflect::TypeUuid;
+use map::TileType;
+use serde_json::Value;
+use std::path::Path;
+
+/// The version of reaction file this build understands.
+pub const REACTION_FORMAT_VERSION: u64 = 1;
+
+/// Where the reaction table lives on disk, for tools that read it without the asset server.
+pub const REACTION_FILE_PATH: &str = "assets/data/chemistry.reactions.json";
+
+/// Everything that can go wrong reading a reaction file.
+#[derive(Debug)]
+pub enum ReactionFileError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    UnsupportedVersion(u64),
+    MissingField(&'static str),
+    InvalidReaction { index: usize, reason: &'static str },
+    UnknownTileType { index: usize, name: String },
+    UnknownElement { index: usize, name: String },
+    UnknownPrerequisiteType { index: usize, name: String },
+    UnknownEffect { index: usize, name: String },
+    UnknownStructure { index: usize, name: String },
+    UnknownShape { index: usize, name: String },
+}
+
+impl std::fmt::Display for ReactionFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "Could not access reaction file: {e}"),
+            Self::Json(e) => write!(f, "Reaction file is not valid JSON: {e}"),
+            Self::UnsupportedVersion(v) => write!(
+                f,
+                "Reaction format version {v} is not supported (expected {REACTION_FORMAT_VERSION})"
+            ),
+            Self::MissingField(field) => {
+                write!(f, "Reaction file is missing the field '{field}'")
+            }
+            Self::InvalidReaction { index, reason } => write!(f, "Reaction {index}: {reason}"),
+            Self::UnknownTileType { index, name } => {
+                write!(f, "Reaction {index}: unknown tile type '{name}'")
+            }
+            Self::UnknownElement { index, name } => {
+                write!(f, "Reaction {index}: unknown element '{name}'")
+            }
+            Self::UnknownPrerequisiteType { index, name } => {
+                write!(f, "Reaction {index}: unknown prerequisite type '{name}'")
+            }
+            Self::UnknownEffect { index, name } => {
+                write!(f, "Reaction {index}: unknown effect '{name}'")
+            }
+            Self::UnknownStructure { index, name } => {
+                write!(f, "Reaction {index}: unknown structure '{name}'")
+            }
+            Self::UnknownShape { index, name } => {
+                write!(f, "Reaction {index}: unknown area shape '{name}'")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReactionFileError {}
+
+impl From<std::io::Error> for ReactionFileError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ReactionFileError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+/// The reaction table as loaded from disk.
+#[derive(Debug, TypeUuid)]
+#[uuid = "5b0b9a4e-2f77-4c1e-9a43-3d8a2c6f1e90"]
+pub struct ReactionTable(pub Vec<ChemicalReaction>);
+
+#[derive(Default)]
+pub struct ReactionTableLoader;
+
+impl AssetLoader for ReactionTableLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let value: Value = serde_json::from_slice(bytes).map_err(ReactionFileError::from)?;
+            let reactions = reactions_from_json(&value)?;
+            load_context.set_default_asset(LoadedAsset::new(ReactionTable(reactions)));
+
+            Ok(())
+        })
+    }
+
+    /// Reaction tables get their own double extension so the loader doesn't claim every JSON
+    /// file in the assets folder.
+    fn extensions(&self) -> &[&str] {
+        &["reactions.json"]
+    }
+}
+
+/// Read the reaction table straight from disk. The game itself loads it through the asset server
+/// so edits are picked up while it runs.
+pub fn load_reactions(path: impl AsRef<Path>) -> Result<Vec<ChemicalReaction>, ReactionFileError> {
+    let contents = std::fs::read_to_string(path)?;
+    let value: Value = serde_json::from_str(&contents)?;
+
+    reactions_from_json(&value)
+}
+
+pub fn reactions_from_json(value: &Value) -> Result<Vec<ChemicalReaction>, ReactionFileError> {
+    let version = value
+        .get("format_version")
+        .and_then(Value::as_u64)
+        .ok_or(ReactionFileError::MissingField("format_version"))?;
+    if version != REACTION_FORMAT_VERSION {
+        return Err(ReactionFileError::UnsupportedVersion(version));
+    }
+
+    value
+        .get("reactions")
+        .and_then(Value::as_array)
+        .ok_or(ReactionFileError::MissingField("reactions"))?
+        .iter()
+        .enumerate()
+        .map(|(index, reaction)| reaction_from_json(index, reaction))
+        .collect()
+}
+
+fn reaction_from_json(
+    index: usize,
+    reaction: &Value,
+) -> Result<ChemicalReaction, ReactionFileError> {
+    let invalid = |reason| ReactionFileError::InvalidReaction { index, reason };
+    let tile_type = |name: &str| {
+        TileType::from_name(name).ok_or_else(|| ReactionFileError::UnknownTileType {
+            index,
+            name: name.to_string(),
+        })
+    };
+
+    let name = reaction
+        .get("name")
+        .and_then(Value::as_str)
+        .ok_or_else(|| invalid("missing 'name'"))?;
+
+    let reacting_tile_type = reaction
+        .get("tile_type")
+        .and_then(Value::as_str)
+        .ok_or_else(|| invalid("missing 'tile_type'"))
+        .and_then(tile_type)?;
+
+    let new_tile_type = match reaction.get("new_tile_type") {
+        None | Some(Value::Null) => None,
+        Some(Value::String(name)) => Some(tile_type(name)?),
+        Some(_) => return Err(invalid("'new_tile_type' must be a string or null")),
+    };
+
+    let prereq_name = reaction
+        .get("prerequisite_type")
+        .and_then(Value::as_str)
+        .ok_or_else(|| invalid("missing 'prerequisite_type'"))?;
+    let prereq_type = PrerequisiteType::from_name(prereq_name).ok_or_else(|| {
+        ReactionFileError::UnknownPrerequisiteType {
+            index,
+            name: prereq_name.to_string(),
+        }
+    })?;
+
+    let subtract_prerequisites = reaction
+        .get("subtract_prerequisites")
+        .and_then(Value::as_bool)
+        .ok_or_else(|| invalid("missing 'subtract_prerequisites'"))?;
+
+    let prerequisites = reaction
+        .get("prerequisites")
+        .ok_or_else(|| invalid("missing 'prerequisites'"))
+        .and_then(|elements| elements_from_json(index, elements))?;
+
+    let effects = match reaction.get("effects") {
+        None => Vec::new(),
+        Some(Value::Array(effects)) => effects
+            .iter()
+            .map(|effect| effect_from_json(index, effect))
+            .collect::<Result<_, _>>()?,
+        Some(_) => return Err(invalid("'effects' must be a list")),
+    };
+
+    Ok(ChemicalReaction {
+        name: name.to_string(),
+        tile_type: reacting_tile_type,
+        prerequisites,
+        prereq_type,
+        subtract_prerequisites,
+        new_tile_type,
+        effects,
+    })
+}
+
+fn elements_from_json(
+    index: usize,
+    elements: &Value,
+) -> Result<ElementalAffliction, ReactionFileError> {
+    let invalid = |reason| ReactionFileError::InvalidReaction { index, reason };
+
+    let mut affliction = ElementalAffliction::empty();
+    let elements = elements
+        .as_object()
+        .ok_or_else(|| invalid("elements must be an object"))?;
+    for (element_name, amount) in elements {
+        let element =
+            Element::from_name(element_name).ok_or_else(|| ReactionFileError::UnknownElement {
+                index,
+                name: element_name.clone(),
+            })?;
+        let amount = amount
+            .as_u64()
+            .and_then(|a| u32::try_from(a).ok())
+            .ok_or_else(|| invalid("element amounts must be positive integers"))?;
+
+        // Zero amounts are kept on purpose so reactions can check for an element running out.
+        affliction.add_element(element, amount);
+    }
+
+    Ok(affliction)
+}
+
+fn effect_from_json(index: usize, effect: &Value) -> Result<ReactionEffect, ReactionFileError> {
+    let invalid = |reason| ReactionFileError::InvalidReaction { index, reason };
+    let string = |field: &'static str| {
+        effect
+            .get(field)
+            .and_then(Value::as_str)
+            .ok_or_else(|| invalid("effect is missing a field"))
+    };
+
+    let effect_type = string("type")?;
+    match effect_type {
+        "ApplyToNeighbours" => effect
+            .get("elements")
+            .ok_or_else(|| invalid("'ApplyToNeighbours' needs 'elements'"))
+            .and_then(|elements| elements_from_json(index, elements))
+            .map(ReactionEffect::ApplyToNeighbours),
+        "ApplyToArea" => {
+            let elements = effect
+                .get("elements")
+                .ok_or_else(|| invalid("'ApplyToArea' needs 'elements'"))
+                .and_then(|elements| elements_from_json(index, elements))?;
+            let shape = effect
+                .get("shape")
+                .ok_or_else(|| invalid("'ApplyToArea' needs a 'shape'"))
+                .and_then(|shape| shape_from_json(index, shape))?;
+            let falloff = match effect.get("falloff") {
+                None => Falloff::None,
+                Some(percent) => percent
+                    .as_u64()
+                    .filter(|&p| p <= 100)
+                    .map(|p| Falloff::Linear(p as u32))
+                    .ok_or_else(|| invalid("'falloff' must be a whole percentage up to 100"))?,
+            };
+
+            Ok(ReactionEffect::ApplyToArea {
+                elements,
+                shape,
+                falloff,
+            })
+        }
+        "DamageEnemies" => {
+            let amount = effect
+                .get("amount")
+                .and_then(Value::as_f64)
+                .ok_or_else(|| invalid("'DamageEnemies' needs a numeric 'amount'"))?;
+            let element = match effect.get("element") {
+                None => None,
+                Some(name) => {
+                    let name = name
+                        .as_str()
+                        .ok_or_else(|| invalid("'element' must be an element name"))?;
+                    let element = Element::from_name(name).ok_or_else(|| {
+                        ReactionFileError::UnknownElement {
+                            index,
+                            name: name.to_string(),
+                        }
+                    })?;
+                    Some(element)
+                }
+            };
+
+            Ok(ReactionEffect::DamageEnemies(Damage {
+                amount: amount as f32,
+                element,
+            }))
+        }
+        "SetStructure" => {
+            let name = string("structure")?;
+            Structure::from_name(name)
+                .map(ReactionEffect::SetStructure)
+                .ok_or_else(|| ReactionFileError::UnknownStructure {
+                    index,
+                    name: name.to_string(),
+                })
+        }
+        "Event" => string("name").map(|name| ReactionEffect::Event(name.to_string())),
+        _ => Err(ReactionFileError::UnknownEffect {
+            index,
+            name: effect_type.to_string(),
+        }),
+    }
+}
+
+fn shape_from_json(index: usize, shape: &Value) -> Result<AreaShape, ReactionFileError> {
+    let invalid = |reason| ReactionFileError::InvalidReaction { index, reason };
+    let tiles = |field: &str| {
+        shape
+            .get(field)
+            .and_then(Value::as_u64)
+            .and_then(|t| u32::try_from(t).ok())
+            .ok_or_else(|| invalid("shape sizes must be positive integers"))
+    };
+    let direction = || {
+        shape
+            .get("direction")
+            .and_then(Value::as_array)
+            .and_then(|d| match d.as_slice() {
+                [x, y] => Some(Vec2::new(x.as_f64()? as f32, y.as_f64()? as f32)),
+                _ => None,
+            })
+            .filter(|d| *d != Vec2::ZERO)
+            .ok_or_else(|| invalid("shape directions must be two numbers that aren't both zero"))
+    };
+
+    let shape_type = shape
+        .get("type")
+        .and_then(Value::as_str)
+        .ok_or_else(|| invalid("shape is missing its 'type'"))?;
+    match shape_type {
+        "Radius" => Ok(AreaShape::Radius(tiles("radius")?)),
+        "Cone" => {
+            let half_angle = shape
+                .get("half_angle")
+                .and_then(Value::as_f64)
+                .ok_or_else(|| invalid("'Cone' needs a numeric 'half_angle'"))?;
+
+            Ok(AreaShape::Cone {
+                direction: direction()?,
+                half_angle: (half_angle as f32).to_radians(),
+                range: tiles("range")?,
+            })
+        }
+        "Line" => Ok(AreaShape::Line {
+            direction: direction()?,
+            length: tiles("length")?,
+        }),
+        "Rectangle" => Ok(AreaShape::Rectangle {
+            half_width: tiles("half_width")?,
+            half_height: tiles("half_height")?,
+        }),
+        _ => Err(ReactionFileError::UnknownShape {
+            index,
+            name: shape_type.to_string(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn shipped_reaction_file_loads() {
+        let contents = std::fs::read_to_string(REACTION_FILE_PATH).unwrap();
+        let reactions = reactions_from_json(&serde_json::from_str(&contents).unwrap()).unwrap();
+
+        let burn_out = reactions
+            .iter()
+            .find(|r| r.name == "Fire burns out")
+            .unwrap();
+        assert_eq!(burn_out.prereq_type, PrerequisiteType::ExactMatch);
+        assert_eq!(burn_out.new_tile_type, Some(TileType::Rock));
+        assert!(!ElementalAffliction::single(Element::Fire, 3)
+            .contains_exactly(&burn_out.prerequisites));
+    }
+
+    #[test]
+    fn newer_elements_react_with_every_tile_type() {
+        let contents = std::fs::read_to_string(REACTION_FILE_PATH).unwrap();
+        let reactions = reactions_from_json(&serde_json::from_str(&contents).unwrap()).unwrap();
+
+        for element in [
+            Element::Ice,
+            Element::Lightning,
+            Element::Nature,
+            Element::Void,
+        ] {
+            for tile_type in TileType::all() {
+                assert!(
+                    reactions.iter().any(|r| r.tile_type == tile_type
+                        && r.prerequisites.get_element_amount(element) > 0),
+                    "{element} has no reaction on {tile_type}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn reaction_file_errors_name_the_bad_reaction() {
+        let file = |tile_type: &str, element: &str| {
+            json!({
+                "format_version": 1,
+                "reactions": [
+                    {
+                        "name": "Fine",
+                        "tile_type": "Rock",
+                        "prerequisites": { "Fire": 1 },
+                        "prerequisite_type": "Contains",
+                        "subtract_prerequisites": false,
+                    },
+                    {
+                        "name": "Broken",
+                        "tile_type": tile_type,
+                        "prerequisites": { element: 1 },
+                        "prerequisite_type": "Contains",
+                        "subtract_prerequisites": false,
+                        "new_tile_type": null,
+                    },
+                ],
+            })
+        };
+
+        assert!(reactions_from_json(&file("Water", "Fire")).is_ok());
+        assert!(matches!(
+            reactions_from_json(&file("Lava", "Fire")),
+            Err(ReactionFileError::UnknownTileType { index: 1, name }) if name == "Lava"
+        ));
+        assert!(matches!(
+            reactions_from_json(&file("Water", "Plasma")),
+            Err(ReactionFileError::UnknownElement { index: 1, name }) if name == "Plasma"
+        ));
+    }
+
+    #[test]
+    fn area_effects_parse_their_shape() {
+        let file = |shape: Value| {
+            json!({
+                "format_version": 1,
+                "reactions": [{
+                    "name": "Splash",
+                    "tile_type": "Water",
+                    "prerequisites": { "Earth": 10 },
+                    "prerequisite_type": "Contains",
+                    "subtract_prerequisites": true,
+                    "effects": [{
+                        "type": "ApplyToArea",
+                        "elements": { "Water": 20 },
+                        "shape": shape,
+                        "falloff": 25,
+                    }],
+                }],
+            })
+        };
+
+        let reactions = reactions_from_json(&file(json!({
+            "type": "Cone",
+            "direction": [0.0, 1.0],
+            "half_angle": 90.0,
+            "range": 2,
+        })))
+        .unwrap();
+        assert_eq!(
+            reactions[0].effects,
+            vec![ReactionEffect::ApplyToArea {
+                elements: ElementalAffliction::single(Element::Water, 20),
+                shape: AreaShape::Cone {
+                    direction: Vec2::Y,
+                    half_angle: std::f32::consts::FRAC_PI_2,
+                    range: 2,
+                },
+                falloff: Falloff::Linear(25),
+            }]
+        );
+
+        assert!(matches!(
+            reactions_from_json(&file(json!({ "type": "Star", "points": 5 }))),
+            Err(ReactionFileError::UnknownShape { index: 0, name }) if name == "Star"
+        ));
+        assert!(matches!(
+            reactions_from_json(&file(
+                json!({ "type": "Line", "direction": [0, 0], "length": 3 })
+            )),
+            Err(ReactionFileError::InvalidReaction { index: 0, .. })
+        ));
+
+        let mut too_much_falloff = file(json!({ "type": "Radius", "radius": 1 }));
+        too_much_falloff["reactions"][0]["effects"][0]["falloff"] = 101.into();
+        assert!(matches!(
+            reactions_from_json(&too_much_falloff),
+            Err(ReactionFileError::InvalidReaction { index: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn damage_effects_can_have_an_element() {
+        let file = |effect: Value| {
+            json!({
+                "format_version": 1,
+                "reactions": [{
+                    "name": "Scorch",
+                    "tile_type": "Barren",
+                    "prerequisites": { "Fire": 10 },
+                    "prerequisite_type": "Contains",
+                    "subtract_prerequisites": true,
+                    "effects": [effect],
+                }],
+            })
+        };
+
+        let damage = |effect: Value| {
+            reactions_from_json(&file(effect)).map(|reactions| reactions[0].effects.clone())
+        };
+        assert_eq!(
+            damage(json!({ "type": "DamageEnemies", "amount": 10.0, "element": "Fire" })).unwrap(),
+            vec![ReactionEffect::DamageEnemies(Damage {
+                amount: 10.0,
+                element: Some(Element::Fire),
+            })]
+        );
+        assert_eq!(
+            damage(json!({ "type": "DamageEnemies", "amount": 10.0 })).unwrap(),
+            vec![ReactionEffect::DamageEnemies(Damage {
+                amount: 10.0,
+                element: None,
+            })]
+        );
+        assert!(matches!(
+            damage(json!({ "type": "DamageEnemies", "amount": 10.0, "element": "Plasma" })),
+            Err(ReactionFileError::UnknownElement { index: 0, name }) if name == "Plasma"
+        ));
+    }
+}
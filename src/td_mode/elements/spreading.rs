@@ -0,0 +1,192 @@
+//! Elements spreading between neighbouring tiles
+//!
+//! Every spreading element on a tile leaks a share of itself into each of the tile's neighbours,
+//! four or eight depending on the map's `Neighbourhood`. The share depends on the type of the
+//! tile it leaks from, and each tile type caps how much it can soak up from its neighbours.
+//! Whatever a neighbour soaks up is taken off the tile it leaked from, so spreading moves
+//! elements around without creating any. A fire only keeps burning while the tiles around it
+//! are still soaking its fire up slower than it can pass it on.
+
+use super::*;
+use map::{MapRoot, Tile};
+
+/// Spreading runs on its own, slower timestep so fires creep rather than jump across the map.
+pub(super) const SPREAD_STEP_MS: u64 = 100;
+
+/// What spreading does to one tile over a step.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Spread {
+    /// Elements soaked up from the tile's neighbours
+    pub gained: ElementalAffliction,
+    /// Elements soaked up by the tile's neighbours
+    pub lost: ElementalAffliction,
+}
+
+/// Work out how much of each spreading element moves between neighbouring tiles over `seconds`.
+///
+/// `afflictions` is indexed the same way as the map's tiles, and so is the result.
+pub fn spread_step(map: &Map, afflictions: &[ElementalAffliction], seconds: f32) -> Vec<Spread> {
+    // The shares each tile is offered by its neighbours, as (neighbour, element, amount).
+    let mut offers = vec![Vec::new(); map.tile_count()];
+
+    afflictions
+        .iter()
+        .enumerate()
+        .for_each(|(idx, affliction)| {
+            let rate = map
+                .tile_type_at_index(idx)
+                .map_or(0.0, TileType::spread_rate);
+            let neighbours = map.coord_neighbour_indices(map.idx_to_coord(idx));
+            if neighbours.is_empty() {
+                return;
+            }
+
+            affliction
+                .iter()
+                .filter(|(element, _)| element.spreads())
+                .for_each(|(element, amount)| {
+                    // A tile can't give away more than it has, however fast it spreads.
+                    let share = ((amount as f32 * rate * seconds) as u32)
+                        .min(amount / neighbours.len() as u32);
+                    if share > 0 {
+                        neighbours.iter().for_each(|&n| {
+                            offers[n].push((idx, element, share));
+                        });
+                    }
+                });
+        });
+
+    // Only let each tile take in as much as it has room for, and only take what it does take
+    // from the tiles that offered it.
+    let empty = Spread {
+        gained: ElementalAffliction::empty(),
+        lost: ElementalAffliction::empty(),
+    };
+    let mut spread = vec![empty; map.tile_count()];
+    offers.iter().enumerate().for_each(|(idx, offers)| {
+        if let Some(tile_type) = map.tile_type_at_index(idx) {
+            offers.iter().for_each(|&(source, element, share)| {
+                let current = afflictions
+                    .get(idx)
+                    .map_or(0, |a| a.get_element_amount(element))
+                    + spread[idx].gained.get_element_amount(element);
+                let taken = share.min(tile_type.spread_cap(element).saturating_sub(current));
+                if taken > 0 {
+                    spread[idx].gained.add_element(element, taken);
+                    spread[source].lost.add_element(element, taken);
+                }
+            });
+        }
+    });
+
+    spread
+}
+
+pub(super) fn spread_elements(
+    tile_query: Query<Option<&ElementalAffliction>, With<Tile>>,
+    map_root_query: Query<&MapRoot>,
+    map: Res<Map>,
+    mut commands: Commands,
+) {
+    if let Ok(map_root) = map_root_query.get_single() {
+        // Tiles may be out of sync with the map for a frame after a resize.
+        if map_root.tile_entities.len() != map.tile_count() {
+            return;
+        }
+
+        let afflictions: Vec<ElementalAffliction> = map_root
+            .tile_entities
+            .iter()
+            .map(|&e| {
+                tile_query
+                    .get(e)
+                    .ok()
+                    .flatten()
+                    .copied()
+                    .unwrap_or_else(ElementalAffliction::empty)
+            })
+            .collect();
+
+        let seconds = seconds_rate_to_fixed_rate(1.0, SPREAD_STEP_MS);
+        spread_step(&map, &afflictions, seconds)
+            .into_iter()
+            .zip(map_root.tile_entities.iter())
+            .for_each(|(spread, &e)| {
+                if !spread.lost.is_empty() {
+                    commands
+                        .spawn()
+                        .insert(Message)
+                        .insert(Target(e))
+                        .insert(RemoveElements)
+                        .insert(spread.lost);
+                }
+                if !spread.gained.is_empty() {
+                    commands.spawn_bundle(ApplyElementMessage::new(spread.gained, e));
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::simulation::Simulation;
+    use super::*;
+
+    #[test]
+    fn fire_front_crosses_a_row_of_rock_without_creating_fire() {
+        let mut map = Map::new((6, 1));
+        for x in 1..6 {
+            map.set_tile((x, 0).into(), Some(TileType::Rock), None);
+        }
+        map.set_tile((0, 0).into(), Some(TileType::Fire), None);
+
+        // Rock catches fire under the shipped reaction table. Nothing decays, so the only thing
+        // moving fire around is spreading.
+        let mut simulation = Simulation::new(
+            map,
+            &[ElementalAffliction::single(Element::Fire, 600)],
+            load_reactions(REACTION_FILE_PATH).unwrap(),
+            Vec::new(),
+            DecayRates::none(),
+            Duration::from_millis(SPREAD_STEP_MS),
+        );
+        let total_fire = |simulation: &Simulation| -> u32 {
+            simulation
+                .afflictions()
+                .iter()
+                .map(|a| a.get_element_amount(Element::Fire))
+                .sum()
+        };
+
+        // Tiles that catch fire, in the order they did.
+        let mut lit = vec![0];
+        while simulation.tick() < 200 && lit.len() < 6 {
+            simulation.step();
+            assert_eq!(total_fire(&simulation), 600);
+
+            for idx in 0..6 {
+                let burning = simulation.map().tile_type_at_index(idx) == Some(&TileType::Fire);
+                if burning && !lit.contains(&idx) {
+                    lit.push(idx);
+                }
+            }
+        }
+
+        assert_eq!(lit, vec![0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn water_tiles_do_not_catch_fire() {
+        let mut map = Map::new((2, 1));
+        map.set_tile((1, 0).into(), Some(TileType::Water), None);
+
+        let afflictions = vec![
+            ElementalAffliction::single(Element::Fire, 100),
+            ElementalAffliction::empty(),
+        ];
+
+        let spread = spread_step(&map, &afflictions, 1.0);
+        assert_eq!(spread[1].gained.get_element_amount(Element::Fire), 0);
+        assert!(spread[0].lost.is_empty());
+    }
+}
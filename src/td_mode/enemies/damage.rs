@@ -0,0 +1,323 @@
+//! Resolving damage dealt to enemies
+//!
+//! Anything that hurts enemies sends a `Damage` message, either to an enemy or to a tile to hit
+//! every enemy standing on it. Each hit is worked out from the message's amount in turn:
+//! 1. The enemy's armour blocks a fraction of it.
+//! 2. The enemy's resistance to the damage's element blocks or adds a fraction of what's left.
+//! 3. Elements the enemy is carrying can leave it vulnerable to the damage's element, see
+//!    `VULNERABILITIES`.
+//! 4. Being Armoured by Earth blocks a fraction of the rest.
+//!
+//! A hit that takes an enemy's health to nothing sends a `Died` message with how much damage
+//! went past what was needed, and the enemy is despawned once that message is handled. Damage
+//! dealt, kills and overkill are added up per `Source` in `DamageTotals` for the wave in
+//! progress.
+
+use super::*;
+use std::collections::HashMap;
+
+/// Carrying enough of one element makes an enemy take more of another element's damage.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Vulnerability {
+    pub carried: Element,
+    /// How much of the carried element an enemy needs before it's vulnerable
+    pub threshold: u32,
+    pub damage: Element,
+    /// What the damage is multiplied by
+    pub multiplier: f32,
+}
+
+/// Every way elements on an enemy change the damage it takes.
+pub const VULNERABILITIES: [Vulnerability; 3] = [
+    // Wet enemies conduct.
+    Vulnerability {
+        carried: Element::Water,
+        threshold: 10,
+        damage: Element::Lightning,
+        multiplier: 1.5,
+    },
+    // Frozen enemies shatter.
+    Vulnerability {
+        carried: Element::Ice,
+        threshold: 10,
+        damage: Element::Earth,
+        multiplier: 1.5,
+    },
+    // Overgrown enemies catch light.
+    Vulnerability {
+        carried: Element::Nature,
+        threshold: 10,
+        damage: Element::Fire,
+        multiplier: 1.25,
+    },
+];
+
+/// How much of a hit of `element` damage an enemy takes while carrying `affliction`.
+pub fn vulnerability_multiplier(affliction: &ElementalAffliction, element: Element) -> f32 {
+    VULNERABILITIES
+        .iter()
+        .filter(|v| v.damage == element)
+        .filter(|v| affliction.get_element_amount(v.carried) >= v.threshold)
+        .map(|v| v.multiplier)
+        .product()
+}
+
+/// The health an enemy loses from `damage`.
+pub fn damage_taken(
+    damage: Damage,
+    kind: EnemyKind,
+    affliction: &ElementalAffliction,
+    status: &StatusEffects,
+) -> f32 {
+    let mut amount = damage.amount * (1.0 - kind.armour());
+    if let Some(element) = damage.element {
+        amount *= (1.0 - kind.resistance(element)) * vulnerability_multiplier(affliction, element);
+    }
+
+    (amount * status.damage_taken_multiplier()).max(0.0)
+}
+
+/// Tag component for messages sent when an enemy dies. The message's Target is the enemy and its
+/// Source is whatever dealt the killing blow, if anything did.
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct Died {
+    /// How much damage the killing blow dealt past what it took to kill the enemy
+    pub overkill: f32,
+}
+
+/// Everything one `Source` has done to enemies.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SourceTotals {
+    /// Damage dealt, not counting overkill
+    pub dealt: f32,
+    pub kills: u32,
+    pub overkill: f32,
+}
+
+/// Resource adding up the damage each `Source` has dealt in the current wave.
+#[derive(Debug, Default)]
+pub struct DamageTotals {
+    /// The wave the totals are for
+    pub wave: Option<usize>,
+    pub sources: HashMap<Entity, SourceTotals>,
+}
+
+impl DamageTotals {
+    /// Start the totals over if a new wave has started since they were last added to.
+    fn track_wave(&mut self, wave: Option<usize>) {
+        if self.wave != wave {
+            self.wave = wave;
+            self.sources.clear();
+        }
+    }
+}
+
+/// Register the damage messages with the message bus. The game and the simulation share this.
+pub fn add_damage_messages(schedule: &mut Schedule) {
+    schedule
+        .add_message(MessageKind::<Damage>::handled_by(
+            resolve_damage_messages.run_in_state(GameState::TDMode),
+        ))
+        .add_message(MessageKind::<Died>::handled_by(
+            handle_died_messages.run_in_state(GameState::TDMode),
+        ));
+}
+
+/// Deal damage sent to enemies, or to the tiles they're standing on.
+pub fn resolve_damage_messages(
+    message_query: Query<
+        (Entity, &Target, &Damage, Option<&Source>),
+        (With<Message>, Without<Handled>),
+    >,
+    tile_query: Query<&Coordinate, With<Tile>>,
+    mut enemy_query: Query<
+        (
+            Entity,
+            &EnemyKind,
+            &Coordinate,
+            &EnemyPath,
+            &StatusEffects,
+            Option<&ElementalAffliction>,
+            &mut Health,
+        ),
+        With<Enemy>,
+    >,
+    wave_state: Option<Res<WaveState>>,
+    mut totals: ResMut<DamageTotals>,
+    mut commands: Commands,
+) {
+    totals.track_wave(wave_state.and_then(|state| state.current_wave));
+
+    message_query
+        .iter()
+        .for_each(|(message_entity, target, damage, source)| {
+            let tile_coord = tile_query.get(target.0).ok();
+            enemy_query
+                .iter_mut()
+                .filter(|(e, _, coord, path, ..)| match tile_coord {
+                    Some(tile_coord) => path.current_tile(**coord) == *tile_coord,
+                    None => *e == target.0,
+                })
+                .filter(|(.., health)| health.current > 0.0)
+                .for_each(|(e, kind, _, _, status, affliction, mut health)| {
+                    let affliction = affliction
+                        .copied()
+                        .unwrap_or_else(ElementalAffliction::empty);
+                    let taken = damage_taken(*damage, *kind, &affliction, status);
+                    // Damage past what the enemy had left only counts as overkill.
+                    let dealt = taken.min(health.current);
+                    health.current -= taken;
+
+                    let source_totals = source.map(|s| totals.sources.entry(s.0).or_default());
+                    if let Some(source_totals) = source_totals {
+                        source_totals.dealt += dealt;
+                    }
+
+                    if health.current <= 0.0 {
+                        let mut died = commands.spawn();
+                        died.insert(Message).insert(Target(e)).insert(Died {
+                            overkill: -health.current,
+                        });
+                        if let Some(source) = source {
+                            died.insert(Source(source.0));
+                        }
+                    }
+                });
+
+            commands.entity(message_entity).insert(Handled);
+        });
+}
+
+/// Credit kills to whatever dealt the killing blow, and remove the dead enemies.
+///
+/// Enemies are only despawned here, so their `Died` messages always reach a live target however
+/// they died.
+pub fn handle_died_messages(
+    message_query: Query<
+        (Entity, &Target, &Died, Option<&Source>),
+        (With<Message>, Without<Handled>),
+    >,
+    mut totals: ResMut<DamageTotals>,
+    mut commands: Commands,
+) {
+    message_query
+        .iter()
+        .for_each(|(message_entity, target, died, source)| {
+            if let Some(source) = source {
+                trace!(
+                    "{:?} killed {:?} with {:.1} overkill",
+                    source.0,
+                    target.0,
+                    died.overkill
+                );
+                let source_totals = totals.sources.entry(source.0).or_default();
+                source_totals.kills += 1;
+                source_totals.overkill += died.overkill;
+            } else {
+                trace!("{:?} died", target.0);
+            }
+
+            commands.entity(target.0).despawn_recursive();
+            commands.entity(message_entity).insert(Handled);
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wet_enemies_take_extra_lightning_damage() {
+        let lightning = Damage {
+            amount: 20.0,
+            element: Some(Element::Lightning),
+        };
+        let status = StatusEffects::default();
+
+        let dry = damage_taken(
+            lightning,
+            EnemyKind::Grunt,
+            &ElementalAffliction::empty(),
+            &status,
+        );
+        let wet = damage_taken(
+            lightning,
+            EnemyKind::Grunt,
+            &ElementalAffliction::single(Element::Water, 10),
+            &status,
+        );
+        assert!((dry - 20.0).abs() < f32::EPSILON);
+        assert!((wet - 30.0).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn armour_and_resistances_stack() {
+        let earth = ElementalAffliction::single(Element::Earth, 30);
+        let hit = |element| {
+            damage_taken(
+                Damage {
+                    amount: 100.0,
+                    element,
+                },
+                EnemyKind::Brute,
+                &earth,
+                &StatusEffects::from_affliction(&earth),
+            )
+        };
+
+        // 25% armour, then 30% from being Armoured.
+        assert!((hit(None) - 52.5).abs() < 0.001);
+        // Brutes shrug off half of Earth damage...
+        assert!((hit(Some(Element::Earth)) - 26.25).abs() < 0.001);
+        // ...and take a quarter extra from Fire.
+        assert!((hit(Some(Element::Fire)) - 65.625).abs() < 0.001);
+    }
+
+    #[test]
+    fn killing_blows_are_credited_to_their_source() {
+        let map = Map::new((3, 1));
+        let mut flow_fields = FlowFields::default();
+        flow_fields.update(&map);
+
+        let mut world = World::new();
+        let enemy = world
+            .spawn()
+            .insert_bundle(EnemyBundle::at_wave_entry(
+                &map,
+                &flow_fields,
+                EnemyKind::Runner,
+            ))
+            .id();
+        let tower = world.spawn().id();
+        world.insert_resource(map);
+        world.insert_resource(DamageTotals::default());
+
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(resolve_damage_messages);
+        stage.add_system(handle_died_messages.after(resolve_damage_messages));
+
+        [50.0, 50.0].into_iter().for_each(|amount| {
+            world
+                .spawn()
+                .insert(Message)
+                .insert(Target(enemy))
+                .insert(Source(tower))
+                .insert(Damage {
+                    amount,
+                    element: None,
+                });
+        });
+        // The first run resolves the damage, the second handles the death.
+        stage.run(&mut world);
+        stage.run(&mut world);
+
+        assert_eq!(
+            world.resource::<DamageTotals>().sources[&tower],
+            SourceTotals {
+                dealt: 60.0,
+                kills: 1,
+                overkill: 40.0,
+            }
+        );
+    }
+}
@@ -0,0 +1,520 @@
+//! Enemies and their movement through the map
+//!
+//! Enemies walk tile by tile along the cheapest route from the wave entry portal to the wave
+//! exit portal, where what's cheapest depends on their `MovementClass`. Whenever the map changes
+//! somewhere along the rest of their route they re-plan by following their class's `FlowField`,
+//! so barricades and terrain changes push them onto new paths mid-wave. Elements applied to
+//! enemies give them status effects, see `status`, and damage dealt to them is worked out in
+//! `damage`.
+
+mod damage;
+mod status;
+mod waves;
+
+use super::td_mode_prelude::*;
+use crate::prelude::*;
+use std::collections::VecDeque;
+
+pub use damage::*;
+pub use status::*;
+pub use waves::*;
+
+const FIXED_STEP_MS: u64 = 20;
+
+/// The fixed timestep stage enemies move in. Anything reacting to enemy positions on a fixed
+/// timestep should run after it.
+pub const ENEMY_FIXED_STAGE: &str = "enemy_fixed_update";
+
+/// How far above the tile enemies are drawn.
+const ENEMY_HEIGHT: f32 = 0.3;
+
+/// How many tiles away from an enemy's route a change has to be for it to re-plan.
+const REPLAN_DISTANCE: u32 = 1;
+
+pub struct EnemyPlugin;
+
+impl Plugin for EnemyPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(setup)
+            .insert_resource(Leaks(0))
+            .insert_resource(WaveSchedule::starter())
+            .insert_resource(WaveState::default())
+            .insert_resource(DamageTotals::default())
+            .add_system(add_enemy_models.run_in_state(GameState::TDMode))
+            .add_system(update_enemy_transforms.run_in_state(GameState::TDMode))
+            .add_message(MessageKind::<Leaked>::handled_by(
+                handle_leaked_messages.run_in_state(GameState::TDMode),
+            ))
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                replan_enemy_paths
+                    .run_in_state(GameState::TDMode)
+                    .after(UpdateFlowFields)
+                    .before(UpdateChangedTiles),
+            );
+
+        let mut fixed_stage = SystemStage::parallel();
+        fixed_stage.add_system(update_status_effects.run_in_state(GameState::TDMode));
+        fixed_stage.add_system(
+            move_enemies
+                .run_in_state(GameState::TDMode)
+                .after(update_status_effects),
+        );
+        fixed_stage.add_system(
+            apply_status_effects
+                .run_in_state(GameState::TDMode)
+                .after(move_enemies),
+        );
+        fixed_stage.add_system(spawn_wave_enemies.run_in_state(GameState::TDMode));
+
+        app.add_stage_before(
+            CoreStage::Update,
+            ENEMY_FIXED_STAGE,
+            FixedTimestepStage::new(Duration::from_millis(FIXED_STEP_MS)).with_stage(fixed_stage),
+        );
+
+        add_damage_messages(&mut app.schedule);
+    }
+}
+
+/// Tag component for enemies
+#[derive(Component)]
+pub struct Enemy;
+
+/// The different types of enemies that can show up in a wave.
+#[derive(Clone, Copy, Component, Debug, PartialEq)]
+pub enum EnemyKind {
+    Grunt,
+    Runner,
+    Brute,
+    Bat,
+    Eel,
+    Mole,
+}
+
+impl EnemyKind {
+    pub fn all() -> [Self; 6] {
+        [
+            Self::Grunt,
+            Self::Runner,
+            Self::Brute,
+            Self::Bat,
+            Self::Eel,
+            Self::Mole,
+        ]
+    }
+
+    fn display_name(&self) -> &str {
+        match *self {
+            Self::Grunt => "Grunt",
+            Self::Runner => "Runner",
+            Self::Brute => "Brute",
+            Self::Bat => "Bat",
+            Self::Eel => "Eel",
+            Self::Mole => "Mole",
+        }
+    }
+
+    /// Look up an enemy kind by the name it is displayed with.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::all().into_iter().find(|k| k.display_name() == name)
+    }
+
+    pub fn base_health(&self) -> f32 {
+        match *self {
+            Self::Grunt => 100.0,
+            Self::Runner => 60.0,
+            Self::Brute => 300.0,
+            Self::Bat => 40.0,
+            Self::Eel => 80.0,
+            Self::Mole => 150.0,
+        }
+    }
+
+    /// The fraction of every hit the enemy blocks.
+    pub fn armour(&self) -> f32 {
+        match *self {
+            Self::Grunt | Self::Runner | Self::Bat | Self::Eel => 0.0,
+            Self::Brute => 0.25,
+            Self::Mole => 0.1,
+        }
+    }
+
+    /// The fraction of `element` damage the enemy shrugs off. Negative if it takes extra.
+    pub fn resistance(&self, element: Element) -> f32 {
+        match (*self, element) {
+            (Self::Runner | Self::Bat, Element::Air)
+            | (Self::Brute | Self::Mole, Element::Earth)
+            | (Self::Eel, Element::Water) => 0.5,
+            (Self::Brute, Element::Fire) => -0.25,
+            (Self::Eel, Element::Lightning) => -0.5,
+            _ => 0.0,
+        }
+    }
+
+    /// Tiles per second
+    pub fn base_speed(&self) -> f32 {
+        match *self {
+            Self::Grunt => 1.0,
+            Self::Runner => 2.0,
+            Self::Brute => 0.5,
+            Self::Bat => 1.5,
+            Self::Eel => 1.25,
+            Self::Mole => 0.75,
+        }
+    }
+
+    /// How the enemy gets around, and so which route it takes.
+    pub fn movement_class(&self) -> MovementClass {
+        match *self {
+            Self::Grunt | Self::Runner | Self::Brute => MovementClass::Walker,
+            Self::Bat => MovementClass::Flier,
+            Self::Eel => MovementClass::Swimmer,
+            Self::Mole => MovementClass::Burrower,
+        }
+    }
+}
+
+impl std::fmt::Display for EnemyKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.display_name())
+    }
+}
+
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct Health {
+    pub current: f32,
+    pub max: f32,
+}
+
+impl Health {
+    pub fn new(max: f32) -> Self {
+        Self { current: max, max }
+    }
+}
+
+/// How fast an enemy moves in tiles per second
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct MoveSpeed(pub f32);
+
+/// The route an enemy is walking along.
+///
+/// The enemy's Coordinate is the tile it's walking away from, and the first tile of the route
+/// is the one it's walking towards.
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct EnemyPath {
+    pub route: VecDeque<Coordinate>,
+    /// How far between its current tile and the next one the enemy is, from 0.0 to 1.0
+    pub progress: f32,
+}
+
+impl EnemyPath {
+    /// Builds a path from a list of coordinates that starts at the enemy's current tile.
+    pub fn from_route(route: &[Coordinate]) -> Self {
+        Self {
+            route: route.iter().skip(1).copied().collect(),
+            progress: 0.0,
+        }
+    }
+
+    /// How many tiles the enemy still has to walk before the end of its route.
+    pub fn tiles_remaining(&self) -> f32 {
+        self.route.len() as f32 - self.progress
+    }
+
+    /// The tile the enemy is mostly standing on.
+    pub fn current_tile(&self, current: Coordinate) -> Coordinate {
+        match self.route.front() {
+            Some(&next) if self.progress >= 0.5 => next,
+            _ => current,
+        }
+    }
+
+    /// The position of an enemy on the map's XZ plane in tile units.
+    pub fn map_position(&self, current: Coordinate) -> Vec2 {
+        let from = Vec2::from(current);
+
+        self.route
+            .front()
+            .map_or(from, |&next| from.lerp(Vec2::from(next), self.progress))
+    }
+}
+
+#[derive(Bundle)]
+pub struct EnemyBundle {
+    enemy: Enemy,
+    kind: EnemyKind,
+    name: Name,
+    health: Health,
+    move_speed: MoveSpeed,
+    movement_class: MovementClass,
+    coord: Coordinate,
+    path: EnemyPath,
+    status: StatusEffects,
+    #[bundle]
+    transform: TransformBundle,
+}
+
+impl EnemyBundle {
+    /// Creates an enemy standing on the wave entry portal, following the flow field for its
+    /// movement class to the exit.
+    ///
+    /// If the field hasn't caught up with the map yet the enemy waits at the entry until
+    /// `replan_enemy_paths` gives it a route.
+    pub fn at_wave_entry(map: &Map, flow_fields: &FlowFields, kind: EnemyKind) -> Self {
+        let movement_class = kind.movement_class();
+        let route = flow_fields
+            .get(movement_class)
+            .filter(|field| !field.is_stale(map))
+            .and_then(|field| field.path_from(map.wave_entry_coord))
+            .map(|(route, _)| route);
+
+        Self {
+            enemy: Enemy,
+            kind,
+            name: Name::new(kind.to_string()),
+            health: Health::new(kind.base_health()),
+            move_speed: MoveSpeed(kind.base_speed()),
+            movement_class,
+            coord: map.wave_entry_coord,
+            path: EnemyPath::from_route(route.as_deref().unwrap_or_default()),
+            status: StatusEffects::default(),
+            transform: TransformBundle::identity(),
+        }
+    }
+}
+
+/// Spawns an enemy as a child of the map so it moves in the same space as the tiles.
+pub fn spawn_enemy(commands: &mut Commands, map_root: Entity, enemy: EnemyBundle) -> Entity {
+    commands.spawn_bundle(enemy).insert(Parent(map_root)).id()
+}
+
+/// Tag component for messages sent when an enemy reaches the exit portal.
+///
+/// The message's Source is the enemy and its Target is the exit portal's tile.
+#[derive(Component, Clone)]
+pub struct Leaked;
+
+/// Resource counting the enemies that have made it to the exit portal.
+pub struct Leaks(pub u32);
+
+struct EnemyModels {
+    mesh: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+}
+
+fn setup(
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut commands: Commands,
+) {
+    commands.insert_resource(EnemyModels {
+        mesh: meshes.add(Mesh::from(shape::Capsule {
+            radius: 0.15,
+            depth: 0.3,
+            ..default()
+        })),
+        material: materials.add(Color::rgb(0.6, 0.1, 0.6).into()),
+    });
+}
+
+fn add_enemy_models(
+    enemy_query: Query<Entity, Added<Enemy>>,
+    models: Res<EnemyModels>,
+    mut commands: Commands,
+) {
+    enemy_query.iter().for_each(|e| {
+        commands.entity(e).with_children(|p| {
+            p.spawn_bundle(PbrBundle {
+                mesh: models.mesh.clone(),
+                material: models.material.clone(),
+                ..default()
+            });
+        });
+    });
+}
+
+fn update_enemy_transforms(
+    mut enemy_query: Query<(&Coordinate, &EnemyPath, &mut Transform), With<Enemy>>,
+) {
+    enemy_query
+        .iter_mut()
+        .for_each(|(coord, path, mut transform)| {
+            let position = path.map_position(*coord);
+            transform.translation = Vec3::new(position.x, ENEMY_HEIGHT, position.y);
+        });
+}
+
+/// Walk enemies along their routes, sending a Leaked message when one runs out of route.
+///
+/// Dead enemies stay where they fell until their `Died` message is handled.
+pub fn move_enemies(
+    mut enemy_query: Query<
+        (
+            Entity,
+            &MoveSpeed,
+            &StatusEffects,
+            &Health,
+            &mut Coordinate,
+            &mut EnemyPath,
+        ),
+        With<Enemy>,
+    >,
+    map_root_query: Query<&MapRoot>,
+    map: Res<Map>,
+    mut commands: Commands,
+) {
+    enemy_query
+        .iter_mut()
+        .filter(|(_, _, _, health, ..)| health.current > 0.0)
+        .for_each(|(e, speed, status, _, mut coord, mut path)| {
+            // Diagonal steps are longer, so they take longer to walk.
+            let step_length = path
+                .route
+                .front()
+                .map_or(1.0, |&next| Vec2::from(*coord).distance(Vec2::from(next)));
+            let speed = speed.0 * status.speed_multiplier() / step_length.max(1.0);
+            path.progress += seconds_rate_to_fixed_rate(speed, FIXED_STEP_MS);
+
+            while path.progress >= 1.0 && !path.route.is_empty() {
+                *coord = path.route.pop_front().unwrap();
+                path.progress -= 1.0;
+            }
+
+            if path.route.is_empty() {
+                path.progress = 0.0;
+
+                if *coord == map.wave_exit_coord {
+                    let exit_tile = map_root_query
+                        .get_single()
+                        .ok()
+                        .and_then(|root| root.tile_entities.get(map.coord_to_idx(*coord)));
+
+                    if let Some(&exit_tile) = exit_tile {
+                        commands
+                            .spawn()
+                            .insert(Message)
+                            .insert(Leaked)
+                            .insert(Source(e))
+                            .insert(Target(exit_tile));
+                    }
+
+                    // The enemy is done walking; it'll be cleaned up once the message is handled
+                    commands.entity(e).remove::<EnemyPath>();
+                }
+            }
+        });
+}
+
+/// Re-plan the route of any enemy whose remaining route passes by a tile that has changed or
+/// no longer leads to the exit portal.
+pub fn replan_enemy_paths(
+    mut enemy_query: Query<(&MovementClass, &mut Coordinate, &mut EnemyPath), With<Enemy>>,
+    map: Res<Map>,
+    flow_fields: Res<FlowFields>,
+) {
+    if !map.is_changed() {
+        return;
+    }
+
+    let changed_coords: Vec<Coordinate> = map
+        .dirty_tiles
+        .iter()
+        .map(|&idx| map.idx_to_coord(idx))
+        .collect();
+
+    enemy_query
+        .iter_mut()
+        .for_each(|(class, mut coord, mut path)| {
+            let destination = path.route.back().unwrap_or(&coord);
+            let affected = *destination != map.wave_exit_coord
+                || std::iter::once(&*coord)
+                    .chain(path.route.iter())
+                    .any(|tile| {
+                        changed_coords.iter().any(|&changed| {
+                            map.movement_rules.neighbourhood.distance(*tile, changed)
+                                <= REPLAN_DISTANCE
+                        })
+                    });
+
+            if let (true, Some(field)) = (affected, flow_fields.get(*class)) {
+                replan(field, &mut coord, &mut path);
+            }
+        });
+}
+
+/// Find a new route to the exit for an enemy that may be part way between two tiles, following
+/// the flow field for its movement class.
+pub fn replan(field: &FlowField, coord: &mut Coordinate, path: &mut EnemyPath) {
+    if let Some((new_route, _)) = field.path_from(*coord) {
+        let new_next = new_route.get(1).copied();
+
+        match path.route.front().copied() {
+            Some(old_next) if path.progress > 0.0 && Some(old_next) != new_next => {
+                // The enemy is heading the wrong way, so turn it around and walk back to the
+                // tile it came from before following the new route.
+                let came_from = *coord;
+                *coord = old_next;
+                path.progress = 1.0 - path.progress;
+                path.route = std::iter::once(came_from)
+                    .chain(new_route.into_iter().skip(1))
+                    .collect();
+            }
+            _ => {
+                path.route = new_route.into_iter().skip(1).collect();
+            }
+        }
+    } else {
+        warn!("Enemy at {coord} has no route to the exit. Keeping its old route.");
+    }
+}
+
+fn handle_leaked_messages(
+    message_query: Query<(Entity, &Source), (With<Message>, With<Leaked>, Without<Handled>)>,
+    mut leaks: ResMut<Leaks>,
+    mut commands: Commands,
+) {
+    message_query.iter().for_each(|(message_entity, source)| {
+        leaks.0 += 1;
+        info!("An enemy reached the exit! Total leaks: {}", leaks.0);
+
+        commands.entity(source.0).despawn_recursive();
+        commands.entity(message_entity).insert(Handled);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enemies_turn_around_when_their_route_changes() {
+        let mut map = Map::new((3, 3));
+        map.wave_entry_coord = (0, 1).into();
+        map.wave_exit_coord = (2, 1).into();
+
+        let mut coord = map.wave_entry_coord;
+        let mut field = FlowField::new(&map, MovementClass::Walker);
+        let mut path = EnemyPath::from_route(&field.path_from(coord).unwrap().0);
+        assert_eq!(path.route.front(), Some(&Coordinate::from((1, 1))));
+
+        // Step part way onto the middle tile, then block it.
+        path.progress = 0.25;
+        map.set_tile((1, 1).into(), None, Some(Structure::Barricade));
+        field.update(&map, &map.dirty_tiles);
+        replan(&field, &mut coord, &mut path);
+
+        assert_eq!(coord, Coordinate::from((1, 1)));
+        assert_eq!(path.route.front(), Some(&Coordinate::from((0, 1))));
+        assert_eq!(path.route.back(), Some(&map.wave_exit_coord));
+        assert!((path.progress - 0.75).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn enemy_position_interpolates_between_tiles() {
+        let mut path = EnemyPath::from_route(&[(0, 0).into(), (1, 0).into(), (1, 1).into()]);
+        path.progress = 0.5;
+
+        assert_eq!(path.map_position(Coordinate::ZERO), Vec2::new(0.5, 0.0));
+    }
+}
@@ -0,0 +1,448 @@
+//! Status effects elements have on enemies
+//!
+//! Enemies pick up elements the same way tiles do, through `ApplyElementMessage`. Once an element
+//! builds up past its threshold in `STATUS_RULES`, the enemy gains that rule's status effect, and
+//! the effect grows stronger the more of the element the enemy carries.
+//!
+//! Rules for the same effect add their strengths together before the effect's cap is applied, so
+//! Water and Ice slowing an enemy at once can never stop it outright. Different effects change
+//! different stats and so never interfere with each other.
+
+use super::*;
+
+/// A status effect an enemy can be under.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatusEffect {
+    /// Loses health every second
+    Burning,
+    /// Moves slower
+    Slowed,
+    /// Takes less damage from towers
+    Armoured,
+    /// Gets pushed back a tile along its route
+    KnockedBack,
+}
+
+impl StatusEffect {
+    pub fn all() -> [Self; 4] {
+        [
+            Self::Burning,
+            Self::Slowed,
+            Self::Armoured,
+            Self::KnockedBack,
+        ]
+    }
+
+    fn display_name(&self) -> &str {
+        match *self {
+            Self::Burning => "Burning",
+            Self::Slowed => "Slowed",
+            Self::Armoured => "Armoured",
+            Self::KnockedBack => "Knocked Back",
+        }
+    }
+
+    /// The most strength the effect can reach, however many rules feed into it.
+    ///
+    /// Burning is in damage per second, Slowed and Armoured are the fraction of speed lost and
+    /// damage blocked, and Knocked Back is either on or off.
+    pub fn cap(&self) -> f32 {
+        match *self {
+            Self::Burning => 40.0,
+            Self::Slowed => 0.75,
+            Self::Armoured => 0.6,
+            Self::KnockedBack => 1.0,
+        }
+    }
+}
+
+impl std::fmt::Display for StatusEffect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.display_name())
+    }
+}
+
+/// One line of the status effect table.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StatusRule {
+    pub element: Element,
+    /// How much of the element an enemy needs before the effect kicks in
+    pub threshold: u32,
+    pub effect: StatusEffect,
+    /// Strength added to the effect for every point of the element the enemy carries
+    pub strength_per_point: f32,
+}
+
+/// Every element that has an effect on enemies.
+pub const STATUS_RULES: [StatusRule; 5] = [
+    StatusRule {
+        element: Element::Fire,
+        threshold: 10,
+        effect: StatusEffect::Burning,
+        strength_per_point: 0.5,
+    },
+    StatusRule {
+        element: Element::Water,
+        threshold: 10,
+        effect: StatusEffect::Slowed,
+        strength_per_point: 0.01,
+    },
+    StatusRule {
+        element: Element::Ice,
+        threshold: 10,
+        effect: StatusEffect::Slowed,
+        strength_per_point: 0.02,
+    },
+    StatusRule {
+        element: Element::Earth,
+        threshold: 10,
+        effect: StatusEffect::Armoured,
+        strength_per_point: 0.01,
+    },
+    // Each knockback uses up the threshold's worth of Air, so it happens once per 20 Air.
+    StatusRule {
+        element: Element::Air,
+        threshold: 20,
+        effect: StatusEffect::KnockedBack,
+        strength_per_point: 1.0,
+    },
+];
+
+/// The status effects an enemy is currently under and how strong each one is.
+#[derive(Component, Debug, Clone, Copy, Default, PartialEq)]
+pub struct StatusEffects {
+    /// Indexed by `StatusEffect`
+    strengths: [f32; 4],
+}
+
+impl StatusEffects {
+    pub fn from_affliction(affliction: &ElementalAffliction) -> Self {
+        let mut strengths = [0.0; 4];
+        STATUS_RULES
+            .iter()
+            .filter(|rule| affliction.get_element_amount(rule.element) >= rule.threshold)
+            .for_each(|rule| {
+                strengths[rule.effect as usize] +=
+                    affliction.get_element_amount(rule.element) as f32 * rule.strength_per_point;
+            });
+
+        StatusEffect::all().into_iter().for_each(|effect| {
+            strengths[effect as usize] = strengths[effect as usize].min(effect.cap());
+        });
+
+        Self { strengths }
+    }
+
+    pub fn strength(&self, effect: StatusEffect) -> f32 {
+        self.strengths[effect as usize]
+    }
+
+    /// The effects with any strength, along with their strength.
+    pub fn active(&self) -> impl Iterator<Item = (StatusEffect, f32)> + '_ {
+        StatusEffect::all()
+            .into_iter()
+            .map(|effect| (effect, self.strength(effect)))
+            .filter(|(_, strength)| *strength > 0.0)
+    }
+
+    pub fn damage_per_second(&self) -> f32 {
+        self.strength(StatusEffect::Burning)
+    }
+
+    /// What the enemy's move speed is multiplied by.
+    pub fn speed_multiplier(&self) -> f32 {
+        1.0 - self.strength(StatusEffect::Slowed)
+    }
+
+    /// What damage dealt to the enemy is multiplied by.
+    pub fn damage_taken_multiplier(&self) -> f32 {
+        1.0 - self.strength(StatusEffect::Armoured)
+    }
+
+    pub fn knocked_back(&self) -> bool {
+        self.strength(StatusEffect::KnockedBack) > 0.0
+    }
+}
+
+impl std::fmt::Display for StatusEffects {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut active = self.active().peekable();
+        if active.peek().is_none() {
+            return write!(f, "None");
+        }
+
+        for (effect, strength) in active {
+            match effect {
+                StatusEffect::Burning => writeln!(f, "{effect}: {strength:.1} damage per second")?,
+                StatusEffect::Slowed | StatusEffect::Armoured => {
+                    writeln!(f, "{effect}: {:.0}%", strength * 100.0)?;
+                }
+                StatusEffect::KnockedBack => writeln!(f, "{effect}")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Keep every enemy's status effects in line with the elements on it.
+pub fn update_status_effects(
+    mut enemy_query: Query<
+        (&ElementalAffliction, &mut StatusEffects),
+        (With<Enemy>, Changed<ElementalAffliction>),
+    >,
+) {
+    enemy_query.iter_mut().for_each(|(affliction, mut status)| {
+        *status = StatusEffects::from_affliction(affliction);
+    });
+}
+
+/// Burn enemies and knock them back. Enemies burnt to death send a `Died` message with no
+/// Source, since nothing in particular killed them.
+pub fn apply_status_effects(
+    mut enemy_query: Query<
+        (
+            Entity,
+            &ElementalAffliction,
+            &MovementClass,
+            &mut StatusEffects,
+            &mut Health,
+            &mut Coordinate,
+            &mut EnemyPath,
+        ),
+        With<Enemy>,
+    >,
+    map: Res<Map>,
+    mut commands: Commands,
+) {
+    let step = seconds_rate_to_fixed_rate(1.0, FIXED_STEP_MS);
+
+    enemy_query.iter_mut().for_each(
+        |(e, affliction, class, mut status, mut health, mut coord, mut path)| {
+            if status.damage_per_second() > 0.0 && health.current > 0.0 {
+                health.current -= status.damage_per_second() * step;
+                if health.current <= 0.0 {
+                    commands
+                        .spawn()
+                        .insert(Message)
+                        .insert(Target(e))
+                        .insert(Died {
+                            overkill: -health.current,
+                        });
+                }
+            }
+
+            if status.knocked_back() {
+                knock_back(&map, *class, &mut coord, &mut path);
+
+                // Use up the elements that caused the knockback. The effect stays off until
+                // the elements are recounted so the enemy isn't knocked back every step.
+                let mut used = ElementalAffliction::empty();
+                STATUS_RULES
+                    .iter()
+                    .filter(|rule| rule.effect == StatusEffect::KnockedBack)
+                    .filter(|rule| affliction.get_element_amount(rule.element) >= rule.threshold)
+                    .for_each(|rule| used.add_element(rule.element, rule.threshold));
+                commands
+                    .spawn()
+                    .insert(Message)
+                    .insert(RemoveElements)
+                    .insert(Target(e))
+                    .insert(used);
+
+                status.strengths[StatusEffect::KnockedBack as usize] = 0.0;
+            }
+        },
+    );
+}
+
+/// Push an enemy one tile back the way it's walking from.
+///
+/// If the enemy can't step onto the tile behind it, it's only pushed back to the start of the
+/// tile it's leaving.
+pub fn knock_back(map: &Map, class: MovementClass, coord: &mut Coordinate, path: &mut EnemyPath) {
+    if let Some(&next) = path.route.front() {
+        let behind = next
+            .direction_to(coord)
+            .and_then(|direction| map.step_cost(*coord, direction, class));
+
+        match behind {
+            Some((behind, _)) => {
+                path.route.push_front(*coord);
+                *coord = behind;
+            }
+            None => path.progress = 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A grunt at the start of a straight corridor, with the systems that move, burn and
+    /// recount the statuses of enemies.
+    fn corridor_grunt() -> (World, SystemStage, Entity) {
+        let mut map = Map::new((8, 1));
+        map.wave_entry_coord = (0, 0).into();
+        map.wave_exit_coord = (7, 0).into();
+
+        let mut flow_fields = FlowFields::default();
+        flow_fields.update(&map);
+
+        let mut world = World::new();
+        let enemy = world
+            .spawn()
+            .insert_bundle(EnemyBundle::at_wave_entry(
+                &map,
+                &flow_fields,
+                EnemyKind::Grunt,
+            ))
+            .id();
+        world.insert_resource(map);
+
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(update_status_effects);
+        stage.add_system(move_enemies.after(update_status_effects));
+        stage.add_system(apply_status_effects.after(move_enemies));
+
+        (world, stage, enemy)
+    }
+
+    /// How far along the corridor the enemy is.
+    fn distance_walked(world: &World, enemy: Entity) -> f32 {
+        let coord = world.get::<Coordinate>(enemy).unwrap();
+        let path = world.get::<EnemyPath>(enemy).unwrap();
+
+        path.map_position(*coord).x
+    }
+
+    #[test]
+    fn water_slows_enemies_down() {
+        let (mut world, mut stage, enemy) = corridor_grunt();
+        let steps = 1000 / FIXED_STEP_MS;
+
+        for _ in 0..steps {
+            stage.run(&mut world);
+        }
+        let unaffected = distance_walked(&world, enemy);
+        assert!((unaffected - 1.0).abs() < 0.01);
+
+        world
+            .entity_mut(enemy)
+            .insert(ElementalAffliction::single(Element::Water, 40));
+        for _ in 0..steps {
+            stage.run(&mut world);
+        }
+        let slowed = distance_walked(&world, enemy) - unaffected;
+        assert!((slowed - 0.6).abs() < 0.01);
+
+        // Ice adds to the slow but can't push it past the cap.
+        let mut frozen = ElementalAffliction::single(Element::Water, 40);
+        frozen.add_element(Element::Ice, 100);
+        world.entity_mut(enemy).insert(frozen);
+        stage.run(&mut world);
+        assert!(
+            (world
+                .get::<StatusEffects>(enemy)
+                .unwrap()
+                .speed_multiplier()
+                - (1.0 - StatusEffect::Slowed.cap()))
+            .abs()
+                < f32::EPSILON
+        );
+    }
+
+    #[test]
+    fn fire_burns_enemies_past_its_threshold() {
+        let (mut world, mut stage, enemy) = corridor_grunt();
+
+        // Below the threshold nothing happens.
+        world
+            .entity_mut(enemy)
+            .insert(ElementalAffliction::single(Element::Fire, 5));
+        for _ in 0..(1000 / FIXED_STEP_MS) {
+            stage.run(&mut world);
+        }
+        assert!((world.get::<Health>(enemy).unwrap().current - 100.0).abs() < f32::EPSILON);
+
+        world
+            .entity_mut(enemy)
+            .insert(ElementalAffliction::single(Element::Fire, 20));
+        for _ in 0..(1000 / FIXED_STEP_MS) {
+            stage.run(&mut world);
+        }
+        assert!((world.get::<Health>(enemy).unwrap().current - 90.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn enemies_burnt_to_death_are_cleaned_up() {
+        let (mut world, _, enemy) = corridor_grunt();
+        world.insert_resource(CurrentState(GameState::TDMode));
+        world.insert_resource(MessageStats::default());
+        world.insert_resource(DamageTotals::default());
+        world.insert_resource(Leaks(0));
+
+        // The bus runs before the enemies' own stage, like it does in the game.
+        let mut schedule = Schedule::default();
+        schedule
+            .add_stage(MESSAGE_STAGE, message_bus())
+            .add_stage(
+                ENEMY_FIXED_STAGE,
+                SystemStage::single_threaded()
+                    .with_system(update_status_effects)
+                    .with_system(move_enemies.after(update_status_effects))
+                    .with_system(apply_status_effects.after(move_enemies)),
+            )
+            .add_message(MessageKind::<Leaked>::handled_by(handle_leaked_messages));
+        add_damage_messages(&mut schedule);
+
+        // Burning at full strength kills a grunt in two and a half seconds, well before it can
+        // walk the corridor.
+        world
+            .entity_mut(enemy)
+            .insert(ElementalAffliction::single(Element::Fire, 100));
+        for _ in 0..(3000 / FIXED_STEP_MS) {
+            schedule.run(&mut world);
+        }
+
+        assert!(world.get_entity(enemy).is_none());
+        assert_eq!(world.query::<&Message>().iter(&world).count(), 0);
+        assert_eq!(world.resource::<Leaks>().0, 0);
+        assert_no_leaked_messages(&world);
+    }
+
+    #[test]
+    fn earth_armours_enemies() {
+        let status =
+            StatusEffects::from_affliction(&ElementalAffliction::single(Element::Earth, 30));
+        assert!((status.damage_taken_multiplier() - 0.7).abs() < f32::EPSILON);
+        assert!((status.speed_multiplier() - 1.0).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn air_knocks_enemies_back_a_tile() {
+        let (mut world, mut stage, enemy) = corridor_grunt();
+        for _ in 0..(2500 / FIXED_STEP_MS) {
+            stage.run(&mut world);
+        }
+        let before = distance_walked(&world, enemy);
+
+        world
+            .entity_mut(enemy)
+            .insert(ElementalAffliction::single(Element::Air, 25));
+        stage.run(&mut world);
+        let after = distance_walked(&world, enemy);
+        assert!((before - after - 1.0).abs() < 0.05);
+
+        // The knockback only happens once, and uses up the Air that caused it.
+        stage.run(&mut world);
+        assert!(distance_walked(&world, enemy) > after);
+        let removed = world
+            .query_filtered::<&ElementalAffliction, With<RemoveElements>>()
+            .iter(&world)
+            .next()
+            .unwrap();
+        assert_eq!(removed.get_element_amount(Element::Air), 20);
+    }
+}
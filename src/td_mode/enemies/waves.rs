@@ -0,0 +1,558 @@
+//! Wave schedules and the spawner that sends enemies out of the entry portal
+//!
+//! A schedule is a list of waves, and each wave is made of groups of a single kind of enemy.
+//! Schedules are stored as JSON next to the map they belong to, so `maps/castle.json` keeps its
+//! waves in `maps/castle.waves.json`:
+//!
+//! ```json
+//! {
+//!     "format_version": 1,
+//!     "time_between_waves": 10.0,
+//!     "waves": [
+//!         { "groups": [ { "enemy": "Grunt", "count": 5, "interval": 1.0, "delay": 0.0 } ] }
+//!     ]
+//! }
+//! ```
+
+use super::*;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+
+/// The version written to new wave files. Bump this whenever the layout changes.
+pub const WAVE_FORMAT_VERSION: u64 = 1;
+
+/// A batch of enemies of the same kind, spawned one after another.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpawnGroup {
+    pub kind: EnemyKind,
+    pub count: u32,
+    /// Seconds between each enemy in the group
+    pub interval: f32,
+    /// Seconds after the start of the wave before the first enemy of the group spawns
+    pub delay: f32,
+}
+
+impl SpawnGroup {
+    /// How many enemies of this group should have spawned `elapsed` seconds into the wave.
+    fn spawned_by(&self, elapsed: f32) -> u32 {
+        if elapsed < self.delay {
+            0
+        } else if self.interval <= 0.0 {
+            self.count
+        } else {
+            // Float to int conversions saturate, so huge waits just mean the whole group.
+            let intervals_passed = ((elapsed - self.delay) / self.interval).floor() as u32;
+
+            self.count.min(intervals_passed.saturating_add(1))
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Wave {
+    pub groups: Vec<SpawnGroup>,
+}
+
+impl Wave {
+    pub fn enemy_count(&self) -> u32 {
+        self.groups.iter().map(|g| g.count).sum()
+    }
+}
+
+/// Resource listing every wave on the current map, in order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WaveSchedule {
+    pub waves: Vec<Wave>,
+    /// Seconds between one wave finishing spawning and the next one starting
+    pub time_between_waves: f32,
+}
+
+impl WaveSchedule {
+    /// A single wave of grunts, used until a schedule is loaded.
+    pub fn starter() -> Self {
+        Self {
+            waves: vec![Wave {
+                groups: vec![SpawnGroup {
+                    kind: EnemyKind::Grunt,
+                    count: 5,
+                    interval: 1.0,
+                    delay: 0.0,
+                }],
+            }],
+            time_between_waves: 10.0,
+        }
+    }
+}
+
+/// Resource tracking the progress of the wave currently being spawned.
+#[derive(Debug, Default)]
+pub struct WaveState {
+    /// The index of the wave being spawned, or the last one to have spawned.
+    pub current_wave: Option<usize>,
+    /// Seconds since the current wave started
+    pub elapsed: f32,
+    /// Seconds until the next wave starts on its own, if one is queued up.
+    pub time_to_next_wave: Option<f32>,
+    /// Enemies in the current wave that haven't spawned yet plus the ones still alive.
+    pub enemies_remaining: u32,
+    /// How many enemies of each group in the current wave have been spawned.
+    spawned: Vec<u32>,
+    /// The groups `spawned` counts, so edits to the current wave can be noticed.
+    counted_groups: Vec<SpawnGroup>,
+}
+
+impl WaveState {
+    /// The wave that will be started by `start_next_wave`, if there is one.
+    pub fn next_wave(&self, schedule: &WaveSchedule) -> Option<usize> {
+        let next = self.current_wave.map_or(0, |w| w + 1);
+
+        if next < schedule.waves.len() {
+            Some(next)
+        } else {
+            None
+        }
+    }
+
+    pub fn start_wave(&mut self, schedule: &WaveSchedule, wave: usize) {
+        self.current_wave = Some(wave);
+        self.elapsed = 0.0;
+        self.time_to_next_wave = None;
+        self.spawned = vec![0; schedule.waves[wave].groups.len()];
+        self.counted_groups.clone_from(&schedule.waves[wave].groups);
+    }
+
+    /// Starts the next wave right away, skipping any countdown.
+    pub fn start_next_wave(&mut self, schedule: &WaveSchedule) {
+        if let Some(next) = self.next_wave(schedule) {
+            self.start_wave(schedule, next);
+        }
+    }
+
+    pub fn is_spawning(&self, schedule: &WaveSchedule) -> bool {
+        match self.current_wave.and_then(|w| schedule.waves.get(w)) {
+            Some(wave) => wave
+                .groups
+                .iter()
+                .zip(self.spawned.iter())
+                .any(|(group, &spawned)| spawned < group.count),
+            None => false,
+        }
+    }
+
+    fn unspawned_enemies(&self, schedule: &WaveSchedule) -> u32 {
+        self.current_wave
+            .and_then(|w| schedule.waves.get(w))
+            .map_or(0, |wave| {
+                wave.groups
+                    .iter()
+                    .zip(self.spawned.iter())
+                    .map(|(group, &spawned)| group.count.saturating_sub(spawned))
+                    .sum()
+            })
+    }
+
+    /// Advance the wave by `seconds`, returning the enemies that should spawn.
+    pub fn tick(&mut self, schedule: &WaveSchedule, seconds: f32) -> Vec<EnemyKind> {
+        let mut to_spawn = Vec::new();
+
+        if let Some(wave) = self.current_wave.and_then(|w| schedule.waves.get(w)) {
+            if self.counted_groups != wave.groups {
+                // The schedule was edited mid-wave, so the old counts may belong to other groups.
+                // Count every group as having spawned whatever its timer says is due, so the edit
+                // doesn't send out a burst of enemies.
+                self.spawned = wave
+                    .groups
+                    .iter()
+                    .map(|group| group.spawned_by(self.elapsed))
+                    .collect();
+                self.counted_groups.clone_from(&wave.groups);
+            }
+
+            if self.is_spawning(schedule) {
+                self.elapsed += seconds;
+
+                wave.groups
+                    .iter()
+                    .zip(self.spawned.iter_mut())
+                    .for_each(|(group, spawned)| {
+                        let due = group.spawned_by(self.elapsed);
+                        while *spawned < due {
+                            to_spawn.push(group.kind);
+                            *spawned += 1;
+                        }
+                    });
+
+                if !self.is_spawning(schedule) && self.next_wave(schedule).is_some() {
+                    self.time_to_next_wave = Some(schedule.time_between_waves);
+                }
+
+                return to_spawn;
+            }
+        }
+
+        if let Some(time_left) = self.time_to_next_wave {
+            let time_left = time_left - seconds;
+
+            if time_left <= 0.0 {
+                self.start_next_wave(schedule);
+            } else {
+                self.time_to_next_wave = Some(time_left);
+            }
+        }
+
+        to_spawn
+    }
+}
+
+/// Spawn enemies at the wave entry portal as the current wave calls for them.
+pub fn spawn_wave_enemies(
+    enemy_query: Query<(), With<Enemy>>,
+    map_root_query: Query<Entity, With<MapRoot>>,
+    schedule: Res<WaveSchedule>,
+    mut state: ResMut<WaveState>,
+    map: Res<Map>,
+    flow_fields: Res<FlowFields>,
+    mut commands: Commands,
+) {
+    let to_spawn = state.tick(&schedule, seconds_rate_to_fixed_rate(1.0, FIXED_STEP_MS));
+
+    if let Ok(map_root) = map_root_query.get_single() {
+        to_spawn.iter().for_each(|&kind| {
+            spawn_enemy(
+                &mut commands,
+                map_root,
+                EnemyBundle::at_wave_entry(&map, &flow_fields, kind),
+            );
+        });
+    }
+
+    let alive = enemy_query.iter().count() + to_spawn.len();
+    state.enemies_remaining = state.unspawned_enemies(&schedule) + alive as u32;
+}
+
+/// Everything that can go wrong reading or writing a wave file.
+#[derive(Debug)]
+pub enum WaveFileError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    UnsupportedVersion(u64),
+    MissingField(&'static str),
+    InvalidTimeBetweenWaves(f64),
+    InvalidWave {
+        wave: usize,
+        reason: &'static str,
+    },
+    InvalidGroup {
+        wave: usize,
+        group: usize,
+        reason: &'static str,
+    },
+    UnknownEnemy {
+        wave: usize,
+        group: usize,
+        name: String,
+    },
+}
+
+impl std::fmt::Display for WaveFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "Could not access wave file: {e}"),
+            Self::Json(e) => write!(f, "Wave file is not valid JSON: {e}"),
+            Self::UnsupportedVersion(v) => write!(
+                f,
+                "Wave format version {v} is not supported (expected {WAVE_FORMAT_VERSION})"
+            ),
+            Self::MissingField(field) => write!(f, "Wave file is missing the field '{field}'"),
+            Self::InvalidTimeBetweenWaves(t) => write!(
+                f,
+                "'time_between_waves' must be a positive number of seconds, not {t}"
+            ),
+            Self::InvalidWave { wave, reason } => write!(f, "Wave {wave}: {reason}"),
+            Self::InvalidGroup {
+                wave,
+                group,
+                reason,
+            } => write!(f, "Wave {wave}, group {group}: {reason}"),
+            Self::UnknownEnemy { wave, group, name } => {
+                write!(f, "Wave {wave}, group {group}: unknown enemy '{name}'")
+            }
+        }
+    }
+}
+
+impl std::error::Error for WaveFileError {}
+
+impl From<std::io::Error> for WaveFileError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for WaveFileError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+/// The path of the wave file that belongs to a map file.
+pub fn wave_file_path(map_path: impl AsRef<Path>) -> PathBuf {
+    map_path.as_ref().with_extension("waves.json")
+}
+
+pub fn save_waves(path: impl AsRef<Path>, schedule: &WaveSchedule) -> Result<(), WaveFileError> {
+    let path = path.as_ref();
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let contents = serde_json::to_string_pretty(&waves_to_json(schedule))?;
+    std::fs::write(path, contents)?;
+
+    Ok(())
+}
+
+pub fn load_waves(path: impl AsRef<Path>) -> Result<WaveSchedule, WaveFileError> {
+    let contents = std::fs::read_to_string(path)?;
+    let value: Value = serde_json::from_str(&contents)?;
+
+    waves_from_json(&value)
+}
+
+pub fn waves_to_json(schedule: &WaveSchedule) -> Value {
+    let waves: Vec<Value> = schedule
+        .waves
+        .iter()
+        .map(|wave| {
+            let groups: Vec<Value> = wave
+                .groups
+                .iter()
+                .map(|group| {
+                    json!({
+                        "enemy": group.kind.to_string(),
+                        "count": group.count,
+                        "interval": group.interval,
+                        "delay": group.delay,
+                    })
+                })
+                .collect();
+
+            json!({ "groups": groups })
+        })
+        .collect();
+
+    json!({
+        "format_version": WAVE_FORMAT_VERSION,
+        "time_between_waves": schedule.time_between_waves,
+        "waves": waves,
+    })
+}
+
+pub fn waves_from_json(value: &Value) -> Result<WaveSchedule, WaveFileError> {
+    let version = value
+        .get("format_version")
+        .and_then(Value::as_u64)
+        .ok_or(WaveFileError::MissingField("format_version"))?;
+    if version != WAVE_FORMAT_VERSION {
+        return Err(WaveFileError::UnsupportedVersion(version));
+    }
+
+    let time_between_waves = value
+        .get("time_between_waves")
+        .and_then(Value::as_f64)
+        .ok_or(WaveFileError::MissingField("time_between_waves"))?;
+    if time_between_waves < 0.0 {
+        return Err(WaveFileError::InvalidTimeBetweenWaves(time_between_waves));
+    }
+
+    let waves = value
+        .get("waves")
+        .and_then(Value::as_array)
+        .ok_or(WaveFileError::MissingField("waves"))?
+        .iter()
+        .enumerate()
+        .map(|(wave_idx, wave)| {
+            let groups =
+                wave.get("groups")
+                    .and_then(Value::as_array)
+                    .ok_or(WaveFileError::InvalidWave {
+                        wave: wave_idx,
+                        reason: "missing 'groups'",
+                    })?;
+
+            let groups = groups
+                .iter()
+                .enumerate()
+                .map(|(group_idx, group)| group_from_json(wave_idx, group_idx, group))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            Ok(Wave { groups })
+        })
+        .collect::<Result<Vec<_>, WaveFileError>>()?;
+
+    Ok(WaveSchedule {
+        waves,
+        time_between_waves: time_between_waves as f32,
+    })
+}
+
+fn group_from_json(wave: usize, group: usize, value: &Value) -> Result<SpawnGroup, WaveFileError> {
+    let invalid = |reason| WaveFileError::InvalidGroup {
+        wave,
+        group,
+        reason,
+    };
+
+    let name = value
+        .get("enemy")
+        .and_then(Value::as_str)
+        .ok_or_else(|| invalid("missing 'enemy'"))?;
+    let kind = EnemyKind::from_name(name).ok_or_else(|| WaveFileError::UnknownEnemy {
+        wave,
+        group,
+        name: name.to_string(),
+    })?;
+
+    let count = value
+        .get("count")
+        .and_then(Value::as_u64)
+        .and_then(|c| u32::try_from(c).ok())
+        .ok_or_else(|| invalid("'count' must be a positive integer"))?;
+
+    let read_seconds = |field, reason| {
+        value
+            .get(field)
+            .map_or(Some(0.0), Value::as_f64)
+            .filter(|s| *s >= 0.0)
+            .ok_or_else(|| invalid(reason))
+    };
+    let interval = read_seconds("interval", "'interval' must be a positive number")?;
+    let delay = read_seconds("delay", "'delay' must be a positive number")?;
+
+    Ok(SpawnGroup {
+        kind,
+        count,
+        interval: interval as f32,
+        delay: delay as f32,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn two_wave_schedule() -> WaveSchedule {
+        WaveSchedule {
+            waves: vec![
+                Wave {
+                    groups: vec![
+                        SpawnGroup {
+                            kind: EnemyKind::Grunt,
+                            count: 3,
+                            interval: 1.0,
+                            delay: 0.0,
+                        },
+                        SpawnGroup {
+                            kind: EnemyKind::Brute,
+                            count: 1,
+                            interval: 0.0,
+                            delay: 1.5,
+                        },
+                    ],
+                },
+                Wave {
+                    groups: vec![SpawnGroup {
+                        kind: EnemyKind::Runner,
+                        count: 2,
+                        interval: 0.5,
+                        delay: 0.0,
+                    }],
+                },
+            ],
+            time_between_waves: 5.0,
+        }
+    }
+
+    #[test]
+    fn waves_spawn_groups_on_their_timers() {
+        let schedule = two_wave_schedule();
+        let mut state = WaveState::default();
+
+        // Nothing happens until a wave is started
+        assert!(state.tick(&schedule, 1.0).is_empty());
+
+        state.start_next_wave(&schedule);
+        assert_eq!(state.tick(&schedule, 0.5), vec![EnemyKind::Grunt]);
+        assert_eq!(state.tick(&schedule, 0.5), vec![EnemyKind::Grunt]);
+        assert_eq!(state.tick(&schedule, 0.5), vec![EnemyKind::Brute]);
+        assert_eq!(state.tick(&schedule, 0.5), vec![EnemyKind::Grunt]);
+
+        // The first wave is done, so the countdown to the second starts
+        assert!(!state.is_spawning(&schedule));
+        assert_eq!(state.time_to_next_wave, Some(5.0));
+
+        assert!(state.tick(&schedule, 4.0).is_empty());
+        assert!(state.tick(&schedule, 1.0).is_empty());
+        assert_eq!(state.current_wave, Some(1));
+        assert_eq!(
+            state.tick(&schedule, 0.5),
+            vec![EnemyKind::Runner, EnemyKind::Runner]
+        );
+
+        // There's no third wave to count down to
+        assert_eq!(state.time_to_next_wave, None);
+        assert_eq!(state.next_wave(&schedule), None);
+    }
+
+    #[test]
+    fn editing_the_current_wave_keeps_each_groups_count() {
+        let mut schedule = two_wave_schedule();
+        let mut state = WaveState::default();
+        state.start_next_wave(&schedule);
+        assert_eq!(state.tick(&schedule, 0.5), vec![EnemyKind::Grunt]);
+
+        // Removing the grunts mustn't hand their count to the brute.
+        schedule.waves[0].groups.remove(0);
+        assert!(state.tick(&schedule, 0.5).is_empty());
+        assert_eq!(state.tick(&schedule, 0.5), vec![EnemyKind::Brute]);
+        assert!(!state.is_spawning(&schedule));
+    }
+
+    #[test]
+    fn wave_file_round_trip() {
+        let schedule = two_wave_schedule();
+
+        let loaded = waves_from_json(&waves_to_json(&schedule)).unwrap();
+
+        assert_eq!(loaded, schedule);
+    }
+
+    #[test]
+    fn wave_file_errors_name_the_bad_group() {
+        let mut value = waves_to_json(&two_wave_schedule());
+        value["waves"][1]["groups"][0]["enemy"] = "Dragon".into();
+
+        match waves_from_json(&value) {
+            Err(WaveFileError::UnknownEnemy { wave, group, name }) => {
+                assert_eq!((wave, group), (1, 0));
+                assert_eq!(name, "Dragon");
+            }
+            other => panic!("Expected an unknown enemy error, got {other:?}"),
+        }
+
+        let mut value = waves_to_json(&two_wave_schedule());
+        value["time_between_waves"] = (-1.0).into();
+        assert!(matches!(
+            waves_from_json(&value),
+            Err(WaveFileError::InvalidTimeBetweenWaves(_))
+        ));
+    }
+
+    #[test]
+    fn wave_files_sit_next_to_their_maps() {
+        assert_eq!(
+            wave_file_path("assets/maps/castle.json"),
+            PathBuf::from("assets/maps/castle.waves.json")
+        );
+    }
+}
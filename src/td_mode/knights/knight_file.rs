@@ -0,0 +1,348 @@
+//! Reading the knight roster from disk
+//!
+//! Knights are stored as JSON in the following shape:
+//!
+//! ```json
+//! {
+//!     "format_version": 1,
+//!     "knights": [
+//!         {
+//!             "id": 0,
+//!             "name": "Normal Knight",
+//!             "affinity": "Fire",
+//!             "behaviours": {
+//!                 "Short": { "range": 1.0, "fire_rate": 1.0, "damage": 1.0 },
+//!                 "Tall": {
+//!                     "damage": 1.2,
+//!                     "upgrades": {
+//!                         "Power": [ { "name": "Heavier Shot", "cost": 150, "damage": 10.0 } ],
+//!                         "Speed": [ ... ]
+//!                     }
+//!                 }
+//!             }
+//!         }
+//!     ]
+//! }
+//! ```
+//!
+//! Tower types left out of `behaviours`, and modifiers left out of a behaviour, default to 1.0.
+//! A knight only needs `upgrades` for the tower types where they offer something different
+//! from the tower's usual upgrades, and an upgrade line left out uses the usual one. Upgrade
+//! tiers add any of `range`, `fire_rate`, `damage` and `element_amount` to the tower's stats.
+
+use super::super::towers::{StatDelta, UpgradeLine, UpgradeTier};
+use super::*;
+use serde_json::Value;
+use std::path::Path;
+
+/// The version of knight file this build understands.
+pub const KNIGHT_FORMAT_VERSION: u64 = 1;
+
+/// Everything that can go wrong reading a knight file.
+#[derive(Debug)]
+pub enum KnightFileError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    UnsupportedVersion(u64),
+    MissingField(&'static str),
+    InvalidKnight { index: usize, reason: &'static str },
+    DuplicateId { index: usize, id: KnightId },
+    UnknownElement { index: usize, name: String },
+    UnknownTowerType { index: usize, name: String },
+    UnknownUpgradeLine { index: usize, name: String },
+}
+
+impl std::fmt::Display for KnightFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "Could not access knight file: {e}"),
+            Self::Json(e) => write!(f, "Knight file is not valid JSON: {e}"),
+            Self::UnsupportedVersion(v) => write!(
+                f,
+                "Knight format version {v} is not supported (expected {KNIGHT_FORMAT_VERSION})"
+            ),
+            Self::MissingField(field) => write!(f, "Knight file is missing the field '{field}'"),
+            Self::InvalidKnight { index, reason } => write!(f, "Knight {index}: {reason}"),
+            Self::DuplicateId { index, id } => {
+                write!(f, "Knight {index}: id {} is already taken", id.0)
+            }
+            Self::UnknownElement { index, name } => {
+                write!(f, "Knight {index}: unknown element '{name}'")
+            }
+            Self::UnknownTowerType { index, name } => {
+                write!(f, "Knight {index}: unknown tower type '{name}'")
+            }
+            Self::UnknownUpgradeLine { index, name } => {
+                write!(f, "Knight {index}: unknown upgrade line '{name}'")
+            }
+        }
+    }
+}
+
+impl std::error::Error for KnightFileError {}
+
+impl From<std::io::Error> for KnightFileError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for KnightFileError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+pub fn load_knights(path: impl AsRef<Path>) -> Result<Vec<Knight>, KnightFileError> {
+    let contents = std::fs::read_to_string(path)?;
+    let value: Value = serde_json::from_str(&contents)?;
+
+    knights_from_json(&value)
+}
+
+pub fn knights_from_json(value: &Value) -> Result<Vec<Knight>, KnightFileError> {
+    let version = value
+        .get("format_version")
+        .and_then(Value::as_u64)
+        .ok_or(KnightFileError::MissingField("format_version"))?;
+    if version != KNIGHT_FORMAT_VERSION {
+        return Err(KnightFileError::UnsupportedVersion(version));
+    }
+
+    let knights = value
+        .get("knights")
+        .and_then(Value::as_array)
+        .ok_or(KnightFileError::MissingField("knights"))?
+        .iter()
+        .enumerate()
+        .map(|(index, knight)| knight_from_json(index, knight))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for (index, knight) in knights.iter().enumerate() {
+        if knights[..index].iter().any(|k| k.id == knight.id) {
+            return Err(KnightFileError::DuplicateId {
+                index,
+                id: knight.id,
+            });
+        }
+    }
+
+    Ok(knights)
+}
+
+fn knight_from_json(index: usize, knight: &Value) -> Result<Knight, KnightFileError> {
+    let id = knight
+        .get("id")
+        .and_then(Value::as_u64)
+        .and_then(|id| u32::try_from(id).ok())
+        .ok_or(KnightFileError::InvalidKnight {
+            index,
+            reason: "missing 'id'",
+        })?;
+
+    let name =
+        knight
+            .get("name")
+            .and_then(Value::as_str)
+            .ok_or(KnightFileError::InvalidKnight {
+                index,
+                reason: "missing 'name'",
+            })?;
+
+    let affinity_name =
+        knight
+            .get("affinity")
+            .and_then(Value::as_str)
+            .ok_or(KnightFileError::InvalidKnight {
+                index,
+                reason: "missing 'affinity'",
+            })?;
+    let affinity =
+        Element::from_name(affinity_name).ok_or_else(|| KnightFileError::UnknownElement {
+            index,
+            name: affinity_name.to_string(),
+        })?;
+
+    let mut behaviours = [KnightBehaviour::default(); 3];
+    let mut upgrades: [Option<UpgradePath>; 3] = Default::default();
+    match knight.get("behaviours") {
+        None => {}
+        Some(Value::Object(entries)) => {
+            for (tower_name, behaviour) in entries {
+                let tower_type = TowerType::from_name(tower_name).ok_or_else(|| {
+                    KnightFileError::UnknownTowerType {
+                        index,
+                        name: tower_name.clone(),
+                    }
+                })?;
+
+                let modifier = |field: &str| match behaviour.get(field) {
+                    None => Ok(1.0),
+                    Some(v) => v
+                        .as_f64()
+                        .map(|m| m as f32)
+                        .ok_or(KnightFileError::InvalidKnight {
+                            index,
+                            reason: "behaviour modifiers must be numbers",
+                        }),
+                };
+
+                behaviours[tower_type as usize] = KnightBehaviour {
+                    range: modifier("range")?,
+                    fire_rate: modifier("fire_rate")?,
+                    damage: modifier("damage")?,
+                };
+
+                if let Some(paths) = behaviour.get("upgrades") {
+                    upgrades[tower_type as usize] =
+                        Some(upgrade_path_from_json(index, tower_type, paths)?);
+                }
+            }
+        }
+        Some(_) => {
+            return Err(KnightFileError::InvalidKnight {
+                index,
+                reason: "'behaviours' must be an object",
+            })
+        }
+    }
+
+    Ok(Knight {
+        id: KnightId(id),
+        name: name.to_string(),
+        affinity,
+        behaviours,
+        upgrades,
+    })
+}
+
+fn upgrade_path_from_json(
+    index: usize,
+    tower_type: TowerType,
+    paths: &Value,
+) -> Result<UpgradePath, KnightFileError> {
+    let paths = paths.as_object().ok_or(KnightFileError::InvalidKnight {
+        index,
+        reason: "'upgrades' must be an object",
+    })?;
+
+    let mut path = UpgradePath::default_for(tower_type);
+    for (line_name, tiers) in paths {
+        let line = UpgradeLine::from_name(line_name).ok_or_else(|| {
+            KnightFileError::UnknownUpgradeLine {
+                index,
+                name: line_name.clone(),
+            }
+        })?;
+
+        let tiers = tiers
+            .as_array()
+            .ok_or(KnightFileError::InvalidKnight {
+                index,
+                reason: "upgrade lines must be lists of tiers",
+            })?
+            .iter()
+            .map(|tier| upgrade_tier_from_json(index, tier))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        match line {
+            UpgradeLine::Power => path.power = tiers,
+            UpgradeLine::Speed => path.speed = tiers,
+        }
+    }
+
+    Ok(path)
+}
+
+fn upgrade_tier_from_json(index: usize, tier: &Value) -> Result<UpgradeTier, KnightFileError> {
+    let invalid = |reason| KnightFileError::InvalidKnight { index, reason };
+
+    let name = tier
+        .get("name")
+        .and_then(Value::as_str)
+        .ok_or_else(|| invalid("upgrade tiers need a 'name'"))?;
+    let base_cost = tier
+        .get("cost")
+        .and_then(Value::as_u64)
+        .and_then(|c| u32::try_from(c).ok())
+        .ok_or_else(|| invalid("upgrade tiers need a positive integer 'cost'"))?;
+
+    let stat = |field: &str| match tier.get(field) {
+        None => Ok(0.0),
+        Some(v) => v
+            .as_f64()
+            .map(|d| d as f32)
+            .ok_or_else(|| invalid("upgrade stats must be numbers")),
+    };
+    let element_amount = match tier.get("element_amount") {
+        None => 0,
+        Some(v) => v
+            .as_u64()
+            .and_then(|a| u32::try_from(a).ok())
+            .ok_or_else(|| invalid("'element_amount' must be a positive integer"))?,
+    };
+
+    Ok(UpgradeTier {
+        name: name.to_string(),
+        base_cost,
+        delta: StatDelta {
+            range: stat("range")?,
+            fire_rate: stat("fire_rate")?,
+            damage: stat("damage")?,
+            element_amount,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn shipped_knight_file_loads() {
+        let knights = load_knights(KNIGHT_FILE_PATH).unwrap();
+        assert_eq!(knights.len(), 12);
+    }
+
+    #[test]
+    fn knight_file_fills_in_missing_behaviours() {
+        let knights = knights_from_json(&json!({
+            "format_version": 1,
+            "knights": [{
+                "id": 4,
+                "name": "Samurai Knight",
+                "affinity": "Air",
+                "behaviours": {
+                    "Tall": {
+                        "fire_rate": 2.0,
+                        "upgrades": { "Speed": [{ "name": "Quickdraw", "cost": 50, "fire_rate": 1.0 }] },
+                    },
+                },
+            }],
+        }))
+        .unwrap();
+
+        let samurai = &knights[0];
+        assert_eq!(samurai.id, KnightId(4));
+        assert_eq!(
+            samurai.behaviour(TowerType::Short),
+            KnightBehaviour::default()
+        );
+        assert_eq!(
+            samurai.behaviour(TowerType::Tall),
+            KnightBehaviour {
+                fire_rate: 2.0,
+                ..default()
+            }
+        );
+
+        // Only the Speed line was replaced.
+        let tall_upgrades = samurai.upgrades[TowerType::Tall as usize].as_ref().unwrap();
+        let usual_upgrades = UpgradePath::default_for(TowerType::Tall);
+        assert_eq!(tall_upgrades.power, usual_upgrades.power);
+        assert_eq!(tall_upgrades.speed.len(), 1);
+        assert_eq!(tall_upgrades.speed[0].base_cost, 50);
+        assert!(samurai.upgrades[TowerType::Short as usize].is_none());
+    }
+}
@@ -0,0 +1,301 @@
+//! The twelve knights and the towers they're stationed at
+//!
+//! Towers don't do anything on their own. A knight has to be stationed at one for it to fire,
+//! and each knight changes how each type of tower behaves. Moving a knight to a tower takes
+//! time based on how far it has to walk.
+
+mod knight_file;
+
+use super::td_mode_prelude::*;
+use super::towers::UpgradePath;
+use crate::prelude::*;
+use std::collections::BTreeMap;
+
+pub use knight_file::*;
+
+const FIXED_STEP_MS: u64 = 20;
+
+/// Where the knight roster is read from on startup.
+const KNIGHT_FILE_PATH: &str = "assets/data/knights.json";
+
+/// How long it takes a knight to walk across a single tile.
+pub const KNIGHT_SECONDS_PER_TILE: f32 = 0.5;
+
+/// The fixed timestep stage knights travel in. Towers check for their knight after it.
+pub const KNIGHT_FIXED_STAGE: &str = "knight_fixed_update";
+
+pub struct KnightPlugin;
+
+impl Plugin for KnightPlugin {
+    fn build(&self, app: &mut App) {
+        let roster = match load_knights(KNIGHT_FILE_PATH) {
+            Ok(knights) => KnightRoster::new(knights),
+            Err(e) => {
+                error!("Failed to load knights from {KNIGHT_FILE_PATH}: {e}");
+                KnightRoster::new(Vec::new())
+            }
+        };
+
+        let mut fixed_stage = SystemStage::parallel();
+        fixed_stage.add_system(travel_knights.run_in_state(GameState::TDMode));
+
+        app.insert_resource(roster).add_stage_before(
+            CoreStage::Update,
+            KNIGHT_FIXED_STAGE,
+            FixedTimestepStage::new(Duration::from_millis(FIXED_STEP_MS)).with_stage(fixed_stage),
+        );
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct KnightId(pub u32);
+
+/// How a knight changes the stats of one type of tower.
+///
+/// Each modifier multiplies the tower's base stat.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KnightBehaviour {
+    pub range: f32,
+    pub fire_rate: f32,
+    pub damage: f32,
+}
+
+impl Default for KnightBehaviour {
+    fn default() -> Self {
+        Self {
+            range: 1.0,
+            fire_rate: 1.0,
+            damage: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Knight {
+    pub id: KnightId,
+    pub name: String,
+    /// The element applied by towers this knight is stationed at
+    pub affinity: Element,
+    /// Indexed by tower type. Tower types missing from the knight file use the default behaviour.
+    pub behaviours: [KnightBehaviour; 3],
+    /// Upgrades this knight offers instead of the tower's usual ones, indexed by tower type.
+    pub upgrades: [Option<UpgradePath>; 3],
+}
+
+impl Knight {
+    pub fn behaviour(&self, tower_type: TowerType) -> KnightBehaviour {
+        self.behaviours[tower_type as usize]
+    }
+}
+
+/// Where a knight currently is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KnightLocation {
+    /// Not assigned to any tower
+    Reserve,
+    /// Manning the tower on this tile
+    Stationed(Coordinate),
+    /// Walking from one tile to the tower on another
+    Travelling {
+        from: Coordinate,
+        to: Coordinate,
+        /// Seconds until the knight arrives
+        remaining: f32,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AssignKnightError {
+    UnknownKnight(KnightId),
+    /// Another knight is already at or on their way to the tower.
+    TowerOccupied(KnightId),
+}
+
+impl std::fmt::Display for AssignKnightError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownKnight(id) => write!(f, "There is no knight with id {}", id.0),
+            Self::TowerOccupied(id) => write!(f, "The tower is already taken by knight {}", id.0),
+        }
+    }
+}
+
+impl std::error::Error for AssignKnightError {}
+
+/// Resource holding every knight and where they are.
+#[derive(Debug)]
+pub struct KnightRoster {
+    knights: BTreeMap<KnightId, Knight>,
+    locations: BTreeMap<KnightId, KnightLocation>,
+}
+
+impl KnightRoster {
+    /// Creates a roster with every knight in reserve.
+    pub fn new(knights: Vec<Knight>) -> Self {
+        let locations = knights
+            .iter()
+            .map(|k| (k.id, KnightLocation::Reserve))
+            .collect();
+        let knights = knights.into_iter().map(|k| (k.id, k)).collect();
+
+        Self { knights, locations }
+    }
+
+    pub fn get(&self, id: KnightId) -> Option<&Knight> {
+        self.knights.get(&id)
+    }
+
+    pub fn location(&self, id: KnightId) -> Option<KnightLocation> {
+        self.locations.get(&id).copied()
+    }
+
+    /// Iterate over every knight in id order along with where they are.
+    pub fn iter(&self) -> impl Iterator<Item = (&Knight, KnightLocation)> + '_ {
+        self.knights.values().map(|k| (k, self.locations[&k.id]))
+    }
+
+    /// The knight manning the tower at `coord`, if one has arrived there.
+    pub fn stationed_at(&self, coord: Coordinate) -> Option<&Knight> {
+        self.locations
+            .iter()
+            .find(|(_, &location)| location == KnightLocation::Stationed(coord))
+            .and_then(|(id, _)| self.knights.get(id))
+    }
+
+    /// The knight stationed at or travelling to the tower at `coord`.
+    pub fn assigned_to(&self, coord: Coordinate) -> Option<KnightId> {
+        self.locations
+            .iter()
+            .find(|(_, location)| match location {
+                KnightLocation::Stationed(at) => *at == coord,
+                KnightLocation::Travelling { to, .. } => *to == coord,
+                KnightLocation::Reserve => false,
+            })
+            .map(|(&id, _)| id)
+    }
+
+    /// Send a knight to the tower at `to`.
+    ///
+    /// Knights in reserve set out from `reserve_coord`. Knights that are already on the move
+    /// set out again from the tile they last left.
+    pub fn assign(
+        &mut self,
+        id: KnightId,
+        to: Coordinate,
+        reserve_coord: Coordinate,
+    ) -> Result<(), AssignKnightError> {
+        let from = match self.locations.get(&id) {
+            None => return Err(AssignKnightError::UnknownKnight(id)),
+            Some(KnightLocation::Reserve) => reserve_coord,
+            Some(KnightLocation::Stationed(at)) => *at,
+            Some(KnightLocation::Travelling { from, .. }) => *from,
+        };
+
+        match self.assigned_to(to) {
+            Some(other) if other != id => return Err(AssignKnightError::TowerOccupied(other)),
+            _ => {}
+        }
+
+        let location = if from == to {
+            KnightLocation::Stationed(to)
+        } else {
+            KnightLocation::Travelling {
+                from,
+                to,
+                remaining: from.distance(&to) as f32 * KNIGHT_SECONDS_PER_TILE,
+            }
+        };
+        self.locations.insert(id, location);
+
+        Ok(())
+    }
+
+    /// Send the knight assigned to the tower at `coord` back to the reserve.
+    pub fn unassign_from(&mut self, coord: Coordinate) {
+        if let Some(id) = self.assigned_to(coord) {
+            self.locations.insert(id, KnightLocation::Reserve);
+        }
+    }
+
+    /// Move every travelling knight along by `seconds`.
+    pub fn tick(&mut self, seconds: f32) {
+        self.locations.values_mut().for_each(|location| {
+            if let KnightLocation::Travelling { to, remaining, .. } = location {
+                *remaining -= seconds;
+                if *remaining <= 0.0 {
+                    *location = KnightLocation::Stationed(*to);
+                }
+            }
+        });
+    }
+}
+
+fn travel_knights(mut roster: ResMut<KnightRoster>) {
+    roster.tick(seconds_rate_to_fixed_rate(1.0, FIXED_STEP_MS));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_roster() -> KnightRoster {
+        let knight = |id| Knight {
+            id: KnightId(id),
+            name: format!("Knight {id}"),
+            affinity: Element::Fire,
+            behaviours: [KnightBehaviour::default(); 3],
+            upgrades: Default::default(),
+        };
+
+        KnightRoster::new(vec![knight(0), knight(1)])
+    }
+
+    #[test]
+    fn knights_take_time_to_reach_their_tower() {
+        let mut roster = test_roster();
+        let tower = Coordinate::from((3, 1));
+
+        roster.assign(KnightId(0), tower, Coordinate::ZERO).unwrap();
+        assert!(roster.stationed_at(tower).is_none());
+        assert_eq!(roster.assigned_to(tower), Some(KnightId(0)));
+
+        // Four tiles away
+        roster.tick(4.0 * KNIGHT_SECONDS_PER_TILE - 0.1);
+        assert!(roster.stationed_at(tower).is_none());
+        roster.tick(0.2);
+        assert_eq!(roster.stationed_at(tower).map(|k| k.id), Some(KnightId(0)));
+
+        // Moving to another tower leaves the first one empty straight away.
+        roster
+            .assign(KnightId(0), (3, 3).into(), Coordinate::ZERO)
+            .unwrap();
+        assert!(roster.stationed_at(tower).is_none());
+        assert_eq!(
+            roster.location(KnightId(0)),
+            Some(KnightLocation::Travelling {
+                from: tower,
+                to: (3, 3).into(),
+                remaining: 2.0 * KNIGHT_SECONDS_PER_TILE,
+            })
+        );
+    }
+
+    #[test]
+    fn towers_only_take_one_knight() {
+        let mut roster = test_roster();
+        let tower = Coordinate::from((1, 1));
+
+        roster.assign(KnightId(0), tower, Coordinate::ZERO).unwrap();
+        assert_eq!(
+            roster.assign(KnightId(1), tower, Coordinate::ZERO),
+            Err(AssignKnightError::TowerOccupied(KnightId(0)))
+        );
+        assert_eq!(
+            roster.assign(KnightId(7), tower, Coordinate::ZERO),
+            Err(AssignKnightError::UnknownKnight(KnightId(7)))
+        );
+
+        roster.unassign_from(tower);
+        assert!(roster.assign(KnightId(1), tower, Coordinate::ZERO).is_ok());
+    }
+}
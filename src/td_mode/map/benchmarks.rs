@@ -0,0 +1,115 @@
+//! Timing comparison between A* and `FlowFields` for re-routing enemies after map edits
+//!
+//! Ignored by default, run it in release mode with `--ignored --nocapture` like the element
+//! benchmarks to see the timings.
+//!
+//! Every frame a few tiles get a barricade put up or taken down on a 64x64 map, and then every
+//! agent needs a fresh route to the exit. A* pays for a full search per agent, while the flow
+//! fields pay once per movement class to repair the field and then each agent just reads its
+//! route off of it, so the two halves of that are timed separately.
+
+use super::*;
+use std::time::{Duration, Instant};
+
+const MAP_SIZE: usize = 64;
+const AGENTS: usize = 500;
+const FRAMES: usize = 20;
+/// Tiles toggled between open and barricaded each frame, like towers and barricades going up
+const EDITS_PER_FRAME: usize = 3;
+
+/// A map with a mix of terrain, the entry on the left and the exit on the right.
+fn benchmark_map() -> Map {
+    let mut map = Map::new((MAP_SIZE, MAP_SIZE));
+    map.wave_entry_coord = (0, MAP_SIZE / 2).into();
+    map.wave_exit_coord = (MAP_SIZE - 1, MAP_SIZE / 2).into();
+    (0..map.tile_count()).for_each(|idx| {
+        let tile_type = TileType::all()[idx * 7 / 5 % TILE_TYPE_COUNT];
+        map.set_tile(map.idx_to_coord(idx), Some(tile_type), None);
+    });
+    map.dirty_tiles.clear();
+
+    map
+}
+
+/// Where each agent stands and how it moves, spread over the whole map.
+fn agents(map: &Map) -> Vec<(Coordinate, MovementClass)> {
+    let classes = MovementClass::all();
+    (0..AGENTS)
+        .map(|i| {
+            let idx = i * 7919 % map.tile_count();
+            (map.idx_to_coord(idx), classes[i % classes.len()])
+        })
+        .collect()
+}
+
+/// Toggle a barricade on a few tiles for the given frame.
+fn edit_frame(map: &mut Map, frame: usize) {
+    (0..EDITS_PER_FRAME).for_each(|i| {
+        let idx = (frame * EDITS_PER_FRAME + i) * 104_729 % map.tile_count();
+        let coord = map.idx_to_coord(idx);
+        if coord != map.wave_exit_coord {
+            let structure = match map.structure_at_coord(coord) {
+                Some(Structure::None) => Structure::Barricade,
+                _ => Structure::None,
+            };
+            map.set_tile(coord, None, Some(structure));
+        }
+    });
+}
+
+/// Time spent routing over every frame, and the summed cost of the routes so the two ways of
+/// routing can be checked against each other.
+#[derive(Default)]
+struct RoutingTotals {
+    astar: Duration,
+    field_updates: Duration,
+    field_lookups: Duration,
+    astar_cost: u64,
+    field_cost: u64,
+}
+
+#[test]
+#[ignore = "only meaningful in release builds"]
+fn compare_astar_and_flow_fields() {
+    let mut map = benchmark_map();
+    let agents = agents(&map);
+    let mut flow_fields = FlowFields::default();
+    flow_fields.update(&map);
+
+    let mut totals = RoutingTotals::default();
+    (0..FRAMES).for_each(|frame| {
+        edit_frame(&mut map, frame);
+
+        let start = Instant::now();
+        totals.astar_cost += agents
+            .iter()
+            .filter_map(|&(coord, class)| map.find_path(coord, map.wave_exit_coord, class))
+            .map(|(_, cost)| u64::from(cost))
+            .sum::<u64>();
+        totals.astar += start.elapsed();
+
+        let start = Instant::now();
+        flow_fields.update(&map);
+        totals.field_updates += start.elapsed();
+
+        let start = Instant::now();
+        totals.field_cost += agents
+            .iter()
+            .filter_map(|&(coord, class)| flow_fields.get(class)?.path_from(coord))
+            .map(|(_, cost)| u64::from(cost))
+            .sum::<u64>();
+        totals.field_lookups += start.elapsed();
+
+        map.dirty_tiles.clear();
+    });
+
+    assert_eq!(totals.astar_cost, totals.field_cost);
+
+    let frames = FRAMES as u32;
+    println!(
+        "Re-routing {AGENTS} agents after {EDITS_PER_FRAME} edits, averaged over {FRAMES} frames:"
+    );
+    println!("  A*:           {:?}", totals.astar / frames);
+    println!("  Field update: {:?}", totals.field_updates / frames);
+    println!("  Field routes: {:?}", totals.field_lookups / frames);
+}
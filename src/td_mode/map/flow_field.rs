@@ -0,0 +1,292 @@
+//! Flow fields towards the wave exit
+//!
+//! Rather than every enemy searching for its own route to the exit, each movement class has a
+//! `FlowField`: the cost of the cheapest route from every tile to the wave exit, found by
+//! searching backwards from the exit. An enemy's route is then a walk downhill from wherever it
+//! is standing.
+//!
+//! When tiles change, only the routes whose first step onto or past a changed tile got dearer are
+//! forgotten and searched again, starting from the tiles around them that still know their way.
+
+use super::*;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+/// The cheapest way to the wave exit from every tile, for one movement class.
+#[derive(Debug)]
+pub struct FlowField {
+    class: MovementClass,
+    dimensions: (usize, usize),
+    exit: Coordinate,
+    rules: MovementRules,
+    /// What it costs to move onto each tile, as of the last update
+    enter_costs: Vec<Option<u32>>,
+    /// The cost of the cheapest route from each tile to the exit. None if there's no route.
+    costs: Vec<Option<u32>>,
+    /// The tile each tile's cheapest route steps onto first
+    next: Vec<Option<usize>>,
+}
+
+impl FlowField {
+    pub fn new(map: &Map, class: MovementClass) -> Self {
+        let tile_count = map.tile_count();
+        let mut field = Self {
+            class,
+            dimensions: map.dimensions,
+            exit: map.wave_exit_coord,
+            rules: map.movement_rules,
+            enter_costs: (0..tile_count)
+                .map(|idx| map.tile_astar_cost(idx, class))
+                .collect(),
+            costs: vec![None; tile_count],
+            next: vec![None; tile_count],
+        };
+
+        let mut frontier = BinaryHeap::new();
+        if field.exit.x < map.dimensions.0 && field.exit.y < map.dimensions.1 {
+            let exit_idx = map.coord_to_idx(field.exit);
+            field.costs[exit_idx] = Some(0);
+            frontier.push(Reverse((0, exit_idx)));
+        }
+        field.search(map, frontier);
+
+        field
+    }
+
+    /// Whether the map has been resized, its exit moved or its movement rules changed since the
+    /// field was built, so the field has to be built again rather than updated.
+    pub fn is_stale(&self, map: &Map) -> bool {
+        self.dimensions != map.dimensions
+            || self.exit != map.wave_exit_coord
+            || self.rules != map.movement_rules
+    }
+
+    /// Bring the field up to date after the tiles at the given indices have changed.
+    pub fn update(&mut self, map: &Map, changed: &[usize]) {
+        let changed: Vec<usize> = changed
+            .iter()
+            .copied()
+            .filter(|&idx| {
+                let cost = map.tile_astar_cost(idx, self.class);
+                let differs = self.enter_costs[idx] != cost;
+                self.enter_costs[idx] = cost;
+                differs
+            })
+            .collect();
+
+        if changed.is_empty() {
+            return;
+        }
+
+        // Forget every route whose first step has got dearer or isn't allowed any more, and every
+        // route that goes through one of those. Those steps are onto or past a changed tile, so
+        // they all start next to one.
+        let mut forgotten = Vec::new();
+        let mut to_forget: Vec<usize> = changed
+            .iter()
+            .flat_map(|&idx| map.coord_neighbour_indices(map.idx_to_coord(idx)))
+            .filter(|&idx| !self.first_step_holds(map, idx))
+            .collect();
+        while let Some(idx) = to_forget.pop() {
+            if self.costs[idx].take().is_some() {
+                self.next[idx] = None;
+                forgotten.push(idx);
+                to_forget.extend(self.routes_through(map, idx));
+            }
+        }
+
+        // Search again from around the changed tiles, which may have become cheaper to step onto
+        // or past, and from the tiles bordering the forgotten ones.
+        let frontier = changed
+            .iter()
+            .flat_map(|&idx| {
+                std::iter::once(idx).chain(map.coord_neighbour_indices(map.idx_to_coord(idx)))
+            })
+            .chain(
+                forgotten
+                    .iter()
+                    .flat_map(|&idx| map.coord_neighbour_indices(map.idx_to_coord(idx))),
+            )
+            .filter_map(|idx| Some(Reverse((self.costs[idx]?, idx))))
+            .collect();
+        self.search(map, frontier);
+    }
+
+    /// The cheapest route from `start` to the exit, starting with `start` itself, along with its
+    /// total cost.
+    pub fn path_from(&self, start: Coordinate) -> Option<(Vec<Coordinate>, u32)> {
+        if start.x >= self.dimensions.0 || start.y >= self.dimensions.1 {
+            return None;
+        }
+
+        let mut idx = start.y * self.dimensions.0 + start.x;
+        let cost = self.costs[idx]?;
+        let mut route = vec![start];
+        while let Some(next) = self.next[idx] {
+            route.push((next % self.dimensions.0, next / self.dimensions.0).into());
+            idx = next;
+        }
+
+        Some((route, cost))
+    }
+
+    /// Whether the first step of the route from `idx` still costs what it did.
+    fn first_step_holds(&self, map: &Map, idx: usize) -> bool {
+        match (self.costs[idx], self.next[idx]) {
+            (Some(cost), Some(next)) => {
+                let from = map.idx_to_coord(idx);
+                let step = from
+                    .direction_to(&map.idx_to_coord(next))
+                    .and_then(|direction| map.step_cost(from, direction, self.class));
+
+                step.zip(self.costs[next])
+                    .map(|((_, step), next_cost)| step.saturating_add(next_cost))
+                    == Some(cost)
+            }
+            _ => true,
+        }
+    }
+
+    /// The tiles next to `idx` whose cheapest route steps onto it.
+    fn routes_through(&self, map: &Map, idx: usize) -> Vec<usize> {
+        let mut neighbours = map.coord_neighbour_indices(map.idx_to_coord(idx));
+        neighbours.retain(|&n| self.next[n] == Some(idx));
+        neighbours
+    }
+
+    /// Dijkstra's algorithm outwards from the frontier, lowering the cost of any tile that can
+    /// get to the exit more cheaply through a tile on it.
+    fn search(&mut self, map: &Map, mut frontier: BinaryHeap<Reverse<(u32, usize)>>) {
+        while let Some(Reverse((cost, idx))) = frontier.pop() {
+            // Skip tiles that have been lowered again since they were added.
+            if self.costs[idx] != Some(cost) {
+                continue;
+            }
+
+            let coord = map.idx_to_coord(idx);
+            coord
+                .neighbours(self.rules.neighbourhood.directions(), self.dimensions)
+                .filter_map(|(direction, from)| {
+                    map.step_cost(from, direction.opposite(), self.class)
+                        .map(|(_, step)| (map.coord_to_idx(from), cost.saturating_add(step)))
+                })
+                .for_each(|(n, through)| {
+                    if self.costs[n].unwrap_or(u32::MAX) > through {
+                        self.costs[n] = Some(through);
+                        self.next[n] = Some(idx);
+                        frontier.push(Reverse((through, n)));
+                    }
+                });
+        }
+    }
+}
+
+/// Resource holding a flow field for every movement class, kept up to date with the map.
+#[derive(Debug, Default)]
+pub struct FlowFields(HashMap<MovementClass, FlowField>);
+
+impl FlowFields {
+    pub fn get(&self, class: MovementClass) -> Option<&FlowField> {
+        self.0.get(&class)
+    }
+
+    /// Build any missing or stale fields, and update the rest with the map's dirty tiles.
+    pub fn update(&mut self, map: &Map) {
+        MovementClass::all()
+            .into_iter()
+            .for_each(|class| match self.0.get_mut(&class) {
+                Some(field) if !field.is_stale(map) => field.update(map, &map.dirty_tiles),
+                _ => {
+                    self.0.insert(class, FlowField::new(map, class));
+                }
+            });
+    }
+}
+
+/// Label for the system that updates `FlowFields`. Anything routing enemies after the map
+/// changes should run after it.
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UpdateFlowFields;
+
+pub fn update_flow_fields(map: Res<Map>, mut flow_fields: ResMut<FlowFields>) {
+    if map.is_changed() {
+        flow_fields.update(&map);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every tile's cost to the exit agrees with a fresh A* search.
+    fn assert_matches_astar(field: &FlowField, map: &Map, class: MovementClass) {
+        (0..map.tile_count()).for_each(|idx| {
+            let start = map.idx_to_coord(idx);
+            let expected = map.find_path(start, map.wave_exit_coord, class);
+            let actual = field.path_from(start);
+
+            assert_eq!(
+                actual.as_ref().map(|(_, cost)| *cost),
+                expected.map(|(_, cost)| cost),
+                "{class} route from {start}"
+            );
+            if let Some((route, _)) = actual {
+                assert_eq!(route.last(), Some(&map.wave_exit_coord));
+            }
+        });
+    }
+
+    #[test]
+    fn updated_fields_agree_with_astar() {
+        let eight_way = MovementRules {
+            neighbourhood: Neighbourhood::EightWay,
+            ..default()
+        };
+        let corner_cutting = MovementRules {
+            corner_cutting: true,
+            ..eight_way
+        };
+
+        [MovementRules::default(), eight_way, corner_cutting]
+            .into_iter()
+            .for_each(check_updates_against_astar);
+    }
+
+    /// Build a field for every movement class, then check each one against A* after every edit
+    /// to the map.
+    fn check_updates_against_astar(movement_rules: MovementRules) {
+        let mut map = Map::new((6, 5));
+        map.wave_exit_coord = (5, 2).into();
+        map.movement_rules = movement_rules;
+        (0..map.tile_count()).for_each(|idx| {
+            let tile_type = TileType::all()[idx * 7 % TILE_TYPE_COUNT];
+            map.set_tile(map.idx_to_coord(idx), Some(tile_type), None);
+        });
+        map.dirty_tiles.clear();
+
+        let mut fields: Vec<FlowField> = MovementClass::all()
+            .into_iter()
+            .map(|class| FlowField::new(&map, class))
+            .collect();
+
+        // Wall off most of a column, open it back up, then change the ground under it.
+        let edits = [
+            ((3, 0), None, Some(Structure::Barricade)),
+            ((3, 1), None, Some(Structure::Barricade)),
+            ((3, 2), Some(TileType::Rock), Some(Structure::Barricade)),
+            ((3, 3), None, Some(Structure::Barricade)),
+            ((3, 1), None, Some(Structure::None)),
+            ((3, 1), Some(TileType::Water), None),
+            ((4, 4), Some(TileType::Air), None),
+            ((4, 2), Some(TileType::Barren), None),
+        ];
+        edits.into_iter().for_each(|(coord, tile_type, structure)| {
+            map.set_tile(coord.into(), tile_type, structure);
+            fields.iter_mut().for_each(|field| {
+                field.update(&map, &map.dirty_tiles);
+                assert_matches_astar(field, &map, field.class);
+            });
+            map.dirty_tiles.clear();
+        });
+    }
+}
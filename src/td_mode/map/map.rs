@@ -1,6 +1,7 @@
 use super::*;
 
 /// A resource containing a collection of tiles
+#[derive(Debug)]
 pub struct Map {
     /// usize tuple, (width, height)
     pub dimensions: (usize, usize),
@@ -16,6 +17,10 @@ pub struct Map {
 
     pub wave_entry_coord: Coordinate,
     pub wave_exit_coord: Coordinate,
+
+    /// Which neighbours enemies can step to and elements can spread to, and what diagonal steps
+    /// cost
+    pub movement_rules: MovementRules,
 }
 
 impl Map {
@@ -35,6 +40,7 @@ impl Map {
             dirty_tiles: Vec::new(),
             wave_entry_coord: Coordinate::ZERO,
             wave_exit_coord: Coordinate::ZERO,
+            movement_rules: MovementRules::default(),
         }
     }
 
@@ -61,18 +67,104 @@ impl Map {
         coord.y * self.dimensions.0 + coord.x
     }
 
-    pub fn find_astar_successors(&self, coord: Coordinate) -> Vec<(Coordinate, u32)> {
-        self.coord_cardinal_indices(coord)
+    /// The cost for an enemy of the given movement class to move onto the tile at idx, taking
+    /// both the terrain and any structure into account. Returns None if the tile can't be entered.
+    pub fn tile_astar_cost(&self, idx: usize, class: MovementClass) -> Option<u32> {
+        let &(tile_type, structure) = self.tiles.get(idx)?;
+
+        class.tile_cost(tile_type, structure)
+    }
+
+    /// The tile a step from `from` in `direction` leads to and what the step costs an enemy of
+    /// the given movement class, or None if the movement rules don't allow it.
+    ///
+    /// Without corner cutting, a diagonal step needs both of the tiles it passes between to be
+    /// enterable too.
+    pub fn step_cost(
+        &self,
+        from: Coordinate,
+        direction: GridDirection,
+        class: MovementClass,
+    ) -> Option<(Coordinate, u32)> {
+        let to = from.step(direction, self.dimensions)?;
+        let cost = self.tile_astar_cost(self.coord_to_idx(to), class)?;
+
+        if direction.is_diagonal() && !self.movement_rules.corner_cutting {
+            let corners = [Coordinate::from((to.x, from.y)), (from.x, to.y).into()];
+            if corners
+                .iter()
+                .any(|&c| self.tile_astar_cost(self.coord_to_idx(c), class).is_none())
+            {
+                return None;
+            }
+        }
+
+        Some((to, self.movement_rules.step_cost(cost, direction)))
+    }
+
+    pub fn find_astar_successors(
+        &self,
+        coord: Coordinate,
+        class: MovementClass,
+    ) -> Vec<(Coordinate, u32)> {
+        self.movement_rules
+            .neighbourhood
+            .directions()
             .iter()
-            .map(|&idx| {
-                (
-                    self.idx_to_coord(idx),
-                    self.tile_type_at_index(idx).unwrap().astar_cost(),
-                )
-            })
+            .filter_map(|&direction| self.step_cost(coord, direction, class))
             .collect()
     }
 
+    /// Find the cheapest route between two tiles for a movement class, along with its total
+    /// cost in `STEP_COST_SCALE`ths of a straight step.
+    pub fn find_path(
+        &self,
+        start: Coordinate,
+        end: Coordinate,
+        class: MovementClass,
+    ) -> Option<(Vec<Coordinate>, u32)> {
+        astar(
+            &start,
+            |p| self.find_astar_successors(*p, class),
+            |p| self.movement_rules.min_route_cost(*p, end),
+            |p| *p == end,
+        )
+    }
+
+    /// Find the route enemies of a movement class will take from the entry portal to the exit
+    /// portal.
+    pub fn find_wave_path(&self, class: MovementClass) -> Option<(Vec<Coordinate>, u32)> {
+        self.find_path(self.wave_entry_coord, self.wave_exit_coord, class)
+    }
+
+    /// Checks whether a structure can be placed on a tile without cutting the wave entry off
+    /// from the wave exit for walkers. Every other movement class can go anywhere a walker can.
+    ///
+    /// Maps that already have no route are left alone so they can still be edited.
+    pub fn can_place_structure(&self, coord: Coordinate, structure: Structure) -> bool {
+        let class = MovementClass::Walker;
+        if structure.astar_cost().is_some() || self.find_wave_path(class).is_none() {
+            return true;
+        }
+
+        if coord == self.wave_entry_coord || coord == self.wave_exit_coord {
+            return false;
+        }
+
+        let end = self.wave_exit_coord;
+        astar(
+            &self.wave_entry_coord,
+            |p| {
+                let mut successors = self.find_astar_successors(*p, class);
+                successors.retain(|(c, _)| *c != coord);
+                successors
+            },
+            |p| self.movement_rules.min_route_cost(*p, end),
+            |p| *p == end,
+        )
+        .is_some()
+    }
+
     pub fn tile_type_at_index(&self, idx: usize) -> Option<&TileType> {
         self.tiles.get(idx).map(|(t_type, _)| t_type)
     }
@@ -105,24 +197,17 @@ impl Map {
         self.dirty_tiles.push(idx);
     }
 
-    pub fn coord_cardinal_indices(&self, coord: Coordinate) -> Vec<usize> {
+    /// The indices of a tile's neighbours under the map's movement rules.
+    pub fn coord_neighbour_indices(&self, coord: Coordinate) -> Vec<usize> {
         coord
-            .cardinals()
-            .iter()
-            .filter_map(move |&c| {
-                if self.coord_in_bounds(c) {
-                    Some(self.coord_to_idx(c))
-                } else {
-                    None
-                }
-            })
+            .neighbours(
+                self.movement_rules.neighbourhood.directions(),
+                self.dimensions,
+            )
+            .map(|(_, c)| self.coord_to_idx(c))
             .collect()
     }
 
-    fn coord_in_bounds(&self, coord: Coordinate) -> bool {
-        coord.x < self.dimensions.0 && coord.y < self.dimensions.1
-    }
-
     pub fn tile_type_at_coord(&self, coord: Coordinate) -> Option<&TileType> {
         let idx = self.coord_to_idx(coord);
         self.tile_type_at_index(idx)
@@ -0,0 +1,363 @@
+//! Saving and loading maps to and from disk
+//!
+//! Maps are stored as JSON in the following shape:
+//!
+//! ```json
+//! {
+//!     "format_version": 1,
+//!     "dimensions": [8, 8],
+//!     "wave_entry": [0, 0],
+//!     "wave_exit": [7, 7],
+//!     "movement": { "neighbourhood": "8-Way", "diagonal_cost": 1.5, "corner_cutting": false },
+//!     "tiles": [
+//!         { "tile_type": "Rock", "structure": "None", "elements": { "Fire": 20 } },
+//!         ...
+//!     ]
+//! }
+//! ```
+//!
+//! Tiles are listed in index order (row by row) and the `elements` entry is left out
+//! for tiles without any elements applied. Maps without a `movement` entry use the default
+//! `MovementRules`, as do any of its fields that are left out.
+
+use super::*;
+use serde_json::{json, Value};
+use std::path::Path;
+
+/// The version written to new map files. Bump this whenever the layout changes.
+pub const MAP_FORMAT_VERSION: u64 = 1;
+
+/// Everything that can go wrong reading or writing a map file.
+#[derive(Debug)]
+pub enum MapFileError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    UnsupportedVersion(u64),
+    MissingField(&'static str),
+    DimensionsTooLarge((usize, usize)),
+    TileCountMismatch {
+        expected: usize,
+        found: usize,
+    },
+    CoordOutOfBounds {
+        field: &'static str,
+        coord: Coordinate,
+    },
+    InvalidMovementRules(&'static str),
+    InvalidTile {
+        index: usize,
+        reason: &'static str,
+    },
+    UnknownTileType {
+        index: usize,
+        name: String,
+    },
+    UnknownStructure {
+        index: usize,
+        name: String,
+    },
+    UnknownElement {
+        index: usize,
+        name: String,
+    },
+    InvalidElementAmount {
+        index: usize,
+        element: Element,
+    },
+}
+
+impl std::fmt::Display for MapFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "Could not access map file: {e}"),
+            Self::Json(e) => write!(f, "Map file is not valid JSON: {e}"),
+            Self::UnsupportedVersion(v) => write!(
+                f,
+                "Map format version {v} is not supported (expected {MAP_FORMAT_VERSION})"
+            ),
+            Self::MissingField(field) => write!(f, "Map file is missing the field '{field}'"),
+            Self::DimensionsTooLarge((width, height)) => {
+                write!(f, "Map dimensions {width}x{height} are too large")
+            }
+            Self::TileCountMismatch { expected, found } => write!(
+                f,
+                "Map dimensions call for {expected} tiles but the file has {found}"
+            ),
+            Self::CoordOutOfBounds { field, coord } => {
+                write!(f, "'{field}' {coord} is outside of the map")
+            }
+            Self::InvalidMovementRules(reason) => write!(f, "Movement rules: {reason}"),
+            Self::InvalidTile { index, reason } => write!(f, "Tile {index}: {reason}"),
+            Self::UnknownTileType { index, name } => {
+                write!(f, "Tile {index}: unknown tile type '{name}'")
+            }
+            Self::UnknownStructure { index, name } => {
+                write!(f, "Tile {index}: unknown structure '{name}'")
+            }
+            Self::UnknownElement { index, name } => {
+                write!(f, "Tile {index}: unknown element '{name}'")
+            }
+            Self::InvalidElementAmount { index, element } => {
+                write!(
+                    f,
+                    "Tile {index}: {element} amount must be a positive integer"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for MapFileError {}
+
+impl From<std::io::Error> for MapFileError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for MapFileError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+/// Write a map and the elements applied to each of its tiles to disk.
+///
+/// `afflictions` is indexed the same way as the map's tiles. Missing entries are treated as empty.
+pub fn save_map(
+    path: impl AsRef<Path>,
+    map: &Map,
+    afflictions: &[ElementalAffliction],
+) -> Result<(), MapFileError> {
+    let path = path.as_ref();
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let contents = serde_json::to_string_pretty(&map_to_json(map, afflictions))?;
+    std::fs::write(path, contents)?;
+
+    Ok(())
+}
+
+/// Read a map file from disk, returning the map and the elements applied to each tile.
+pub fn load_map(path: impl AsRef<Path>) -> Result<(Map, Vec<ElementalAffliction>), MapFileError> {
+    let contents = std::fs::read_to_string(path)?;
+    let value: Value = serde_json::from_str(&contents)?;
+
+    map_from_json(&value)
+}
+
+pub fn map_to_json(map: &Map, afflictions: &[ElementalAffliction]) -> Value {
+    let tiles: Vec<Value> = (0..map.tile_count())
+        .map(|idx| {
+            let mut tile = json!({
+                "tile_type": map.tile_type_at_index(idx).unwrap().to_string(),
+                "structure": map.structure_at_index(idx).unwrap().to_string(),
+            });
+
+            if let Some(affliction) = afflictions.get(idx).filter(|a| !a.is_empty()) {
+                let elements: serde_json::Map<String, Value> = affliction
+                    .iter()
+                    .map(|(element, amount)| (element.to_string(), json!(amount)))
+                    .collect();
+                tile["elements"] = Value::Object(elements);
+            }
+
+            tile
+        })
+        .collect();
+
+    json!({
+        "format_version": MAP_FORMAT_VERSION,
+        "dimensions": [map.dimensions.0, map.dimensions.1],
+        "wave_entry": [map.wave_entry_coord.x, map.wave_entry_coord.y],
+        "wave_exit": [map.wave_exit_coord.x, map.wave_exit_coord.y],
+        "movement": {
+            "neighbourhood": map.movement_rules.neighbourhood.to_string(),
+            "diagonal_cost": map.movement_rules.diagonal_cost,
+            "corner_cutting": map.movement_rules.corner_cutting,
+        },
+        "tiles": tiles,
+    })
+}
+
+pub fn map_from_json(value: &Value) -> Result<(Map, Vec<ElementalAffliction>), MapFileError> {
+    let version = value
+        .get("format_version")
+        .and_then(Value::as_u64)
+        .ok_or(MapFileError::MissingField("format_version"))?;
+    if version != MAP_FORMAT_VERSION {
+        return Err(MapFileError::UnsupportedVersion(version));
+    }
+
+    let dimensions = read_pair(value, "dimensions")?;
+    let wave_entry_coord = Coordinate::from(read_pair(value, "wave_entry")?);
+    let wave_exit_coord = Coordinate::from(read_pair(value, "wave_exit")?);
+
+    let tiles = value
+        .get("tiles")
+        .and_then(Value::as_array)
+        .ok_or(MapFileError::MissingField("tiles"))?;
+
+    // Check the dimensions against the tiles before making a map of that size, so a bad file
+    // can't ask for more tiles than fit in memory.
+    let tile_count = dimensions
+        .0
+        .checked_mul(dimensions.1)
+        .ok_or(MapFileError::DimensionsTooLarge(dimensions))?;
+    if tiles.len() != tile_count {
+        return Err(MapFileError::TileCountMismatch {
+            expected: tile_count,
+            found: tiles.len(),
+        });
+    }
+
+    let mut map = Map::new(dimensions);
+
+    for (field, coord) in [
+        ("wave_entry", wave_entry_coord),
+        ("wave_exit", wave_exit_coord),
+    ] {
+        // Maps without any tiles, like the one the game starts with, keep their portals at the
+        // origin.
+        let empty_map_origin = map.tile_count() == 0 && coord == Coordinate::ZERO;
+        if !empty_map_origin && (coord.x >= dimensions.0 || coord.y >= dimensions.1) {
+            return Err(MapFileError::CoordOutOfBounds { field, coord });
+        }
+    }
+    map.wave_entry_coord = wave_entry_coord;
+    map.wave_exit_coord = wave_exit_coord;
+    if let Some(movement) = value.get("movement") {
+        map.movement_rules = movement_rules_from_json(movement)?;
+    }
+
+    let mut afflictions = Vec::with_capacity(tiles.len());
+    for (index, tile) in tiles.iter().enumerate() {
+        let (tile_type, structure, affliction) = tile_from_json(index, tile)?;
+        map.set_tile(map.idx_to_coord(index), Some(tile_type), Some(structure));
+        afflictions.push(affliction);
+    }
+
+    // A freshly loaded map is reloaded in full, so there's no need to track individual tiles.
+    map.dirty_tiles.clear();
+
+    Ok((map, afflictions))
+}
+
+fn read_pair(value: &Value, field: &'static str) -> Result<(usize, usize), MapFileError> {
+    let pair = value
+        .get(field)
+        .and_then(Value::as_array)
+        .filter(|pair| pair.len() == 2)
+        .ok_or(MapFileError::MissingField(field))?;
+
+    let read = |v: &Value| {
+        v.as_u64()
+            .and_then(|n| usize::try_from(n).ok())
+            .ok_or(MapFileError::MissingField(field))
+    };
+
+    Ok((read(&pair[0])?, read(&pair[1])?))
+}
+
+fn movement_rules_from_json(movement: &Value) -> Result<MovementRules, MapFileError> {
+    let mut rules = MovementRules::default();
+
+    match movement.get("neighbourhood") {
+        None => {}
+        Some(Value::String(name)) => {
+            rules.neighbourhood = Neighbourhood::from_name(name).ok_or(
+                MapFileError::InvalidMovementRules("unknown 'neighbourhood'"),
+            )?;
+        }
+        Some(_) => {
+            return Err(MapFileError::InvalidMovementRules(
+                "'neighbourhood' must be a string",
+            ))
+        }
+    }
+
+    if let Some(cost) = movement.get("diagonal_cost") {
+        rules.diagonal_cost = cost
+            .as_f64()
+            .map(|cost| cost as f32)
+            .filter(|cost| DIAGONAL_COST_RANGE.contains(cost))
+            .ok_or(MapFileError::InvalidMovementRules(
+                "'diagonal_cost' must be a number from 1 to 4",
+            ))?;
+    }
+
+    if let Some(corner_cutting) = movement.get("corner_cutting") {
+        rules.corner_cutting =
+            corner_cutting
+                .as_bool()
+                .ok_or(MapFileError::InvalidMovementRules(
+                    "'corner_cutting' must be true or false",
+                ))?;
+    }
+
+    Ok(rules)
+}
+
+fn tile_from_json(
+    index: usize,
+    tile: &Value,
+) -> Result<(TileType, Structure, ElementalAffliction), MapFileError> {
+    let tile_type_name =
+        tile.get("tile_type")
+            .and_then(Value::as_str)
+            .ok_or(MapFileError::InvalidTile {
+                index,
+                reason: "missing 'tile_type'",
+            })?;
+    let tile_type =
+        TileType::from_name(tile_type_name).ok_or_else(|| MapFileError::UnknownTileType {
+            index,
+            name: tile_type_name.to_string(),
+        })?;
+
+    let structure = match tile.get("structure") {
+        None => Structure::None,
+        Some(Value::String(name)) => {
+            Structure::from_name(name).ok_or_else(|| MapFileError::UnknownStructure {
+                index,
+                name: name.clone(),
+            })?
+        }
+        Some(_) => {
+            return Err(MapFileError::InvalidTile {
+                index,
+                reason: "'structure' must be a string",
+            })
+        }
+    };
+
+    let mut affliction = ElementalAffliction::empty();
+    match tile.get("elements") {
+        None => {}
+        Some(Value::Object(elements)) => {
+            for (name, amount) in elements {
+                let element =
+                    Element::from_name(name).ok_or_else(|| MapFileError::UnknownElement {
+                        index,
+                        name: name.clone(),
+                    })?;
+                let amount = amount
+                    .as_u64()
+                    .and_then(|a| u32::try_from(a).ok())
+                    .ok_or(MapFileError::InvalidElementAmount { index, element })?;
+
+                affliction.add_element(element, amount);
+            }
+        }
+        Some(_) => {
+            return Err(MapFileError::InvalidTile {
+                index,
+                reason: "'elements' must be an object",
+            })
+        }
+    }
+
+    Ok((tile_type, structure, affliction))
+}
@@ -1,13 +1,21 @@
 //! Map and Tile code
 
+#[cfg(test)]
+mod benchmarks;
+mod flow_field;
 mod map;
+mod map_file;
+mod movement;
 mod structures;
 mod tile;
 
 pub use super::td_mode_prelude::*;
 use crate::prelude::*;
 
+pub use flow_field::*;
 pub use map::*;
+pub use map_file::*;
+pub use movement::*;
 pub use structures::*;
 pub use tile::*;
 
@@ -16,21 +24,67 @@ pub struct MapPlugin;
 impl Plugin for MapPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(Map::empty())
+            .init_resource::<FlowFields>()
             .add_plugin(TilePlugin)
             .add_plugin(StructuresPlugin)
-            .add_system(
+            // Changed tiles are synced in PostUpdate so that every system that edits the map
+            // during the frame gets a chance to see the dirty tiles first.
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
                 update_changed_tiles
                     .run_in_state(GameState::TDMode)
-                    .run_if(are_tiles_dirty),
+                    .run_if(are_tiles_dirty)
+                    .label(UpdateChangedTiles),
+            )
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                update_flow_fields
+                    .run_in_state(GameState::TDMode)
+                    .label(UpdateFlowFields)
+                    .before(UpdateChangedTiles),
             )
             .add_system(
                 reload_all_map_tiles
                     .run_in_state(GameState::TDMode)
                     .run_if(is_map_resized),
+            )
+            .add_system(
+                apply_loaded_afflictions
+                    .run_in_state(GameState::TDMode)
+                    .run_if_resource_exists::<LoadedAfflictions>()
+                    .run_if_not(is_map_resized),
             );
     }
 }
 
+/// Elements read from a map file, waiting to be applied once the tile entities for the newly
+/// loaded map exist. Indexed the same way as the map's tiles.
+pub struct LoadedAfflictions(pub Vec<ElementalAffliction>);
+
+/// Replace the elements on every tile with the ones read from a map file.
+fn apply_loaded_afflictions(
+    map_root_query: Query<&MapRoot>,
+    loaded: Res<LoadedAfflictions>,
+    mut commands: Commands,
+) {
+    if let Ok(map_root) = map_root_query.get_single() {
+        map_root
+            .tile_entities
+            .iter()
+            .enumerate()
+            .for_each(|(idx, e)| match loaded.0.get(idx) {
+                Some(affliction) if !affliction.is_empty() => {
+                    commands.entity(*e).insert(*affliction);
+                }
+                _ => {
+                    commands.entity(*e).remove::<ElementalAffliction>();
+                }
+            });
+
+        commands.remove_resource::<LoadedAfflictions>();
+    }
+}
+
 fn reload_all_map_tiles(
     mut map_root_query: Query<(Entity, &mut MapRoot)>,
     mut map: ResMut<Map>,
@@ -78,10 +132,12 @@ fn reload_all_map_tiles(
             .enumerate()
             .for_each(|(idx, e)| {
                 let tile_type = map.tile_type_at_index(idx).unwrap();
+                let structure = map.structure_at_index(idx).unwrap();
                 commands
                     .entity(*e)
                     .insert(Tile)
                     .insert(*tile_type)
+                    .insert(*structure)
                     .insert(map.idx_to_coord(idx))
                     .insert_bundle(TransformBundle::identity());
             });
@@ -96,7 +152,12 @@ fn reload_all_map_tiles(
     }
 }
 
-fn update_changed_tiles(
+/// Label for the system that pushes changed tiles onto their entities and clears
+/// `Map::dirty_tiles`. Anything reacting to dirty tiles in `PostUpdate` must run before it.
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UpdateChangedTiles;
+
+pub fn update_changed_tiles(
     tiles: Query<(Entity, &Coordinate), With<Tile>>,
     mut map: ResMut<Map>,
     mut commands: Commands,
@@ -106,8 +167,9 @@ fn update_changed_tiles(
         .filter(|(_, coord)| map.dirty_tiles.contains(&map.coord_to_idx(**coord)))
         .for_each(|(e, coord)| {
             let tile_type = map.tile_type_at_coord(*coord).unwrap();
+            let structure = map.structure_at_coord(*coord).unwrap();
 
-            commands.entity(e).insert(*tile_type);
+            commands.entity(e).insert(*tile_type).insert(*structure);
         });
 
     map.dirty_tiles = Vec::new();
@@ -141,4 +203,177 @@ mod tests {
 
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn paths_route_around_barricades() {
+        let mut map = Map::new((3, 3));
+        map.wave_entry_coord = (0, 1).into();
+        map.wave_exit_coord = (2, 1).into();
+        map.set_tile((1, 1).into(), None, Some(Structure::Barricade));
+
+        let (path, cost) = map.find_wave_path(MovementClass::Walker).unwrap();
+
+        assert!(!path.contains(&Coordinate::from((1, 1))));
+        assert_eq!(cost, 4 * STEP_COST_SCALE);
+    }
+
+    #[test]
+    fn movement_classes_take_their_own_routes() {
+        let mut map = Map::new((3, 3));
+        map.wave_entry_coord = (0, 1).into();
+        map.wave_exit_coord = (2, 1).into();
+        map.set_tile((1, 0).into(), Some(TileType::Water), None);
+        map.set_tile(
+            (1, 1).into(),
+            Some(TileType::Rock),
+            Some(Structure::Barricade),
+        );
+
+        let passes = |class, coord: (usize, usize)| {
+            let (path, _) = map.find_wave_path(class).unwrap();
+            path.contains(&coord.into())
+        };
+
+        assert!(passes(MovementClass::Walker, (1, 2)));
+        assert!(passes(MovementClass::Swimmer, (1, 0)));
+        assert!(passes(MovementClass::Flier, (1, 1)));
+        assert!(passes(MovementClass::Burrower, (1, 1)));
+    }
+
+    #[test]
+    fn diagonal_steps_follow_the_movement_rules() {
+        let mut map = Map::new((3, 3));
+        map.wave_entry_coord = (0, 0).into();
+        map.wave_exit_coord = (2, 2).into();
+        let class = MovementClass::Walker;
+        assert_eq!(map.find_wave_path(class).unwrap().1, 4 * STEP_COST_SCALE);
+
+        map.movement_rules.neighbourhood = Neighbourhood::EightWay;
+        let (path, cost) = map.find_wave_path(class).unwrap();
+        assert_eq!(path, vec![(0, 0).into(), (1, 1).into(), (2, 2).into()]);
+        assert_eq!(cost, 28);
+
+        // The barricade stops the first diagonal step squeezing past it.
+        map.set_tile((1, 0).into(), None, Some(Structure::Barricade));
+        let (path, cost) = map.find_wave_path(class).unwrap();
+        assert_eq!(path[1], (0, 1).into());
+        assert_eq!(cost, 34);
+
+        map.movement_rules.corner_cutting = true;
+        assert_eq!(map.find_wave_path(class).unwrap().1, 28);
+    }
+
+    #[test]
+    fn barricades_cannot_cut_off_the_exit() {
+        let mut map = Map::new((3, 2));
+        map.wave_entry_coord = (0, 0).into();
+        map.wave_exit_coord = (2, 0).into();
+
+        assert!(map.can_place_structure((1, 0).into(), Structure::Barricade));
+        map.set_tile((1, 0).into(), None, Some(Structure::Barricade));
+
+        // The only remaining route goes through (1, 1)
+        assert!(!map.can_place_structure((1, 1).into(), Structure::Barricade));
+        assert!(!map.can_place_structure(map.wave_exit_coord, Structure::Barricade));
+        assert!(map.can_place_structure((1, 1).into(), Structure::None));
+    }
+
+    #[test]
+    fn map_file_round_trip() {
+        let mut map = Map::new((3, 2));
+        map.set_tile(
+            (1, 0).into(),
+            Some(TileType::Rock),
+            Some(Structure::Barricade),
+        );
+        map.set_tile((2, 1).into(), Some(TileType::Water), None);
+        map.wave_entry_coord = (0, 1).into();
+        map.wave_exit_coord = (2, 0).into();
+        map.movement_rules = MovementRules {
+            neighbourhood: Neighbourhood::EightWay,
+            diagonal_cost: 1.5,
+            corner_cutting: true,
+        };
+
+        let mut afflictions = vec![ElementalAffliction::empty(); map.tile_count()];
+        afflictions[1].add_element(Element::Fire, 12);
+        afflictions[1].add_element(Element::Air, 3);
+
+        let (loaded, loaded_afflictions) = map_from_json(&map_to_json(&map, &afflictions)).unwrap();
+
+        assert_eq!(loaded.dimensions, map.dimensions);
+        assert_eq!(loaded.wave_entry_coord, map.wave_entry_coord);
+        assert_eq!(loaded.wave_exit_coord, map.wave_exit_coord);
+        assert_eq!(loaded.movement_rules, map.movement_rules);
+        (0..map.tile_count()).for_each(|idx| {
+            assert_eq!(loaded.tile_type_at_index(idx), map.tile_type_at_index(idx));
+            assert_eq!(loaded.structure_at_index(idx), map.structure_at_index(idx));
+        });
+        assert_eq!(loaded_afflictions, afflictions);
+    }
+
+    #[test]
+    fn empty_maps_round_trip() {
+        let (loaded, afflictions) = map_from_json(&map_to_json(&Map::empty(), &[])).unwrap();
+
+        assert!(loaded.is_empty());
+        assert!(afflictions.is_empty());
+    }
+
+    #[test]
+    fn map_file_errors_name_the_bad_tile() {
+        let map = Map::new((2, 1));
+        let mut value = map_to_json(&map, &[]);
+        value["tiles"][1]["tile_type"] = "Lava".into();
+
+        match map_from_json(&value) {
+            Err(MapFileError::UnknownTileType { index, name }) => {
+                assert_eq!(index, 1);
+                assert_eq!(name, "Lava");
+            }
+            other => panic!("Expected an unknown tile type error, got {other:?}"),
+        }
+
+        value["dimensions"] = serde_json::json!([u64::MAX, 2]);
+        assert!(matches!(
+            map_from_json(&value),
+            Err(MapFileError::DimensionsTooLarge(_))
+        ));
+
+        value["dimensions"] = serde_json::json!([1_000_000_000, 1_000_000_000]);
+        assert!(matches!(
+            map_from_json(&value),
+            Err(MapFileError::TileCountMismatch { found: 2, .. })
+        ));
+
+        value["format_version"] = (MAP_FORMAT_VERSION + 1).into();
+        assert!(matches!(
+            map_from_json(&value),
+            Err(MapFileError::UnsupportedVersion(_))
+        ));
+    }
+
+    #[test]
+    fn diagonal_costs_stay_in_range() {
+        let mut value = map_to_json(&Map::new((2, 1)), &[]);
+
+        [0.5, -1.0, 4.5, 1e30].into_iter().for_each(|cost| {
+            value["movement"]["diagonal_cost"] = cost.into();
+            assert!(
+                matches!(
+                    map_from_json(&value),
+                    Err(MapFileError::InvalidMovementRules(_))
+                ),
+                "Diagonal cost {cost} should be rejected"
+            );
+        });
+
+        value["movement"]["diagonal_cost"] = 4.0.into();
+        let (loaded, _) = map_from_json(&value).unwrap();
+        let expected = MovementRules {
+            diagonal_cost: 4.0,
+            ..MovementRules::default()
+        };
+        assert_eq!(loaded.movement_rules, expected);
+    }
 }
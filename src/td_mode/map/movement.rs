@@ -0,0 +1,150 @@
+use super::*;
+
+/// How an enemy gets around the map, which decides what each tile costs it to cross.
+#[derive(Clone, Copy, Component, Debug, PartialEq, Eq, Hash)]
+pub enum MovementClass {
+    /// Walks over the terrain and around structures.
+    Walker,
+    /// Swims through Water and struggles on land.
+    Swimmer,
+    /// Flies over everything, terrain and structures alike.
+    Flier,
+    /// Digs under Rock and anything built on it, and walks everywhere else.
+    Burrower,
+}
+
+impl MovementClass {
+    pub fn all() -> [Self; 4] {
+        [Self::Walker, Self::Swimmer, Self::Flier, Self::Burrower]
+    }
+
+    fn display_name(&self) -> &str {
+        match *self {
+            Self::Walker => "Walker",
+            Self::Swimmer => "Swimmer",
+            Self::Flier => "Flier",
+            Self::Burrower => "Burrower",
+        }
+    }
+
+    /// The cost to move onto a tile with this terrain and structure.
+    /// Returns None if the tile can't be entered at all.
+    pub fn tile_cost(&self, tile_type: TileType, structure: Structure) -> Option<u32> {
+        match (*self, tile_type) {
+            (Self::Flier, _) | (Self::Burrower, TileType::Rock) => Some(1),
+            (Self::Swimmer, TileType::Water) => Some(structure.astar_cost()? + 1),
+            (Self::Swimmer, TileType::Ice | TileType::Mud) => Some(structure.astar_cost()? + 5),
+            (Self::Swimmer, _) => Some(structure.astar_cost()? + tile_type.astar_cost() * 4),
+            (Self::Walker | Self::Burrower, _) => {
+                Some(structure.astar_cost()? + tile_type.astar_cost())
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for MovementClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.display_name())
+    }
+}
+
+/// Which of a tile's neighbours enemies can step to and elements can spread to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Neighbourhood {
+    /// The four tiles sharing an edge
+    FourWay,
+    /// The four tiles sharing an edge and the four sharing a corner
+    EightWay,
+}
+
+impl Neighbourhood {
+    pub fn all() -> [Self; 2] {
+        [Self::FourWay, Self::EightWay]
+    }
+
+    fn display_name(&self) -> &str {
+        match *self {
+            Self::FourWay => "4-Way",
+            Self::EightWay => "8-Way",
+        }
+    }
+
+    /// Look up a neighbourhood by the name it is displayed with.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::all().into_iter().find(|n| n.display_name() == name)
+    }
+
+    pub fn directions(&self) -> &'static [GridDirection] {
+        match *self {
+            Self::FourWay => &GridDirection::CARDINALS,
+            Self::EightWay => &GridDirection::ALL,
+        }
+    }
+
+    /// How many steps it takes to get between two tiles.
+    pub fn distance(&self, a: Coordinate, b: Coordinate) -> u32 {
+        match *self {
+            Self::FourWay => a.distance(&b),
+            Self::EightWay => a.x.abs_diff(b.x).max(a.y.abs_diff(b.y)) as u32,
+        }
+    }
+}
+
+impl std::fmt::Display for Neighbourhood {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.display_name())
+    }
+}
+
+/// Route costs count each step as this many times the cost of entering its tile, so a diagonal
+/// step can cost a fraction more than a straight one.
+pub const STEP_COST_SCALE: u32 = 10;
+
+/// The diagonal costs a map can use. Keeping them small keeps route costs far from overflowing.
+pub const DIAGONAL_COST_RANGE: std::ops::RangeInclusive<f32> = 1.0..=4.0;
+
+/// Map-wide rules for moving between tiles.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MovementRules {
+    pub neighbourhood: Neighbourhood,
+    /// What a diagonal step costs compared to a straight step onto the same tile
+    pub diagonal_cost: f32,
+    /// Whether a diagonal step can squeeze past a tile that can't be entered on either side of it
+    pub corner_cutting: bool,
+}
+
+impl Default for MovementRules {
+    fn default() -> Self {
+        Self {
+            neighbourhood: Neighbourhood::FourWay,
+            diagonal_cost: std::f32::consts::SQRT_2,
+            corner_cutting: false,
+        }
+    }
+}
+
+impl MovementRules {
+    /// What a step in `direction` onto a tile costing `cost` to enter adds to a route.
+    pub fn step_cost(self, cost: u32, direction: GridDirection) -> u32 {
+        if direction.is_diagonal() {
+            ((cost.saturating_mul(STEP_COST_SCALE) as f32 * self.diagonal_cost).round() as u32)
+                .max(1)
+        } else {
+            cost.saturating_mul(STEP_COST_SCALE)
+        }
+    }
+
+    /// The least a route between two tiles can cost, for guiding A*.
+    pub fn min_route_cost(self, a: Coordinate, b: Coordinate) -> u32 {
+        let cheapest_step = match self.neighbourhood {
+            Neighbourhood::FourWay => STEP_COST_SCALE,
+            Neighbourhood::EightWay => {
+                STEP_COST_SCALE.min(self.step_cost(1, GridDirection::SouthEast))
+            }
+        };
+
+        self.neighbourhood
+            .distance(a, b)
+            .saturating_mul(cheapest_step)
+    }
+}
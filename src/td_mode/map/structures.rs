@@ -4,41 +4,209 @@ pub struct StructuresPlugin;
 
 impl Plugin for StructuresPlugin {
     fn build(&self, app: &mut App) {
-        app.add_startup_system(setup).add_system_to_stage(
-            CoreStage::Last,
-            place_portals.run_in_state(GameState::TDMode),
-        );
+        app.add_startup_system(setup)
+            // This has to be in PreUpdate for the same reason as the tile models.
+            .add_system_to_stage(CoreStage::PreUpdate, update_structure_model)
+            .add_system_to_stage(
+                CoreStage::Last,
+                place_portals.run_in_state(GameState::TDMode),
+            );
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Component, Debug, PartialEq)]
 pub enum Structure {
     None,
     Barricade,
+    Tower(TowerType),
 }
 
 impl Structure {
-    pub fn all() -> [Self; 2] {
-        [Self::None, Self::Barricade]
+    pub fn all() -> [Self; 5] {
+        [
+            Self::None,
+            Self::Barricade,
+            Self::Tower(TowerType::Short),
+            Self::Tower(TowerType::Medium),
+            Self::Tower(TowerType::Tall),
+        ]
+    }
+
+    /// The extra cost for enemies to path through a tile with this structure on it.
+    /// Returns None if the structure can't be pathed through at all.
+    pub fn astar_cost(&self) -> Option<u32> {
+        match *self {
+            Self::None => Some(0),
+            Self::Barricade | Self::Tower(_) => None,
+        }
+    }
+
+    fn display_name(&self) -> &str {
+        match *self {
+            Self::None => "None",
+            Self::Barricade => "Barricade",
+            Self::Tower(TowerType::Short) => "Short Tower",
+            Self::Tower(TowerType::Medium) => "Medium Tower",
+            Self::Tower(TowerType::Tall) => "Tall Tower",
+        }
+    }
+
+    /// Look up a structure by the name it is displayed with.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::all().into_iter().find(|s| s.display_name() == name)
+    }
+}
+
+impl std::fmt::Display for Structure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.display_name())
+    }
+}
+
+/// The three tower bodies. Each one fires in its own way.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TowerType {
+    Short,
+    Medium,
+    Tall,
+}
+
+impl TowerType {
+    pub fn all() -> [Self; 3] {
+        [Self::Short, Self::Medium, Self::Tall]
+    }
+
+    fn display_name(&self) -> &str {
+        match *self {
+            Self::Short => "Short",
+            Self::Medium => "Medium",
+            Self::Tall => "Tall",
+        }
+    }
+
+    /// Look up a tower type by the name it is displayed with.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::all().into_iter().find(|t| t.display_name() == name)
+    }
+
+    /// How tall the placeholder model for the tower is.
+    fn model_height(&self) -> f32 {
+        match *self {
+            Self::Short => 0.4,
+            Self::Medium => 0.8,
+            Self::Tall => 1.2,
+        }
     }
 }
 
+impl std::fmt::Display for TowerType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.display_name())
+    }
+}
+
+/// The model drawn on top of a tile for a structure.
+enum StructureModel {
+    Scene(Handle<Scene>),
+    /// A plain mesh for structures that don't have a proper model yet, offset so it sits on
+    /// the tile.
+    Placeholder(Handle<Mesh>, Handle<StandardMaterial>, Vec3),
+}
+
 struct StructureModels {
     wave_entry: Handle<Scene>,
     wave_exit: Handle<Scene>,
     barricade: Handle<Scene>,
+    tower_meshes: [Handle<Mesh>; 3],
+    tower_material: Handle<StandardMaterial>,
+}
+
+impl StructureModels {
+    fn model_for_structure(&self, structure: Structure) -> Option<StructureModel> {
+        match structure {
+            Structure::None => None,
+            Structure::Barricade => Some(StructureModel::Scene(self.barricade.clone())),
+            Structure::Tower(tower_type) => Some(StructureModel::Placeholder(
+                self.tower_meshes[tower_type as usize].clone(),
+                self.tower_material.clone(),
+                Vec3::Y * tower_type.model_height() / 2.0,
+            )),
+        }
+    }
 }
 
-fn setup(assets: Res<AssetServer>, mut commands: Commands) {
+fn setup(
+    assets: Res<AssetServer>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut commands: Commands,
+) {
+    let tower_meshes = TowerType::all().map(|tower_type| {
+        meshes.add(Mesh::from(shape::Box::new(
+            0.4,
+            tower_type.model_height(),
+            0.4,
+        )))
+    });
+
     let structure_models = StructureModels {
         wave_entry: assets.load("models/wave_portal.glb#Scene0"),
         wave_exit: assets.load("models/wave_portal.glb#Scene0"),
         barricade: assets.load("models/barricade.glb#Scene0"),
+        tower_meshes,
+        tower_material: materials.add(Color::rgb(0.55, 0.55, 0.6).into()),
     };
 
     commands.insert_resource(structure_models);
 }
 
+/// A pointer to the base of the scene for the structure sitting on a tile.
+///
+/// Kept separate from the tile's `ModelRoot` so terrain and structures can be swapped independently.
+#[derive(Component)]
+pub struct StructureModelRoot(pub Entity);
+
+fn update_structure_model(
+    tile_query: Query<
+        (Entity, &Structure, Option<&StructureModelRoot>),
+        (With<Tile>, Changed<Structure>),
+    >,
+    models: Res<StructureModels>,
+    mut commands: Commands,
+) {
+    tile_query.iter().for_each(|(e, structure, existing_root)| {
+        if let Some(existing_root) = existing_root {
+            commands.entity(existing_root.0).despawn_recursive();
+        }
+
+        if let Some(model) = models.model_for_structure(*structure) {
+            let new_root_e = commands
+                .spawn()
+                .insert(Parent(e))
+                .insert_bundle(TransformBundle::identity())
+                .with_children(|p| match model {
+                    StructureModel::Scene(scene) => {
+                        p.spawn_scene(scene);
+                    }
+                    StructureModel::Placeholder(mesh, material, offset) => {
+                        p.spawn_bundle(PbrBundle {
+                            mesh,
+                            material,
+                            transform: Transform::from_translation(offset),
+                            ..default()
+                        });
+                    }
+                })
+                .id();
+
+            commands.entity(e).insert(StructureModelRoot(new_root_e));
+            trace!("Spawned the {structure} model for tile entity: {e:?}");
+        } else {
+            commands.entity(e).remove::<StructureModelRoot>();
+        }
+    });
+}
+
 /// A Tag Component for the entry and exit portals
 #[derive(Component)]
 struct Portal;
@@ -19,23 +19,37 @@ impl Plugin for TilePlugin {
 #[derive(Component)]
 pub struct Tile;
 
+/// How many tile types there are, for tables indexed by `TileType`.
+pub const TILE_TYPE_COUNT: usize = 10;
+
 #[derive(Copy, Clone, Component, PartialEq, Debug)]
 pub enum TileType {
     Rock,
     Water,
     Air,
     Fire,
+    /// Bare ground, which is also what's left once a forest burns down
     Barren,
+    Forest,
+    Grass,
+    Ice,
+    Mud,
+    Sand,
 }
 
 impl TileType {
-    pub fn all() -> [TileType; 5] {
+    pub fn all() -> [TileType; TILE_TYPE_COUNT] {
         [
             TileType::Rock,
             TileType::Water,
             TileType::Air,
             TileType::Fire,
             TileType::Barren,
+            TileType::Forest,
+            TileType::Grass,
+            TileType::Ice,
+            TileType::Mud,
+            TileType::Sand,
         ]
     }
 
@@ -46,15 +60,53 @@ impl TileType {
             TileType::Air => "Air",
             TileType::Fire => "Fire",
             TileType::Barren => "Barren",
+            TileType::Forest => "Forest",
+            TileType::Grass => "Grass",
+            TileType::Ice => "Ice",
+            TileType::Mud => "Mud",
+            TileType::Sand => "Sand",
         }
     }
 
+    /// Look up a tile type by the name it is displayed with.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::all().into_iter().find(|t| t.display_name() == name)
+    }
+
     pub fn astar_cost(&self) -> u32 {
         match *self {
             TileType::Water | TileType::Fire => 100,
             TileType::Air => 9999,
+            TileType::Mud => 20,
+            TileType::Forest => 10,
+            TileType::Sand => 8,
+            TileType::Ice | TileType::Grass => 2,
             TileType::Barren => 1,
-            _ => 5,
+            TileType::Rock => 5,
+        }
+    }
+
+    /// The share of each spreading element on a tile of this type that leaks into each of its
+    /// neighbours every second.
+    pub fn spread_rate(&self) -> f32 {
+        match *self {
+            TileType::Fire | TileType::Air => 1.0,
+            TileType::Forest => 0.75,
+            TileType::Rock | TileType::Grass | TileType::Sand => 0.5,
+            TileType::Water | TileType::Barren | TileType::Ice | TileType::Mud => 0.25,
+        }
+    }
+
+    /// The most of an element a tile of this type can build up from its neighbours.
+    ///
+    /// Elements applied directly aren't limited by this.
+    pub fn spread_cap(&self, element: Element) -> u32 {
+        match (*self, element) {
+            (TileType::Water | TileType::Ice, Element::Fire) => 0,
+            (TileType::Mud, Element::Fire) => 10,
+            (TileType::Fire, _) => 200,
+            (TileType::Barren | TileType::Sand, _) => 40,
+            _ => 100,
         }
     }
 }
@@ -72,6 +124,11 @@ struct TileModels {
     air: Handle<Scene>,
     fire: Handle<Scene>,
     barren: Handle<Scene>,
+    forest: Handle<Scene>,
+    grass: Handle<Scene>,
+    ice: Handle<Scene>,
+    mud: Handle<Scene>,
+    sand: Handle<Scene>,
 }
 
 impl TileModels {
@@ -82,6 +139,11 @@ impl TileModels {
             TileType::Air => self.air.clone(),
             TileType::Fire => self.fire.clone(),
             TileType::Barren => self.barren.clone(),
+            TileType::Forest => self.forest.clone(),
+            TileType::Grass => self.grass.clone(),
+            TileType::Ice => self.ice.clone(),
+            TileType::Mud => self.mud.clone(),
+            TileType::Sand => self.sand.clone(),
         }
     }
 }
@@ -94,6 +156,11 @@ fn setup(assets: Res<AssetServer>, mut commands: Commands) {
         air: assets.load("models/tile_air.glb#Scene0"),
         fire: assets.load("models/tile_fire.glb#Scene0"),
         barren: assets.load("models/tile_barren.glb#Scene0"),
+        forest: assets.load("models/tile_forest.glb#Scene0"),
+        grass: assets.load("models/tile_grass.glb#Scene0"),
+        ice: assets.load("models/tile_ice.glb#Scene0"),
+        mud: assets.load("models/tile_mud.glb#Scene0"),
+        sand: assets.load("models/tile_sand.glb#Scene0"),
     };
 
     commands.insert_resource(tile_models);
@@ -1,4 +1,46 @@
+//! Messages between systems
+//!
+//! A message is an entity tagged with `Message`, a component saying what kind of message it is,
+//! and whatever else that kind needs, like a `Target`. Every kind of message is registered with
+//! the message bus along with the system that handles it:
+//!
+//! ```ignore
+//! app.add_message(MessageKind::<ApplyElement>::handled_by(handle_apply_element_messages)
+//!     .consolidate::<ElementalAffliction>());
+//! ```
+//!
+//! The bus is a single stage, `MESSAGE_STAGE`, that runs once a frame. Inside it each kind gets
+//! its own pair of stages in the order the kinds were registered: one to merge messages sent to
+//! the same target, if the kind asked for that, and one for its handler. Since they're separate
+//! stages, everything a handler does is in place before the next kind is handled, and any
+//! messages it sends to a later kind are handled in the same pass.
+//!
+//! Handlers mark the messages they've dealt with `Handled`. Once every kind has been handled the
+//! handled messages are despawned. Messages sent after the bus has run wait for the next frame.
+//!
+//! The bus also keeps an eye on messages that go astray, logging each one and counting them in
+//! `MessageStats`:
+//! * Messages whose `Target` has been despawned are dropped as orphaned before their kind is
+//!   handled, so handlers never touch dead entities.
+//! * A message its handler leaves unhandled gets another pass, up to its kind's max age, and is
+//!   then dropped as unhandled. Messages carrying more than one kind's component, like area
+//!   messages that are also `ApplyElement` messages, are aged once against the largest max age
+//!   among their kinds.
+//! * Messages that aren't of any registered kind are dropped straight away.
+
 use super::*;
+use bevy::ecs::entity::Entities;
+use bevy::ecs::schedule::{IntoSystemDescriptor, SystemDescriptor};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+/// The stage messages are processed in. Anything sending messages that should be handled the
+/// same frame needs to run before it.
+pub const MESSAGE_STAGE: &str = "messages";
+
+/// How many passes of the bus a message can wait to be handled unless its kind says otherwise.
+/// With 1 a message has to be handled the first time the bus sees it.
+pub const DEFAULT_MAX_MESSAGE_AGE: u32 = 1;
 
 #[derive(Component)]
 pub struct Message;
@@ -15,6 +57,336 @@ pub struct Target(pub Entity);
 #[derive(Component)]
 pub struct Source(pub Entity);
 
+/// Component for messages dealing damage to their target. Messages aimed at a tile damage every
+/// enemy standing on it. The message's Source, if it has one, is credited with the damage.
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct Damage {
+    pub amount: f32,
+    /// The element the damage is made of, for resistances and vulnerabilities. Damage without
+    /// one only goes through armour.
+    pub element: Option<Element>,
+}
+
+/// How many passes of the bus a message has been left unhandled for.
+#[derive(Component, Debug)]
+pub struct MessageAge(pub u32);
+
+/// The most passes an unhandled message can wait, noted down by each of its kinds before it's
+/// aged, along with the kind that lets it wait longest.
+#[derive(Component, Debug)]
+struct MaxMessageAge {
+    passes: u32,
+    kind: &'static str,
+}
+
+/// Running totals of the messages the bus has had to throw away.
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub struct MessageStats {
+    /// Messages that weren't of any registered kind
+    pub dropped: u32,
+    /// Messages whose `Target` was despawned before they were handled
+    pub orphaned: u32,
+    /// Messages that were still unhandled when they reached their kind's max age
+    pub unhandled: u32,
+}
+
+/// A part of a message that can be merged with the same part of other messages sent to the same
+/// target.
+pub trait Consolidate: Component + Copy {
+    fn consolidate(&mut self, other: &Self);
+}
+
+/// How one kind of message is processed. `M` is the component that marks a message as this
+/// kind.
+pub struct MessageKind<M: Component + Clone> {
+    handler: SystemDescriptor,
+    consolidator: Option<SystemDescriptor>,
+    max_age: u32,
+    kind: PhantomData<M>,
+}
+
+impl<M: Component + Clone> MessageKind<M> {
+    /// Messages of this kind are passed to `handler`, which should mark each one it deals with
+    /// as `Handled`.
+    pub fn handled_by<Params>(handler: impl IntoSystemDescriptor<Params>) -> Self {
+        Self {
+            handler: handler.into_descriptor(),
+            consolidator: None,
+            max_age: DEFAULT_MAX_MESSAGE_AGE,
+            kind: PhantomData,
+        }
+    }
+
+    /// Before they're handled, merge every message of this kind sent to the same `Target` into
+    /// a single message by consolidating their `P`s. The merged message has no `Source`.
+    pub fn consolidate<P: Consolidate>(mut self) -> Self {
+        self.consolidator = Some(
+            consolidate_messages::<M, P>
+                .run_in_state(GameState::TDMode)
+                .into_descriptor(),
+        );
+        self
+    }
+
+    /// Let messages of this kind wait up to `passes` passes of the bus to be handled, for
+    /// handlers that sometimes can't deal with a message yet.
+    pub fn max_age(mut self, passes: u32) -> Self {
+        self.max_age = passes;
+        self
+    }
+}
+
+/// The stages inside the message bus.
+#[derive(StageLabel, Debug, Clone, PartialEq, Eq, Hash)]
+enum MessageStep {
+    DropOrphans(&'static str),
+    Consolidate(&'static str),
+    Handle(&'static str),
+    /// Every kind's handler has run by here, so every message that's going to be handled this
+    /// pass has been.
+    AllHandled,
+    NoteMaxAge(&'static str),
+    Age,
+    Finish,
+}
+
+/// Create an empty message bus, to be added to a schedule as `MESSAGE_STAGE`.
+pub fn message_bus() -> Schedule {
+    let mut finish_stage = SystemStage::single_threaded();
+    finish_stage
+        .add_system(report_unknown_messages.run_in_state(GameState::TDMode))
+        .add_system(
+            drop_unknown_messages
+                .run_in_state(GameState::TDMode)
+                .after(report_unknown_messages),
+        )
+        .add_system(clear_handled_messages.run_in_state(GameState::TDMode));
+
+    let mut bus = Schedule::default();
+    bus.add_stage(MessageStep::AllHandled, SystemStage::single_threaded())
+        .add_stage(
+            MessageStep::Age,
+            SystemStage::single_threaded()
+                .with_system(age_messages.run_in_state(GameState::TDMode)),
+        )
+        .add_stage(MessageStep::Finish, finish_stage);
+
+    bus
+}
+
+/// Registering kinds of message with the message bus.
+pub trait AddMessage {
+    /// Register a kind of message. Kinds are handled in the order they're registered in.
+    ///
+    /// Panics if there's no `MESSAGE_STAGE` to register with.
+    fn add_message<M: Component + Clone>(&mut self, kind: MessageKind<M>) -> &mut Self;
+}
+
+impl AddMessage for Schedule {
+    fn add_message<M: Component + Clone>(&mut self, kind: MessageKind<M>) -> &mut Self {
+        let name = std::any::type_name::<M>();
+        let max_age = kind.max_age;
+
+        self.stage(MESSAGE_STAGE, |bus: &mut Schedule| {
+            bus.add_stage_before(
+                MessageStep::AllHandled,
+                MessageStep::DropOrphans(name),
+                SystemStage::single_threaded()
+                    .with_system(drop_orphaned_messages::<M>.run_in_state(GameState::TDMode)),
+            );
+
+            if let Some(consolidator) = kind.consolidator {
+                bus.add_stage_before(
+                    MessageStep::AllHandled,
+                    MessageStep::Consolidate(name),
+                    SystemStage::single_threaded().with_system(consolidator),
+                );
+            }
+
+            // Each kind notes its max age in a stage of its own, so a message of several kinds
+            // ends up with the largest of them.
+            bus.add_stage_before(
+                MessageStep::AllHandled,
+                MessageStep::Handle(name),
+                SystemStage::single_threaded().with_system(kind.handler),
+            )
+            .add_stage_before(
+                MessageStep::Age,
+                MessageStep::NoteMaxAge(name),
+                SystemStage::single_threaded().with_system(
+                    (move |message_query: Query<
+                        (Entity, Option<&MaxMessageAge>),
+                        (With<Message>, With<M>, Without<Handled>),
+                    >,
+                           commands: Commands| {
+                        note_max_age::<M>(max_age, &message_query, commands);
+                    })
+                    .run_in_state(GameState::TDMode),
+                ),
+            )
+        })
+    }
+}
+
+impl AddMessage for App {
+    fn add_message<M: Component + Clone>(&mut self, kind: MessageKind<M>) -> &mut Self {
+        self.schedule.add_message(kind);
+        self
+    }
+}
+
+/// Replace every unhandled `M` message with one per target, carrying the consolidated `P` of
+/// all the messages sent to it.
+fn consolidate_messages<M: Component + Clone, P: Consolidate>(
+    message_query: Query<(Entity, &Target, &M, &P), (With<Message>, Without<Handled>)>,
+    mut commands: Commands,
+) {
+    let mut totals: HashMap<Entity, (M, P)> = HashMap::new();
+    message_query
+        .iter()
+        .for_each(|(entity, target, kind, part)| {
+            totals
+                .entry(target.0)
+                .and_modify(|(_, total)| total.consolidate(part))
+                .or_insert_with(|| (kind.clone(), *part));
+
+            commands.entity(entity).despawn_recursive();
+        });
+
+    // The kind goes in first, so kinds that are their own payload end up with the total.
+    totals.into_iter().for_each(|(target, (kind, total))| {
+        commands
+            .spawn()
+            .insert(Message)
+            .insert(Target(target))
+            .insert(kind)
+            .insert(total);
+    });
+}
+
+/// Drop `M` messages whose target no longer exists.
+fn drop_orphaned_messages<M: Component>(
+    message_query: Query<(Entity, &Target), (With<Message>, With<M>, Without<Handled>)>,
+    entities: &Entities,
+    mut stats: ResMut<MessageStats>,
+    mut commands: Commands,
+) {
+    message_query
+        .iter()
+        .filter(|(_, target)| !entities.contains(target.0))
+        .for_each(|(message_entity, target)| {
+            stats.orphaned += 1;
+            warn!(
+                "Dropped {} message {message_entity:?} to despawned {:?} ({} orphaned so far)",
+                short_name::<M>(),
+                target.0,
+                stats.orphaned
+            );
+            commands.entity(message_entity).despawn();
+        });
+}
+
+/// Raise the max age of one kind's unhandled messages to `max_age`, unless another of their
+/// kinds lets them wait longer.
+fn note_max_age<M: Component>(
+    max_age: u32,
+    message_query: &Query<
+        (Entity, Option<&MaxMessageAge>),
+        (With<Message>, With<M>, Without<Handled>),
+    >,
+    mut commands: Commands,
+) {
+    message_query
+        .iter()
+        .filter(|(_, noted)| noted.map_or(0, |noted| noted.passes) < max_age)
+        .for_each(|(message_entity, _)| {
+            commands.entity(message_entity).insert(MaxMessageAge {
+                passes: max_age,
+                kind: short_name::<M>(),
+            });
+        });
+}
+
+/// Age the unhandled messages of every registered kind, dropping the ones that have waited as
+/// many passes as their kinds allow.
+fn age_messages(
+    message_query: Query<
+        (Entity, &MaxMessageAge, Option<&MessageAge>),
+        (With<Message>, Without<Handled>),
+    >,
+    mut stats: ResMut<MessageStats>,
+    mut commands: Commands,
+) {
+    message_query
+        .iter()
+        .for_each(|(message_entity, max_age, age)| {
+            let age = age.map_or(0, |age| age.0) + 1;
+            if age < max_age.passes {
+                commands
+                    .entity(message_entity)
+                    .insert(MessageAge(age))
+                    .remove::<MaxMessageAge>();
+                return;
+            }
+
+            stats.unhandled += 1;
+            warn!(
+                "Dropped {} message {message_entity:?} after {age} unhandled passes ({} unhandled so far)",
+                max_age.kind,
+                stats.unhandled
+            );
+            commands.entity(message_entity).despawn();
+        });
+}
+
+/// Log the messages that aren't of any kind registered with the bus, by their components since
+/// there's no kind to name them by. Messages of a registered kind have been either handled or
+/// aged by now.
+fn report_unknown_messages(
+    message_query: Query<Entity, (With<Message>, Without<Handled>, Without<MessageAge>)>,
+    world: &World,
+) {
+    message_query.iter().for_each(|message_entity| {
+        let components: Vec<&str> = world
+            .entities()
+            .get(message_entity)
+            .and_then(|location| world.archetypes().get(location.archetype_id))
+            .map(|archetype| {
+                archetype
+                    .components()
+                    .filter_map(|id| world.components().get_info(id))
+                    .map(|info| short_name_of(info.name()))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        warn!(
+            "Dropped message {message_entity:?} that nothing handles: {}",
+            components.join(", ")
+        );
+    });
+}
+
+/// Get rid of the messages `report_unknown_messages` reported.
+fn drop_unknown_messages(
+    message_query: Query<Entity, (With<Message>, Without<Handled>, Without<MessageAge>)>,
+    mut stats: ResMut<MessageStats>,
+    mut commands: Commands,
+) {
+    message_query.iter().for_each(|message_entity| {
+        stats.dropped += 1;
+        commands.entity(message_entity).despawn();
+    });
+}
+
+fn short_name<T>() -> &'static str {
+    short_name_of(std::any::type_name::<T>())
+}
+
+fn short_name_of(type_name: &str) -> &str {
+    type_name.rsplit("::").next().unwrap_or_default()
+}
+
 pub fn clear_handled_messages(
     m_query: Query<Entity, (With<Message>, With<Handled>)>,
     mut commands: Commands,
@@ -23,3 +395,182 @@ pub fn clear_handled_messages(
         commands.entity(e).despawn();
     });
 }
+
+/// Fail a test if the bus has had to drop any messages since `world` was set up.
+#[cfg(test)]
+pub fn assert_no_leaked_messages(world: &World) {
+    let stats = world.resource::<MessageStats>();
+    assert_eq!(*stats, MessageStats::default(), "messages were dropped");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A message that passes itself on as points to the same target.
+    #[derive(Component, Clone)]
+    struct Relay(f32);
+
+    #[derive(Component, Clone, Copy)]
+    struct Points(f32);
+
+    impl Consolidate for Points {
+        fn consolidate(&mut self, other: &Self) {
+            self.0 += other.0;
+        }
+    }
+
+    /// The points handled, in the order they were handled.
+    struct Received(Vec<(Entity, f32)>);
+
+    fn relay(
+        message_query: Query<(Entity, &Target, &Relay), (With<Message>, Without<Handled>)>,
+        mut commands: Commands,
+    ) {
+        message_query.iter().for_each(|(entity, target, relay)| {
+            commands
+                .spawn()
+                .insert(Message)
+                .insert(Target(target.0))
+                .insert(Points(relay.0));
+            commands.entity(entity).insert(Handled);
+        });
+    }
+
+    fn receive(
+        message_query: Query<(Entity, &Target, &Points), (With<Message>, Without<Handled>)>,
+        mut received: ResMut<Received>,
+        mut commands: Commands,
+    ) {
+        message_query.iter().for_each(|(entity, target, points)| {
+            received.0.push((target.0, points.0));
+            commands.entity(entity).insert(Handled);
+        });
+    }
+
+    /// Handles points messages every other pass, like a handler waiting on something.
+    fn receive_slowly(
+        message_query: Query<(Entity, &Target, &Points), (With<Message>, Without<Handled>)>,
+        received: ResMut<Received>,
+        commands: Commands,
+        mut ready: Local<bool>,
+    ) {
+        if *ready {
+            receive(message_query, received, commands);
+        }
+        *ready = !*ready;
+    }
+
+    fn bus_world() -> World {
+        let mut world = World::new();
+        world.insert_resource(CurrentState(GameState::TDMode));
+        world.insert_resource(Received(Vec::new()));
+        world.insert_resource(MessageStats::default());
+        world
+    }
+
+    #[test]
+    fn messages_are_consolidated_handled_in_order_and_cleared() {
+        let mut world = bus_world();
+
+        let mut schedule = Schedule::default();
+        schedule
+            .add_stage(MESSAGE_STAGE, message_bus())
+            .add_message(MessageKind::<Relay>::handled_by(relay))
+            .add_message(MessageKind::<Points>::handled_by(receive).consolidate::<Points>());
+
+        let target = world.spawn().id();
+        world
+            .spawn()
+            .insert(Message)
+            .insert(Target(target))
+            .insert(Points(2.0));
+        world
+            .spawn()
+            .insert(Message)
+            .insert(Target(target))
+            .insert(Relay(3.0));
+
+        schedule.run(&mut world);
+
+        // The relayed points were merged with the points already on their way in the same pass.
+        assert_eq!(world.resource::<Received>().0, [(target, 5.0)]);
+        assert_eq!(
+            world
+                .query_filtered::<Entity, With<Message>>()
+                .iter(&world)
+                .count(),
+            0
+        );
+        assert_no_leaked_messages(&world);
+    }
+
+    #[test]
+    fn stray_messages_are_counted_and_dropped() {
+        let mut world = bus_world();
+        let mut schedule = Schedule::default();
+        schedule
+            .add_stage(MESSAGE_STAGE, message_bus())
+            .add_message(MessageKind::<Points>::handled_by(receive));
+
+        let despawned = world.spawn().id();
+        world.despawn(despawned);
+        let target = world.spawn().id();
+
+        world
+            .spawn()
+            .insert(Message)
+            .insert(Target(despawned))
+            .insert(Points(1.0));
+        world.spawn().insert(Message).insert(Target(target));
+        schedule.run(&mut world);
+
+        assert!(world.resource::<Received>().0.is_empty());
+        assert_eq!(
+            *world.resource::<MessageStats>(),
+            MessageStats {
+                dropped: 1,
+                orphaned: 1,
+                unhandled: 0,
+            }
+        );
+        assert_eq!(world.query::<&Message>().iter(&world).count(), 0);
+    }
+
+    #[test]
+    fn messages_wait_up_to_their_max_age() {
+        let send = |world: &mut World, target: Entity| {
+            world
+                .spawn()
+                .insert(Message)
+                .insert(Target(target))
+                .insert(Points(1.0));
+        };
+
+        // Given a second pass, the slow handler gets to the message.
+        let mut world = bus_world();
+        let mut schedule = Schedule::default();
+        schedule
+            .add_stage(MESSAGE_STAGE, message_bus())
+            .add_message(MessageKind::<Points>::handled_by(receive_slowly).max_age(2));
+        let target = world.spawn().id();
+        send(&mut world, target);
+        schedule.run(&mut world);
+        schedule.run(&mut world);
+        assert_eq!(world.resource::<Received>().0, [(target, 1.0)]);
+        assert_no_leaked_messages(&world);
+
+        // With the default max age it's dropped before it gets the chance.
+        let mut world = bus_world();
+        let mut schedule = Schedule::default();
+        schedule
+            .add_stage(MESSAGE_STAGE, message_bus())
+            .add_message(MessageKind::<Points>::handled_by(receive_slowly));
+        let target = world.spawn().id();
+        send(&mut world, target);
+        schedule.run(&mut world);
+        schedule.run(&mut world);
+        assert!(world.resource::<Received>().0.is_empty());
+        assert_eq!(world.resource::<MessageStats>().unhandled, 1);
+    }
+}
@@ -7,10 +7,14 @@ use crate::prelude::*;
 
 mod camera;
 mod elements;
+mod enemies;
+mod knights;
 mod map;
 mod messages;
 mod raycast;
 mod sandbox;
+mod simulation;
+mod towers;
 
 mod td_mode_prelude {
     pub use super::elements::*;
@@ -20,20 +24,30 @@ mod td_mode_prelude {
 
 use td_mode_prelude::*;
 
+pub use messages::MessageStats;
+pub use simulation::run_simulation;
+
 pub struct TDModePlugin;
 
 impl Plugin for TDModePlugin {
     fn build(&self, app: &mut App) {
+        // The message bus goes in first so every plugin can register its messages with it.
+        app.add_stage_before(
+            CoreStage::Update,
+            messages::MESSAGE_STAGE,
+            messages::message_bus(),
+        )
+        .init_resource::<messages::MessageStats>();
+
         app.add_plugin(camera::TDCameraPlugin)
             .add_plugin(map::MapPlugin)
             .add_plugin(raycast::PickablePlugin)
             .add_plugin(sandbox::SandboxPlugin)
             .add_plugin(elements::ElementPlugin)
+            .add_plugin(enemies::EnemyPlugin)
+            .add_plugin(knights::KnightPlugin)
+            .add_plugin(towers::TowerPlugin)
             .add_enter_system(GameState::TDMode, setup)
-            .add_system_to_stage(
-                CoreStage::First,
-                messages::clear_handled_messages.run_in_state(GameState::TDMode),
-            )
             .add_system(
                 go_to_main_menu
                     .run_in_state(GameState::TDMode)
@@ -1,14 +1,20 @@
 //! Code for the map editor/sandbox tools
 
 use super::td_mode_prelude::*;
-use super::{elements::ApplyElementMessage, elements::ElementalAffliction, *};
+use super::{
+    elements::ApplyElementMessage, elements::ElementalAffliction, elements::ReactionEvent, *,
+};
 use bevy_egui::{egui, EguiContext};
-use map::{MapRoot, TileType};
+use map::{MapRoot, MovementClass, Neighbourhood, Tile, TileType};
+use std::collections::VecDeque;
 
 const FIXED_STEP_MS: u64 = 20;
 const APPLICATOR_ELEMENTS_PER_SECOND: u32 = 10;
 const APPLICATOR_ELEMENTS_PER_FRAME: f32 =
     (APPLICATOR_ELEMENTS_PER_SECOND as f32) / (1000 / FIXED_STEP_MS) as f32;
+const DEFAULT_MAP_FILE_PATH: &str = "assets/maps/sandbox.json";
+/// How many reaction events the sandbox keeps around to show.
+const REACTION_LOG_LENGTH: usize = 10;
 
 pub struct SandboxPlugin;
 
@@ -17,13 +23,19 @@ impl Plugin for SandboxPlugin {
         app.insert_resource(SandboxControlState::new())
             .add_system(sandbox_ui.run_in_state(GameState::TDMode))
             .add_system(place_debug_cubes_along_path.run_in_state(GameState::TDMode))
-            .add_system(tile_inspector_ui.run_in_state(GameState::TDMode));
+            .add_system(tile_inspector_ui.run_in_state(GameState::TDMode))
+            .add_system(enemy_inspector_ui.run_in_state(GameState::TDMode))
+            .add_system(wave_editor_ui.run_in_state(GameState::TDMode))
+            .add_message(MessageKind::<ReactionEvent>::handled_by(
+                log_reaction_events.run_in_state(GameState::TDMode),
+            ));
 
         let mut fixed_stage = SystemStage::parallel();
         fixed_stage.add_system(use_tool.run_in_state(GameState::TDMode));
 
+        // Elements applied with the tools land the same frame.
         app.add_stage_before(
-            CoreStage::Update,
+            MESSAGE_STAGE,
             "tool_fixed_stage",
             FixedTimestepStage::new(Duration::from_millis(FIXED_STEP_MS)).with_stage(fixed_stage),
         );
@@ -35,6 +47,13 @@ struct SandboxControlState {
     current_tool: Tool,
     selected_tile: Option<Entity>,
     redraw_path: bool,
+    /// The movement classes whose wave routes are drawn on the map
+    path_classes: Vec<MovementClass>,
+    map_file_path: String,
+    /// The result of the last save or load, shown under the file controls.
+    map_file_status: Option<String>,
+    /// The most recent reaction events, oldest first
+    reaction_log: VecDeque<String>,
 }
 
 impl SandboxControlState {
@@ -44,6 +63,10 @@ impl SandboxControlState {
             current_tool: Tool::Select,
             selected_tile: None,
             redraw_path: true,
+            path_classes: vec![MovementClass::Walker],
+            map_file_path: DEFAULT_MAP_FILE_PATH.to_string(),
+            map_file_status: None,
+            reaction_log: VecDeque::new(),
         }
     }
 }
@@ -65,10 +88,16 @@ enum TilePiece {
     PathEnd,
 }
 
+#[allow(clippy::too_many_arguments)]
 fn sandbox_ui(
+    affliction_query: Query<(&Coordinate, &ElementalAffliction), With<Tile>>,
+    map_root_query: Query<Entity, With<MapRoot>>,
+    schedule: Res<enemies::WaveSchedule>,
+    flow_fields: Res<map::FlowFields>,
     mut control_state: ResMut<SandboxControlState>,
     mut egui_context: ResMut<EguiContext>,
     mut map: ResMut<map::Map>,
+    mut commands: Commands,
 ) {
     egui::Window::new("Sandbox Tools").show(egui_context.ctx_mut(), |ui| {
         ui.heading("Map");
@@ -86,6 +115,17 @@ fn sandbox_ui(
             map.resize(control_state.new_dimensions);
         }
 
+        movement_rules_ui(ui, &mut map);
+
+        map_file_ui(
+            ui,
+            &affliction_query,
+            &mut control_state,
+            &mut map,
+            &schedule,
+            &mut commands,
+        );
+
         ui.heading("Tools");
 
         if ui.button("Select").clicked() {
@@ -105,6 +145,19 @@ fn sandbox_ui(
             })
         });
 
+        let s_brush_text = if let Tool::StructureBrush(structure) = control_state.current_tool {
+            format!("{structure}")
+        } else {
+            "Structure Brush".to_string()
+        };
+        ui.menu_button(s_brush_text, |ui| {
+            Structure::all().iter().for_each(|s| {
+                if ui.button(format!("{s}")).clicked() {
+                    control_state.current_tool = Tool::StructureBrush(*s);
+                }
+            });
+        });
+
         let e_brush_text = if let Tool::ElementApplicator(element, _) = control_state.current_tool {
             format!("{element}")
         } else {
@@ -126,19 +179,299 @@ fn sandbox_ui(
                 control_state.current_tool = Tool::PlacePiece(TilePiece::PathEnd);
             }
         });
+
+        path_classes_ui(ui, &mut control_state);
+
+        ui.heading("Enemies");
+        ui.menu_button("Spawn Enemy", |ui| {
+            enemies::EnemyKind::all().into_iter().for_each(|kind| {
+                if ui.button(format!("{kind}")).clicked() {
+                    if let Ok(map_root) = map_root_query.get_single() {
+                        enemies::spawn_enemy(
+                            &mut commands,
+                            map_root,
+                            enemies::EnemyBundle::at_wave_entry(&map, &flow_fields, kind),
+                        );
+                    }
+                }
+            });
+        });
+
+        ui.heading("Reactions");
+        if control_state.reaction_log.is_empty() {
+            ui.label("No reactions yet");
+        }
+        control_state.reaction_log.iter().for_each(|event| {
+            ui.label(event);
+        });
+    });
+}
+
+/// Keep track of the latest reaction events to show in the sandbox tools.
+fn log_reaction_events(
+    event_query: Query<(Entity, &ReactionEvent, &Source), (With<Message>, Without<Handled>)>,
+    tile_query: Query<&Coordinate, With<Tile>>,
+    mut control_state: ResMut<SandboxControlState>,
+    mut commands: Commands,
+) {
+    event_query
+        .iter()
+        .for_each(|(message_entity, event, source)| {
+            let log = &mut control_state.reaction_log;
+            match tile_query.get(source.0) {
+                Ok(coord) => log.push_back(format!("{} at {coord}", event.0)),
+                Err(_) => log.push_back(event.0.clone()),
+            }
+            if log.len() > REACTION_LOG_LENGTH {
+                log.pop_front();
+            }
+
+            commands.entity(message_entity).insert(Handled);
+        });
+}
+
+/// A window for editing the map's wave schedule and starting waves early.
+fn wave_editor_ui(
+    mut schedule: ResMut<enemies::WaveSchedule>,
+    mut state: ResMut<enemies::WaveState>,
+    leaks: Res<enemies::Leaks>,
+    damage_totals: Res<enemies::DamageTotals>,
+    source_query: Query<(&Coordinate, Option<&towers::Tower>)>,
+    mut egui_context: ResMut<EguiContext>,
+) {
+    egui::Window::new("Waves").show(egui_context.ctx_mut(), |ui| {
+        let wave_text = state
+            .current_wave
+            .map_or("None".to_string(), |w| format!("{}", w + 1));
+        ui.label(format!(
+            "Current Wave: {wave_text} / {}",
+            schedule.waves.len()
+        ));
+        if let Some(time_left) = state.time_to_next_wave {
+            ui.label(format!("Next Wave In: {time_left:.1}s"));
+        }
+        ui.label(format!("Enemies Remaining: {}", state.enemies_remaining));
+        ui.label(format!("Leaks: {}", leaks.0));
+
+        egui::CollapsingHeader::new("Damage This Wave").show(ui, |ui| {
+            damage_totals.sources.iter().for_each(|(source, totals)| {
+                let name = match source_query.get(*source) {
+                    Ok((coord, Some(_))) => format!("Tower at {coord}"),
+                    Ok((coord, None)) => format!("Reactions at {coord}"),
+                    Err(_) => "Something gone".to_string(),
+                };
+                ui.label(format!(
+                    "{name}: {:.0} damage, {} kills, {:.0} overkill",
+                    totals.dealt, totals.kills, totals.overkill
+                ));
+            });
+        });
+
+        if let Some(next) = state.next_wave(&schedule) {
+            if ui.button(format!("Start Wave {}", next + 1)).clicked() {
+                state.start_wave(&schedule, next);
+            }
+        }
+
+        ui.separator();
+        ui.add(
+            egui::DragValue::new(&mut schedule.time_between_waves)
+                .speed(0.1)
+                .clamp_range(0.0..=120.0)
+                .prefix("Time Between Waves: ")
+                .suffix("s"),
+        );
+
+        let mut wave_to_remove = None;
+        schedule
+            .waves
+            .iter_mut()
+            .enumerate()
+            .for_each(|(wave_idx, wave)| {
+                let header = format!("Wave {} ({} enemies)", wave_idx + 1, wave.enemy_count());
+                egui::CollapsingHeader::new(header)
+                    .id_source(wave_idx)
+                    .show(ui, |ui| {
+                        let mut group_to_remove = None;
+                        wave.groups
+                            .iter_mut()
+                            .enumerate()
+                            .for_each(|(group_idx, group)| {
+                                ui.horizontal(|ui| {
+                                    spawn_group_ui(ui, group);
+                                    if ui.button("X").clicked() {
+                                        group_to_remove = Some(group_idx);
+                                    }
+                                });
+                            });
+
+                        if let Some(group_idx) = group_to_remove {
+                            wave.groups.remove(group_idx);
+                        }
+
+                        ui.horizontal(|ui| {
+                            if ui.button("Add Group").clicked() {
+                                wave.groups.push(enemies::SpawnGroup {
+                                    kind: enemies::EnemyKind::Grunt,
+                                    count: 1,
+                                    interval: 1.0,
+                                    delay: 0.0,
+                                });
+                            }
+                            if ui.button("Remove Wave").clicked() {
+                                wave_to_remove = Some(wave_idx);
+                            }
+                        });
+                    });
+            });
+
+        if let Some(wave_idx) = wave_to_remove {
+            schedule.waves.remove(wave_idx);
+            *state = enemies::WaveState::default();
+        }
+
+        if ui.button("Add Wave").clicked() {
+            schedule.waves.push(enemies::Wave::default());
+        }
+    });
+}
+
+fn spawn_group_ui(ui: &mut egui::Ui, group: &mut enemies::SpawnGroup) {
+    ui.menu_button(format!("{}", group.kind), |ui| {
+        enemies::EnemyKind::all().into_iter().for_each(|kind| {
+            if ui.button(format!("{kind}")).clicked() {
+                group.kind = kind;
+            }
+        });
+    });
+    ui.add(
+        egui::DragValue::new(&mut group.count)
+            .clamp_range(1..=500)
+            .suffix("x"),
+    );
+    ui.add(
+        egui::DragValue::new(&mut group.interval)
+            .speed(0.05)
+            .clamp_range(0.0..=30.0)
+            .prefix("every ")
+            .suffix("s"),
+    );
+    ui.add(
+        egui::DragValue::new(&mut group.delay)
+            .speed(0.1)
+            .clamp_range(0.0..=300.0)
+            .prefix("after ")
+            .suffix("s"),
+    );
+}
+
+/// Controls for saving the current map and its waves to disk and loading them back.
+fn map_file_ui(
+    ui: &mut egui::Ui,
+    affliction_query: &Query<(&Coordinate, &ElementalAffliction), With<Tile>>,
+    control_state: &mut SandboxControlState,
+    map: &mut ResMut<map::Map>,
+    schedule: &enemies::WaveSchedule,
+    commands: &mut Commands,
+) {
+    let wave_path = enemies::wave_file_path(&control_state.map_file_path);
+
+    ui.text_edit_singleline(&mut control_state.map_file_path);
+    ui.horizontal(|ui| {
+        if ui.button("Save").clicked() {
+            let mut afflictions = vec![ElementalAffliction::empty(); map.tile_count()];
+            affliction_query.iter().for_each(|(coord, affliction)| {
+                if let Some(slot) = afflictions.get_mut(map.coord_to_idx(*coord)) {
+                    *slot = *affliction;
+                }
+            });
+
+            let saved = map::save_map(&control_state.map_file_path, map, &afflictions)
+                .map_err(|e| e.to_string())
+                .and_then(|()| {
+                    enemies::save_waves(&wave_path, schedule).map_err(|e| e.to_string())
+                });
+
+            let status = match saved {
+                Ok(()) => format!("Saved to {}", control_state.map_file_path),
+                Err(e) => {
+                    error!("Failed to save map: {e}");
+                    format!("Save failed: {e}")
+                }
+            };
+            control_state.map_file_status = Some(status);
+        }
+
+        if ui.button("Load").clicked() {
+            // Maps without a wave file keep whatever schedule is currently being edited.
+            let loaded = map::load_map(&control_state.map_file_path)
+                .map_err(|e| e.to_string())
+                .and_then(|(loaded_map, afflictions)| {
+                    if wave_path.exists() {
+                        let loaded_waves = enemies::load_waves(&wave_path)
+                            .map_err(|e| format!("{}: {e}", wave_path.display()))?;
+                        commands.insert_resource(loaded_waves);
+                    }
+                    Ok((loaded_map, afflictions))
+                });
+
+            let status = match loaded {
+                Ok((loaded_map, afflictions)) => {
+                    commands.insert_resource(enemies::WaveState::default());
+                    commands.insert_resource(map::FlowFields::default());
+                    control_state.new_dimensions = loaded_map.dimensions;
+                    control_state.selected_tile = None;
+                    control_state.redraw_path = true;
+                    **map = loaded_map;
+                    commands.insert_resource(map::LoadedAfflictions(afflictions));
+                    format!("Loaded {}", control_state.map_file_path)
+                }
+                Err(e) => {
+                    error!("Failed to load map: {e}");
+                    format!("Load failed: {e}")
+                }
+            };
+            control_state.map_file_status = Some(status);
+        }
     });
+
+    if let Some(status) = &control_state.map_file_status {
+        ui.label(status);
+    }
 }
 
 fn tile_inspector_ui(
-    tile_query: Query<(&TileType, &Coordinate, Option<&ElementalAffliction>)>,
+    mut tile_query: Query<(
+        &TileType,
+        &Structure,
+        &Coordinate,
+        Option<&ElementalAffliction>,
+        Option<&mut towers::Tower>,
+    )>,
+    map: Res<map::Map>,
+    mut roster: ResMut<knights::KnightRoster>,
+    mut gold: ResMut<towers::Gold>,
+    upgrade_table: Res<towers::UpgradeTable>,
     control_state: Res<SandboxControlState>,
     mut egui_context: ResMut<EguiContext>,
 ) {
     if let Some(tile_entity) = control_state.selected_tile {
-        if let Ok((tile_type, coord, elements)) = tile_query.get(tile_entity) {
+        if let Ok((tile_type, structure, coord, elements, tower)) = tile_query.get_mut(tile_entity)
+        {
             egui::Window::new("Tile Inspector").show(egui_context.ctx_mut(), |ui| {
                 ui.label(format!("Coordinates: {coord}"));
                 ui.label(format!("Tile Type: {tile_type}"));
+                ui.label(format!("Structure: {structure}"));
+
+                if let Some(mut tower) = tower {
+                    // Knights in reserve wait at the exit portal they're guarding.
+                    tower_ui(ui, &mut tower, *coord, &mut roster, map.wave_exit_coord);
+
+                    if let Some(knight) = roster.stationed_at(*coord) {
+                        let path = upgrade_table.path_for(knight, tower.kind);
+                        tower_upgrades_ui(ui, &mut tower, knight.id, path, &mut gold);
+                    }
+                }
 
                 if let Some(applied_elements) = elements {
                     ui.label("Applied Elements:");
@@ -149,6 +482,204 @@ fn tile_inspector_ui(
     }
 }
 
+/// Controls for the map's movement rules. The map is only written to when a rule changes, so
+/// routes aren't rebuilt every frame.
+fn movement_rules_ui(ui: &mut egui::Ui, map: &mut ResMut<map::Map>) {
+    let mut rules = map.movement_rules;
+
+    ui.horizontal(|ui| {
+        ui.label("Movement:");
+        Neighbourhood::all().into_iter().for_each(|neighbourhood| {
+            ui.radio_value(
+                &mut rules.neighbourhood,
+                neighbourhood,
+                neighbourhood.to_string(),
+            );
+        });
+    });
+    ui.add(
+        egui::Slider::new(&mut rules.diagonal_cost, map::DIAGONAL_COST_RANGE).text("Diagonal Cost"),
+    );
+    ui.checkbox(&mut rules.corner_cutting, "Corner Cutting");
+
+    if rules != map.movement_rules {
+        map.movement_rules = rules;
+    }
+}
+
+/// Checkboxes for which movement classes' routes the path preview shows.
+fn path_classes_ui(ui: &mut egui::Ui, control_state: &mut SandboxControlState) {
+    ui.label("Show routes for:");
+    ui.horizontal(|ui| {
+        MovementClass::all().into_iter().for_each(|class| {
+            let mut shown = control_state.path_classes.contains(&class);
+            let [r, g, b, _] = path_colour(class).as_rgba_f32();
+            let label =
+                egui::RichText::new(format!("{class}")).color(egui::Rgba::from_rgb(r, g, b));
+            if ui.checkbox(&mut shown, label).changed() {
+                control_state.path_classes.retain(|&c| c != class);
+                if shown {
+                    control_state.path_classes.push(class);
+                }
+                control_state.redraw_path = true;
+            }
+        });
+    });
+}
+
+/// Lists every enemy on the map along with its elements and status effects.
+fn enemy_inspector_ui(
+    enemy_query: Query<
+        (
+            Entity,
+            &Name,
+            &enemies::Health,
+            &enemies::MoveSpeed,
+            &enemies::StatusEffects,
+            Option<&ElementalAffliction>,
+        ),
+        With<enemies::Enemy>,
+    >,
+    mut egui_context: ResMut<EguiContext>,
+) {
+    egui::Window::new("Enemy Inspector").show(egui_context.ctx_mut(), |ui| {
+        if enemy_query.is_empty() {
+            ui.label("No enemies on the map");
+        }
+
+        enemy_query
+            .iter()
+            .for_each(|(e, name, health, speed, status, elements)| {
+                // Enemies of the same kind share a name, so the entity keeps their headers apart.
+                egui::CollapsingHeader::new(format!("{name}"))
+                    .id_source(e)
+                    .show(ui, |ui| {
+                        ui.label(format!("Health: {:.0} / {:.0}", health.current, health.max));
+                        ui.label(format!(
+                            "Speed: {:.2} tiles/s",
+                            speed.0 * status.speed_multiplier()
+                        ));
+                        if let Some(applied_elements) = elements {
+                            ui.label("Applied Elements:");
+                            ui.label(format!("{applied_elements}"));
+                        }
+                        ui.label("Status Effects:");
+                        ui.label(format!("{status}"));
+                    });
+            });
+    });
+}
+
+/// Shows a tower's stats and lets its targeting policy and knight be changed.
+fn tower_ui(
+    ui: &mut egui::Ui,
+    tower: &mut towers::Tower,
+    coord: Coordinate,
+    roster: &mut knights::KnightRoster,
+    reserve_coord: Coordinate,
+) {
+    let stats = roster
+        .stationed_at(coord)
+        .map_or(tower.stats, |knight| tower.stats_with_knight(knight));
+    ui.label(format!("Attack: {}", stats.attack));
+    ui.label(format!("Range: {:.1} tiles", stats.range));
+    ui.label(format!("Fire Rate: {:.1}/s", stats.fire_rate));
+    ui.label(format!("Damage: {:.0}", stats.damage));
+    ui.label(format!(
+        "Applies: {} {}",
+        stats.element_amount, stats.element
+    ));
+
+    egui::ComboBox::from_label("Targeting")
+        .selected_text(tower.targeting.to_string())
+        .show_ui(ui, |ui| {
+            towers::Targeting::all().into_iter().for_each(|targeting| {
+                ui.selectable_value(&mut tower.targeting, targeting, targeting.to_string());
+            });
+        });
+
+    ui.separator();
+    let knight_text = match roster.assigned_to(coord) {
+        Some(id) => {
+            let name = roster.get(id).map_or("Unknown", |k| k.name.as_str());
+            match roster.location(id) {
+                Some(knights::KnightLocation::Travelling { remaining, .. }) => {
+                    format!("{name} (arriving in {remaining:.1}s)")
+                }
+                _ => name.to_string(),
+            }
+        }
+        None => "None (tower is inert)".to_string(),
+    };
+    ui.label(format!("Knight: {knight_text}"));
+
+    ui.horizontal(|ui| {
+        ui.menu_button("Assign Knight", |ui| {
+            let mut chosen = None;
+            roster.iter().for_each(|(knight, location)| {
+                let location_text = match location {
+                    knights::KnightLocation::Reserve => "in reserve".to_string(),
+                    knights::KnightLocation::Stationed(at) => format!("at {at}"),
+                    knights::KnightLocation::Travelling { to, .. } => format!("heading to {to}"),
+                };
+
+                if ui
+                    .button(format!("{} ({location_text})", knight.name))
+                    .clicked()
+                {
+                    chosen = Some(knight.id);
+                }
+            });
+
+            if let Some(id) = chosen {
+                if let Err(e) = roster.assign(id, coord, reserve_coord) {
+                    warn!("Could not assign knight: {e}");
+                }
+                ui.close_menu();
+            }
+        });
+
+        if roster.assigned_to(coord).is_some() && ui.button("Send to Reserve").clicked() {
+            roster.unassign_from(coord);
+        }
+    });
+}
+
+/// Buttons for buying the next tier in each of a tower's upgrade lines.
+fn tower_upgrades_ui(
+    ui: &mut egui::Ui,
+    tower: &mut towers::Tower,
+    knight: knights::KnightId,
+    path: &towers::UpgradePath,
+    gold: &mut towers::Gold,
+) {
+    ui.separator();
+    ui.horizontal(|ui| {
+        ui.label(format!("Gold: {}", gold.0));
+        if ui.button("+500").clicked() {
+            gold.0 += 500;
+        }
+    });
+
+    towers::UpgradeLine::all().into_iter().for_each(|line| {
+        let tiers = tower.upgrade_tiers(knight, line);
+        let total = path.line(line).len();
+        match path.next_tier(tower, knight, line) {
+            Some((tier, cost)) => {
+                let text = format!("{line} {tiers}/{total}: {} ({cost} gold)", tier.name);
+                if ui.button(text).clicked() {
+                    if let Err(e) = path.purchase(tower, knight, line, gold) {
+                        warn!("Could not buy upgrade: {e}");
+                    }
+                }
+            }
+            None => {
+                ui.label(format!("{line} {tiers}/{total}: Maxed"));
+            }
+        }
+    });
+}
+
 use raycast::CursorState;
 fn use_tool(
     button: Res<Input<MouseButton>>,
@@ -179,7 +710,11 @@ fn use_tool(
                     let prev_structure = map.structure_at_coord(coord).unwrap();
 
                     if *prev_structure != structure {
-                        map.set_tile(coord, None, Some(structure));
+                        if map.can_place_structure(coord, structure) {
+                            map.set_tile(coord, None, Some(structure));
+                        } else if button.just_pressed(MouseButton::Left) {
+                            warn!("Placing a {structure} at {coord} would block the wave path.");
+                        }
                     }
                 }
 
@@ -227,6 +762,16 @@ fn use_tool(
 #[derive(Component)]
 struct DebugPoint;
 
+/// The colour each movement class's route is drawn in.
+fn path_colour(class: MovementClass) -> Color {
+    match class {
+        MovementClass::Walker => Color::rgb(1.0, 0.0, 0.0),
+        MovementClass::Swimmer => Color::rgb(0.0, 0.4, 1.0),
+        MovementClass::Flier => Color::rgb(1.0, 1.0, 1.0),
+        MovementClass::Burrower => Color::rgb(0.6, 0.4, 0.1),
+    }
+}
+
 fn place_debug_cubes_along_path(
     debug_obj_query: Query<Entity, With<DebugPoint>>,
     mut meshes: ResMut<Assets<Mesh>>,
@@ -236,37 +781,37 @@ fn place_debug_cubes_along_path(
     mut commands: Commands,
 ) {
     if control_state.redraw_path || map.is_changed() {
-        let start_coord = map.wave_entry_coord;
-        let end_coord = map.wave_exit_coord;
-        let result = astar(
-            &start_coord,
-            |p| map.find_astar_successors(*p),
-            |p| p.distance(&end_coord),
-            |p| *p == end_coord,
-        );
-
-        if let Some(path) = result {
-            debug_obj_query.iter().for_each(|e| {
-                commands.entity(e).despawn_recursive();
-            });
+        debug_obj_query.iter().for_each(|e| {
+            commands.entity(e).despawn_recursive();
+        });
 
-            path.0.iter().for_each(|coord| {
-                let tlation = (*coord * Vec3::new(1.0, 0.0, 1.0))
-                    + Vec3::new(
-                        map.dimensions.0 as f32 * -0.5,
-                        0.0,
-                        map.dimensions.1 as f32 * -0.5,
-                    );
-                commands
-                    .spawn_bundle(PbrBundle {
-                        mesh: meshes.add(Mesh::from(shape::Cube { size: 0.25 })),
-                        material: materials.add(Color::rgb(1.0, 0.0, 0.0).into()),
-                        transform: Transform::from_translation(tlation),
-                        ..default()
-                    })
-                    .insert(DebugPoint);
+        control_state
+            .path_classes
+            .iter()
+            .enumerate()
+            .for_each(|(i, &class)| {
+                if let Some(path) = map.find_wave_path(class) {
+                    // Raise each route a little so overlapping routes can all be seen.
+                    let height = i as f32 * 0.1;
+                    let material = materials.add(path_colour(class).into());
+                    path.0.iter().for_each(|coord| {
+                        let tlation = (*coord * Vec3::new(1.0, 0.0, 1.0))
+                            + Vec3::new(
+                                map.dimensions.0 as f32 * -0.5,
+                                height,
+                                map.dimensions.1 as f32 * -0.5,
+                            );
+                        commands
+                            .spawn_bundle(PbrBundle {
+                                mesh: meshes.add(Mesh::from(shape::Cube { size: 0.25 })),
+                                material: material.clone(),
+                                transform: Transform::from_translation(tlation),
+                                ..default()
+                            })
+                            .insert(DebugPoint);
+                    });
+                }
             });
-        }
 
         control_state.redraw_path = false;
     }
@@ -0,0 +1,187 @@
+//! Reading the elements a simulation applies, and when
+//!
+//! Applications are stored as JSON in the following shape:
+//!
+//! ```json
+//! {
+//!     "format_version": 1,
+//!     "applications": [
+//!         { "tick": 0, "coord": [2, 1], "elements": { "Fire": 60 } },
+//!         { "tick": 25, "coord": [2, 1], "elements": { "Water": 30 } }
+//!     ]
+//! }
+//! ```
+//!
+//! Each application is sent to its tile at the start of its tick, counting from 0. They can be
+//! listed in any order.
+
+use super::*;
+use std::path::Path;
+
+/// The version of application file this build understands.
+pub const APPLICATION_FORMAT_VERSION: u64 = 1;
+
+/// Elements to send to a tile partway through a simulation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Application {
+    pub tick: u32,
+    pub coord: Coordinate,
+    pub elements: ElementalAffliction,
+}
+
+/// Everything that can go wrong reading an application file.
+#[derive(Debug)]
+pub enum ApplicationFileError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    UnsupportedVersion(u64),
+    MissingField(&'static str),
+    InvalidApplication { index: usize, reason: &'static str },
+    UnknownElement { index: usize, name: String },
+}
+
+impl std::fmt::Display for ApplicationFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "Could not access application file: {e}"),
+            Self::Json(e) => write!(f, "Application file is not valid JSON: {e}"),
+            Self::UnsupportedVersion(v) => write!(
+                f,
+                "Application format version {v} is not supported (expected {APPLICATION_FORMAT_VERSION})"
+            ),
+            Self::MissingField(field) => {
+                write!(f, "Application file is missing the field '{field}'")
+            }
+            Self::InvalidApplication { index, reason } => {
+                write!(f, "Application {index}: {reason}")
+            }
+            Self::UnknownElement { index, name } => {
+                write!(f, "Application {index}: unknown element '{name}'")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ApplicationFileError {}
+
+impl From<std::io::Error> for ApplicationFileError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ApplicationFileError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+pub fn load_applications(path: impl AsRef<Path>) -> Result<Vec<Application>, ApplicationFileError> {
+    let contents = std::fs::read_to_string(path)?;
+    let value: Value = serde_json::from_str(&contents)?;
+
+    applications_from_json(&value)
+}
+
+pub fn applications_from_json(value: &Value) -> Result<Vec<Application>, ApplicationFileError> {
+    let version = value
+        .get("format_version")
+        .and_then(Value::as_u64)
+        .ok_or(ApplicationFileError::MissingField("format_version"))?;
+    if version != APPLICATION_FORMAT_VERSION {
+        return Err(ApplicationFileError::UnsupportedVersion(version));
+    }
+
+    value
+        .get("applications")
+        .and_then(Value::as_array)
+        .ok_or(ApplicationFileError::MissingField("applications"))?
+        .iter()
+        .enumerate()
+        .map(|(index, application)| application_from_json(index, application))
+        .collect()
+}
+
+fn application_from_json(
+    index: usize,
+    application: &Value,
+) -> Result<Application, ApplicationFileError> {
+    let invalid = |reason| ApplicationFileError::InvalidApplication { index, reason };
+
+    let tick = application
+        .get("tick")
+        .and_then(Value::as_u64)
+        .and_then(|t| u32::try_from(t).ok())
+        .ok_or_else(|| invalid("'tick' must be a positive integer"))?;
+    let coord = application
+        .get("coord")
+        .and_then(Value::as_array)
+        .and_then(|pair| match pair.as_slice() {
+            [x, y] => Some((x.as_u64()? as usize, y.as_u64()? as usize)),
+            _ => None,
+        })
+        .ok_or_else(|| invalid("'coord' must be a pair of positive integers"))?;
+    let elements = application
+        .get("elements")
+        .and_then(Value::as_object)
+        .ok_or_else(|| invalid("'elements' must be an object"))?;
+
+    let mut affliction = ElementalAffliction::empty();
+    for (element_name, amount) in elements {
+        let element = Element::from_name(element_name).ok_or_else(|| {
+            ApplicationFileError::UnknownElement {
+                index,
+                name: element_name.clone(),
+            }
+        })?;
+        let amount = amount
+            .as_u64()
+            .and_then(|a| u32::try_from(a).ok())
+            .ok_or_else(|| invalid("element amounts must be positive integers"))?;
+
+        affliction.add_element(element, amount);
+    }
+
+    Ok(Application {
+        tick,
+        coord: coord.into(),
+        elements: affliction,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn application_file_errors_name_the_bad_application() {
+        let file = |coord: Value| {
+            json!({
+                "format_version": 1,
+                "applications": [
+                    { "tick": 3, "coord": [1, 2], "elements": { "Fire": 60 } },
+                    { "tick": 5, "coord": coord, "elements": { "Water": 10 } },
+                ],
+            })
+        };
+
+        let applications = applications_from_json(&file(json!([0, 0]))).unwrap();
+        assert_eq!(
+            applications[0],
+            Application {
+                tick: 3,
+                coord: (1, 2).into(),
+                elements: ElementalAffliction::single(Element::Fire, 60),
+            }
+        );
+        assert!(matches!(
+            applications_from_json(&file(json!([0]))),
+            Err(ApplicationFileError::InvalidApplication { index: 1, .. })
+        ));
+        assert!(matches!(
+            applications_from_json(&file(json!([-1, 0]))),
+            Err(ApplicationFileError::InvalidApplication { index: 1, .. })
+        ));
+    }
+}
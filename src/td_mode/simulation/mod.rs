@@ -0,0 +1,541 @@
+//! Running the chemistry on a map without opening a window
+//!
+//! `twelve-knights-vigil simulate` loads a map file and an optional list of timed element
+//! applications, steps the element systems for a set number of ticks, and prints the tiles at
+//! the end or after every tick. The schedule is built with the same `add_element_stages` the
+//! game uses, so a reaction table tuned here behaves the same way in game.
+//!
+//! ```sh
+//! cargo run -- simulate assets/maps/chemistry_demo.json \
+//!     --apply assets/simulations/chemistry_demo.json --ticks 250 --trace
+//! ```
+
+mod application_file;
+
+use super::enemies::{add_damage_messages, DamageTotals};
+use super::map::{update_changed_tiles, MapRoot, Tile, TileType};
+use super::td_mode_prelude::*;
+use crate::prelude::*;
+use anyhow::{bail, Context};
+use application_file::load_applications;
+use serde_json::{json, Value};
+
+/// A frame at the fixed timestep the rest of the game runs at.
+const DEFAULT_TICK_MS: u64 = 20;
+const DEFAULT_TICKS: u32 = 100;
+
+const USAGE: &str = "\
+Usage: twelve-knights-vigil simulate <map file> [options]
+
+Options:
+    --apply <file>       Elements to apply to tiles, and on which ticks
+    --reactions <file>   Reaction table to use instead of the game's
+    --ticks <count>      How many ticks to run (default 100)
+    --tick-ms <ms>       Game time that passes each tick (default 20)
+    --format <format>    'ascii' or 'json' (default ascii)
+    --trace              Print the tiles after every tick instead of only at the end";
+
+/// How the simulation prints the state of the map.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum OutputFormat {
+    /// A grid of tile symbols followed by the elements on each tile
+    Ascii,
+    /// One map file per line, with the tick and its reaction events alongside
+    Json,
+}
+
+/// What to simulate and how to report it, read from the command line.
+#[derive(Debug, PartialEq)]
+struct SimulationOptions {
+    map_path: String,
+    applications_path: Option<String>,
+    reactions_path: String,
+    ticks: u32,
+    tick_ms: u64,
+    format: OutputFormat,
+    trace: bool,
+}
+
+fn parse_args(args: &[String]) -> Result<SimulationOptions, String> {
+    let mut map_path = None;
+    let mut options = SimulationOptions {
+        map_path: String::new(),
+        applications_path: None,
+        reactions_path: REACTION_FILE_PATH.to_string(),
+        ticks: DEFAULT_TICKS,
+        tick_ms: DEFAULT_TICK_MS,
+        format: OutputFormat::Ascii,
+        trace: false,
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("'{arg}' needs a value"))
+        };
+        match arg.as_str() {
+            "--apply" => options.applications_path = Some(value()?),
+            "--reactions" => options.reactions_path = value()?,
+            "--ticks" => {
+                options.ticks = value()?
+                    .parse()
+                    .map_err(|_| "'--ticks' must be a whole number".to_string())?;
+            }
+            "--tick-ms" => {
+                options.tick_ms = value()?
+                    .parse()
+                    .ok()
+                    .filter(|&ms| ms > 0)
+                    .ok_or_else(|| "'--tick-ms' must be a whole number above 0".to_string())?;
+            }
+            "--format" => {
+                options.format = match value()?.as_str() {
+                    "ascii" => OutputFormat::Ascii,
+                    "json" => OutputFormat::Json,
+                    other => return Err(format!("Unknown format '{other}'")),
+                };
+            }
+            "--trace" => options.trace = true,
+            _ if arg.starts_with("--") => return Err(format!("Unknown option '{arg}'")),
+            _ if map_path.is_none() => map_path = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument '{arg}'")),
+        }
+    }
+
+    options.map_path = map_path.ok_or_else(|| "No map file given".to_string())?;
+    Ok(options)
+}
+
+/// Run the `simulate` command with the arguments that followed it.
+pub fn run_simulation(args: &[String]) -> anyhow::Result<()> {
+    let options = parse_args(args).map_err(|e| anyhow::anyhow!("{e}\n\n{USAGE}"))?;
+
+    let (map, afflictions) = load_map(&options.map_path)
+        .with_context(|| format!("Failed to load map from {}", options.map_path))?;
+    let mut applications = match &options.applications_path {
+        Some(path) => load_applications(path)
+            .with_context(|| format!("Failed to load applications from {path}"))?,
+        None => Vec::new(),
+    };
+    if let Some(outside) = applications
+        .iter()
+        .find(|a| a.coord.x >= map.dimensions.0 || a.coord.y >= map.dimensions.1)
+    {
+        bail!("Application at {} is outside of the map", outside.coord);
+    }
+    applications.sort_by_key(|a| a.tick);
+
+    let reactions = load_reactions(&options.reactions_path)
+        .with_context(|| format!("Failed to load reactions from {}", options.reactions_path))?;
+    let recipes = load_compound_recipes(COMPOUND_FILE_PATH)
+        .with_context(|| format!("Failed to load compound recipes from {COMPOUND_FILE_PATH}"))?;
+    let decay = load_decay_rates(DECAY_FILE_PATH)
+        .with_context(|| format!("Failed to load decay rates from {DECAY_FILE_PATH}"))?;
+
+    let mut simulation = Simulation::new(
+        map,
+        &afflictions,
+        reactions,
+        recipes,
+        decay,
+        Duration::from_millis(options.tick_ms),
+    );
+    let print = |simulation: &Simulation| match options.format {
+        OutputFormat::Ascii => println!("{}", ascii_frame(simulation, options.tick_ms)),
+        OutputFormat::Json => println!("{}", json_frame(simulation)),
+    };
+
+    let mut pending = applications.iter().peekable();
+    for tick in 0..options.ticks {
+        while let Some(application) = pending.next_if(|a| a.tick == tick) {
+            simulation.apply(application.coord, application.elements);
+        }
+        simulation.step();
+
+        if options.trace {
+            print(&simulation);
+        }
+    }
+    if !options.trace {
+        print(&simulation);
+    }
+
+    Ok(())
+}
+
+/// Runs a stage every `step` of simulated time, given that each run of the schedule covers
+/// `tick`. This is the simulation's stand-in for `FixedTimestepStage`, which follows the clock
+/// on the wall instead.
+struct TickedStage {
+    step: Duration,
+    tick: Duration,
+    accumulator: Duration,
+    stage: SystemStage,
+}
+
+impl Stage for TickedStage {
+    fn run(&mut self, world: &mut World) {
+        self.accumulator += self.tick;
+        while self.accumulator >= self.step {
+            self.accumulator -= self.step;
+            self.stage.run(world);
+        }
+    }
+}
+
+/// Reaction events from the latest tick, with the tile each happened on.
+struct TickEvents(Vec<(String, Coordinate)>);
+
+fn record_reaction_events(
+    event_query: Query<(Entity, &ReactionEvent, &Source), (With<Message>, Without<Handled>)>,
+    tile_query: Query<&Coordinate, With<Tile>>,
+    mut events: ResMut<TickEvents>,
+    mut commands: Commands,
+) {
+    event_query
+        .iter()
+        .for_each(|(message_entity, event, source)| {
+            if let Ok(coord) = tile_query.get(source.0) {
+                events.0.push((event.0.clone(), *coord));
+            }
+
+            commands.entity(message_entity).insert(Handled);
+        });
+}
+
+/// A map with the element systems running on it, moved on a tick at a time.
+pub struct Simulation {
+    world: World,
+    schedule: Schedule,
+    tiles: Vec<Entity>,
+    tick: u32,
+}
+
+impl Simulation {
+    /// Set up `map` with `afflictions` on its tiles, indexed the same way as the map's tiles.
+    /// Every tick moves the simulation on by `tick_length`.
+    pub fn new(
+        map: Map,
+        afflictions: &[ElementalAffliction],
+        reactions: Vec<ChemicalReaction>,
+        recipes: Vec<CompoundRecipe>,
+        decay: DecayRates,
+        tick_length: Duration,
+    ) -> Self {
+        let mut world = World::new();
+        let tiles: Vec<Entity> = (0..map.tile_count())
+            .map(|idx| {
+                let mut tile = world.spawn();
+                tile.insert(Tile)
+                    .insert(*map.tile_type_at_index(idx).unwrap())
+                    .insert(*map.structure_at_index(idx).unwrap())
+                    .insert(map.idx_to_coord(idx));
+                if let Some(affliction) = afflictions.get(idx).filter(|a| !a.is_empty()) {
+                    tile.insert(*affliction);
+                }
+
+                tile.id()
+            })
+            .collect();
+        world.spawn().insert(MapRoot {
+            tile_entities: tiles.clone(),
+        });
+
+        world.insert_resource(map);
+        world.insert_resource(CurrentState(GameState::TDMode));
+        world.insert_resource(Reactions(reactions));
+        world.insert_resource(CompoundRecipes(recipes));
+        world.insert_resource(decay);
+        world.insert_resource(TickEvents(Vec::new()));
+        world.insert_resource(MessageStats::default());
+        world.insert_resource(DamageTotals::default());
+
+        // The same core stages the game has, so the element stages slot in around them the
+        // same way.
+        let mut schedule = Schedule::default();
+        schedule
+            .add_stage(CoreStage::Update, SystemStage::single_threaded())
+            .add_stage(CoreStage::PostUpdate, SystemStage::single_threaded())
+            .add_stage_before(CoreStage::Update, MESSAGE_STAGE, message_bus());
+        add_element_stages(&mut schedule, CoreStage::Update, |step, stage| {
+            TickedStage {
+                step,
+                tick: tick_length,
+                accumulator: Duration::ZERO,
+                stage,
+            }
+        });
+        add_damage_messages(&mut schedule);
+        schedule
+            .add_system_to_stage(CoreStage::PostUpdate, record_reaction_events)
+            .add_system_to_stage(CoreStage::PostUpdate, update_changed_tiles);
+
+        Self {
+            world,
+            schedule,
+            tiles,
+            tick: 0,
+        }
+    }
+
+    /// Send elements to the tile at `coord`. They land during the next tick.
+    pub fn apply(&mut self, coord: Coordinate, elements: ElementalAffliction) {
+        let tile = self.tiles[self.world.resource::<Map>().coord_to_idx(coord)];
+        self.world
+            .spawn()
+            .insert_bundle(ApplyElementMessage::new(elements, tile));
+    }
+
+    /// Run every element system once, and any timed ones that are due.
+    pub fn step(&mut self) {
+        self.world.resource_mut::<TickEvents>().0.clear();
+        self.schedule.run(&mut self.world);
+        self.tick += 1;
+    }
+
+    /// How many ticks have been run.
+    pub fn tick(&self) -> u32 {
+        self.tick
+    }
+
+    pub fn map(&self) -> &Map {
+        self.world.resource::<Map>()
+    }
+
+    /// The elements on every tile, indexed the same way as the map's tiles.
+    pub fn afflictions(&self) -> Vec<ElementalAffliction> {
+        self.tiles
+            .iter()
+            .map(|&tile| {
+                self.world
+                    .get::<ElementalAffliction>(tile)
+                    .copied()
+                    .unwrap_or_else(ElementalAffliction::empty)
+            })
+            .collect()
+    }
+
+    /// The reaction events from the latest tick, with the tile each happened on.
+    pub fn events(&self) -> &[(String, Coordinate)] {
+        &self.world.resource::<TickEvents>().0
+    }
+}
+
+/// The character a tile type is drawn with in ASCII output.
+fn tile_symbol(tile_type: TileType) -> char {
+    match tile_type {
+        TileType::Rock => '#',
+        TileType::Water => '~',
+        TileType::Air => '"',
+        TileType::Fire => '*',
+        TileType::Barren => '.',
+        TileType::Forest => '&',
+        TileType::Grass => ',',
+        TileType::Ice => '=',
+        TileType::Mud => '%',
+        TileType::Sand => ':',
+    }
+}
+
+/// The map as a grid of tile symbols, followed by the latest tick's reaction events and the
+/// elements on every tile that has any.
+fn ascii_frame(simulation: &Simulation, tick_ms: u64) -> String {
+    let map = simulation.map();
+    let afflictions = simulation.afflictions();
+    let tick = simulation.tick();
+    let mut lines = vec![format!("Tick {tick} ({}ms)", u64::from(tick) * tick_ms)];
+
+    lines.extend((0..map.dimensions.1).map(|y| {
+        (0..map.dimensions.0)
+            .map(|x| {
+                map.tile_type_at_coord((x, y).into())
+                    .map_or(' ', |t| tile_symbol(*t))
+            })
+            .collect::<String>()
+    }));
+    lines.extend(
+        simulation
+            .events()
+            .iter()
+            .map(|(name, coord)| format!("{name} at {coord}")),
+    );
+    lines.extend(
+        afflictions
+            .iter()
+            .enumerate()
+            .filter(|(_, affliction)| !affliction.is_empty())
+            .map(|(idx, affliction)| {
+                let elements: Vec<String> = affliction
+                    .iter()
+                    .map(|(element, amount)| format!("{element} {amount}"))
+                    .collect();
+                let tile_type = map.tile_type_at_index(idx).unwrap();
+                format!(
+                    "{} {tile_type}: {}",
+                    map.idx_to_coord(idx),
+                    elements.join(", ")
+                )
+            }),
+    );
+
+    lines.join("\n")
+}
+
+/// The map in the same shape as a map file, along with the tick and its reaction events.
+fn json_frame(simulation: &Simulation) -> Value {
+    let events: Vec<Value> = simulation
+        .events()
+        .iter()
+        .map(|(name, coord)| json!({ "name": name, "coord": [coord.x, coord.y] }))
+        .collect();
+
+    json!({
+        "tick": simulation.tick(),
+        "events": events,
+        "map": map_to_json(simulation.map(), &simulation.afflictions()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn simulate_arguments() {
+        let options = parse_args(&args("map.json --ticks 40 --format json --trace")).unwrap();
+        assert_eq!(options.map_path, "map.json");
+        assert_eq!(options.ticks, 40);
+        assert_eq!(options.tick_ms, DEFAULT_TICK_MS);
+        assert_eq!(options.format, OutputFormat::Json);
+        assert!(options.trace);
+
+        assert!(parse_args(&args("--ticks 40")).is_err());
+        assert!(parse_args(&args("map.json --ticks")).is_err());
+        assert!(parse_args(&args("map.json --tick-ms 0")).is_err());
+        assert!(parse_args(&args("map.json --format xml")).is_err());
+    }
+
+    #[test]
+    fn fire_spreads_and_is_put_out_without_a_window() {
+        // A row of rock with a fire tile at one end.
+        let mut map = Map::new((4, 1));
+        for x in 1..4 {
+            map.set_tile((x, 0).into(), Some(TileType::Rock), None);
+        }
+        map.set_tile((0, 0).into(), Some(TileType::Fire), None);
+
+        let mut simulation = Simulation::new(
+            map,
+            &[],
+            load_reactions(REACTION_FILE_PATH).unwrap(),
+            Vec::new(),
+            load_decay_rates(DECAY_FILE_PATH).unwrap(),
+            Duration::from_millis(DEFAULT_TICK_MS),
+        );
+        // Spreading only moves fire around, so there has to be enough to go round all four tiles.
+        simulation.apply(
+            (0, 0).into(),
+            ElementalAffliction::single(Element::Fire, 300),
+        );
+
+        let tile_types = |simulation: &Simulation| -> String {
+            (0..4)
+                .map(|x| tile_symbol(*simulation.map().tile_type_at_coord((x, 0).into()).unwrap()))
+                .collect()
+        };
+
+        // Fire creeps along the rock one tile at a time.
+        let mut seen = vec![tile_types(&simulation)];
+        while simulation.tick() < 500 && seen.last().unwrap() != "****" {
+            simulation.step();
+            let current = tile_types(&simulation);
+            if seen.last() != Some(&current) {
+                seen.push(current);
+            }
+        }
+        assert_eq!(seen, ["*###", "**##", "***#", "****"]);
+
+        // Dousing the whole row puts it out on the next tick.
+        (0..4).for_each(|x| {
+            simulation.apply(
+                (x, 0).into(),
+                ElementalAffliction::single(Element::Water, 30),
+            );
+        });
+        simulation.step();
+        assert_eq!(tile_types(&simulation), "####");
+        assert!(simulation
+            .afflictions()
+            .iter()
+            .all(|a| a.get_element_amount(Element::Water) == 10));
+        assert_no_leaked_messages(&simulation.world);
+    }
+
+    #[test]
+    fn forests_burn_down_and_regrow() {
+        let mut map = Map::new((3, 1));
+        for x in 0..3 {
+            map.set_tile((x, 0).into(), Some(TileType::Forest), None);
+        }
+
+        let mut simulation = Simulation::new(
+            map,
+            &[],
+            load_reactions(REACTION_FILE_PATH).unwrap(),
+            Vec::new(),
+            load_decay_rates(DECAY_FILE_PATH).unwrap(),
+            Duration::from_millis(DEFAULT_TICK_MS),
+        );
+        let tile_types = |simulation: &Simulation| -> String {
+            (0..3)
+                .map(|x| tile_symbol(*simulation.map().tile_type_at_coord((x, 0).into()).unwrap()))
+                .collect()
+        };
+
+        // Lighting one end sets each forest in the row alight in turn, and then it burns down.
+        simulation.apply(
+            (0, 0).into(),
+            ElementalAffliction::single(Element::Fire, 40),
+        );
+        let mut caught_fire = [false; 3];
+        while simulation.tick() < 1000 && tile_types(&simulation) != "..." {
+            simulation.step();
+            tile_types(&simulation)
+                .chars()
+                .zip(caught_fire.iter_mut())
+                .for_each(|(symbol, caught)| *caught |= symbol == '*');
+        }
+        assert_eq!(caught_fire, [true; 3]);
+        assert_eq!(tile_types(&simulation), "...");
+
+        // Once the embers have cooled, watering the burnt ground grows the forest back.
+        (0..(5000 / DEFAULT_TICK_MS)).for_each(|_| simulation.step());
+        (0..3).for_each(|x| {
+            simulation.apply(
+                (x, 0).into(),
+                ElementalAffliction::single(Element::Water, 40),
+            );
+        });
+        simulation.step();
+        assert_eq!(tile_types(&simulation), "&&&");
+
+        // A forest struck by lightning burns down the same way.
+        simulation.apply(
+            (1, 0).into(),
+            ElementalAffliction::single(Element::Lightning, 30),
+        );
+        simulation.step();
+        assert_eq!(tile_types(&simulation), "&*&");
+        let start = simulation.tick();
+        while simulation.tick() < start + 1000 && tile_types(&simulation) != "&.&" {
+            simulation.step();
+        }
+        assert_eq!(tile_types(&simulation), "&.&");
+    }
+}
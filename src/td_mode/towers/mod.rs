@@ -0,0 +1,535 @@
+//! Towers and how they pick and hit their targets
+//!
+//! Towers are structures, so they live on tile entities. When a tile's structure becomes a
+//! tower it gets a `Tower` component holding the tower's stats, its targeting policy and how
+//! long until it can fire again. Towers only fire while a knight is stationed at them.
+
+mod upgrades;
+
+use super::enemies::{Enemy, EnemyPath, Health};
+use super::knights::{Knight, KnightId, KnightRoster, KNIGHT_FIXED_STAGE};
+use super::td_mode_prelude::*;
+use crate::prelude::*;
+use std::collections::BTreeMap;
+
+pub use upgrades::*;
+
+const FIXED_STEP_MS: u64 = 20;
+
+pub struct TowerPlugin;
+
+impl Plugin for TowerPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Gold(STARTING_GOLD))
+            .insert_resource(UpgradeTable::new())
+            .add_system(update_tower_components.run_in_state(GameState::TDMode));
+
+        let mut fixed_stage = SystemStage::parallel();
+        fixed_stage.add_system(fire_towers.run_in_state(GameState::TDMode));
+
+        // Towers aim at where enemies are after they've moved this step, and only once any
+        // knights arriving this step have taken their posts.
+        app.add_stage_after(
+            KNIGHT_FIXED_STAGE,
+            "tower_fixed_update",
+            FixedTimestepStage::new(Duration::from_millis(FIXED_STEP_MS)).with_stage(fixed_stage),
+        );
+    }
+}
+
+/// How a tower decides which enemy in range to shoot at.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Targeting {
+    /// The enemy closest to the exit portal
+    First,
+    /// The enemy furthest from the exit portal
+    Last,
+    /// The enemy with the most health left
+    Strongest,
+    /// The enemy closest to the tower
+    Closest,
+}
+
+impl Targeting {
+    pub fn all() -> [Self; 4] {
+        [Self::First, Self::Last, Self::Strongest, Self::Closest]
+    }
+
+    fn display_name(&self) -> &str {
+        match *self {
+            Self::First => "First",
+            Self::Last => "Last",
+            Self::Strongest => "Strongest",
+            Self::Closest => "Closest",
+        }
+    }
+}
+
+impl std::fmt::Display for Targeting {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.display_name())
+    }
+}
+
+/// The way a tower's shots land.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Attack {
+    /// Hits every enemy in range within `half_angle` radians of the target.
+    Cone { half_angle: f32 },
+    /// Hits only the target.
+    Single,
+    /// Hits every enemy within `radius` tiles of the target.
+    Explosive { radius: f32 },
+}
+
+impl Attack {
+    fn display_name(&self) -> &str {
+        match *self {
+            Self::Cone { .. } => "Cone",
+            Self::Single => "Single Target",
+            Self::Explosive { .. } => "Explosive",
+        }
+    }
+}
+
+impl std::fmt::Display for Attack {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.display_name())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TowerStats {
+    /// In tiles
+    pub range: f32,
+    /// Shots per second
+    pub fire_rate: f32,
+    /// Damage dealt to every enemy hit by a shot, made of the tower's element
+    pub damage: f32,
+    pub attack: Attack,
+    /// The element applied to every enemy hit by a shot
+    pub element: Element,
+    pub element_amount: u32,
+}
+
+impl TowerStats {
+    /// The stats a freshly built tower starts with.
+    pub fn base(tower_type: TowerType) -> Self {
+        match tower_type {
+            TowerType::Short => Self {
+                range: 2.0,
+                fire_rate: 1.0,
+                damage: 20.0,
+                attack: Attack::Cone {
+                    half_angle: std::f32::consts::FRAC_PI_4,
+                },
+                element: Element::Fire,
+                element_amount: 5,
+            },
+            TowerType::Medium => Self {
+                range: 3.0,
+                fire_rate: 2.0,
+                damage: 15.0,
+                attack: Attack::Single,
+                element: Element::Air,
+                element_amount: 5,
+            },
+            TowerType::Tall => Self {
+                range: 4.5,
+                fire_rate: 0.5,
+                damage: 40.0,
+                attack: Attack::Explosive { radius: 1.0 },
+                element: Element::Earth,
+                element_amount: 5,
+            },
+        }
+    }
+}
+
+#[derive(Component, Clone, Debug, PartialEq)]
+pub struct Tower {
+    pub kind: TowerType,
+    /// The tower's stats before any upgrades or its knight are accounted for
+    pub stats: TowerStats,
+    /// The upgrades bought for the tower, kept apart for each knight since every knight can
+    /// offer their own upgrade lines
+    pub upgrades: BTreeMap<KnightId, TowerUpgrades>,
+    pub targeting: Targeting,
+    /// Seconds until the tower can fire again
+    pub cooldown: f32,
+}
+
+impl Tower {
+    pub fn new(tower_type: TowerType) -> Self {
+        Self {
+            kind: tower_type,
+            stats: TowerStats::base(tower_type),
+            upgrades: BTreeMap::new(),
+            targeting: Targeting::First,
+            cooldown: 0.0,
+        }
+    }
+
+    /// How many tiers have been bought in `line` while `knight` was stationed at the tower.
+    pub fn upgrade_tiers(&self, knight: KnightId, line: UpgradeLine) -> u32 {
+        self.upgrades
+            .get(&knight)
+            .map_or(0, |upgrades| upgrades.tiers[line as usize])
+    }
+
+    /// The tower's stats with the upgrades bought for `knight`, before the knight's behaviour is
+    /// accounted for.
+    pub fn upgraded_stats(&self, knight: KnightId) -> TowerStats {
+        self.upgrades
+            .get(&knight)
+            .map_or(self.stats, |upgrades| upgrades.stats)
+    }
+
+    /// The tower's stats once the knight stationed at it has been taken into account.
+    pub fn stats_with_knight(&self, knight: &Knight) -> TowerStats {
+        let behaviour = knight.behaviour(self.kind);
+        let stats = self.upgraded_stats(knight.id);
+
+        TowerStats {
+            range: stats.range * behaviour.range,
+            fire_rate: stats.fire_rate * behaviour.fire_rate,
+            damage: stats.damage * behaviour.damage,
+            element: knight.affinity,
+            ..stats
+        }
+    }
+}
+
+/// Keep the Tower component on tiles in sync with the structure placed on them.
+///
+/// Knights assigned to a tower that gets removed go back to the reserve.
+fn update_tower_components(
+    tile_query: Query<
+        (Entity, &Coordinate, &Structure, Option<&Tower>),
+        (With<Tile>, Changed<Structure>),
+    >,
+    mut roster: ResMut<KnightRoster>,
+    mut commands: Commands,
+) {
+    tile_query.iter().for_each(
+        |(e, coord, structure, existing)| match (*structure, existing) {
+            // Keep the tower's settings if the same tower was placed again.
+            (Structure::Tower(tower_type), Some(tower)) if tower.kind == tower_type => {}
+            (Structure::Tower(tower_type), _) => {
+                commands.entity(e).insert(Tower::new(tower_type));
+            }
+            (_, Some(_)) => {
+                roster.unassign_from(*coord);
+                commands.entity(e).remove::<Tower>();
+            }
+            (_, None) => {}
+        },
+    );
+}
+
+/// A snapshot of an enemy a tower could shoot at.
+struct Candidate {
+    entity: Entity,
+    position: Vec2,
+    tiles_remaining: f32,
+    health: f32,
+}
+
+/// Pick the enemy a tower should shoot at out of the ones in range.
+fn select_target<'a>(
+    targeting: Targeting,
+    tower_position: Vec2,
+    in_range: impl Iterator<Item = &'a Candidate>,
+) -> Option<&'a Candidate> {
+    let key = |c: &Candidate| match targeting {
+        Targeting::First => -c.tiles_remaining,
+        Targeting::Last => c.tiles_remaining,
+        Targeting::Strongest => c.health,
+        Targeting::Closest => -c.position.distance(tower_position),
+    };
+
+    in_range.max_by(|a, b| key(a).total_cmp(&key(b)))
+}
+
+/// Count down tower cooldowns and fire at enemies in range.
+///
+/// Every enemy hit by a shot is sent damage from the tower and gets the tower's element applied
+/// to it. Cone and explosive shots also splash their element over the tiles they cover. Towers
+/// without a knight stationed at them are left alone.
+pub fn fire_towers(
+    mut tower_query: Query<(Entity, &Coordinate, &mut Tower)>,
+    enemy_query: Query<(Entity, &Coordinate, &EnemyPath, &Health), With<Enemy>>,
+    roster: Res<KnightRoster>,
+    mut commands: Commands,
+) {
+    let step = seconds_rate_to_fixed_rate(1.0, FIXED_STEP_MS);
+
+    tower_query
+        .iter_mut()
+        .filter_map(|(tower_entity, coord, tower)| {
+            roster
+                .stationed_at(*coord)
+                .map(|k| (tower_entity, coord, tower, k))
+        })
+        .for_each(|(tower_entity, tower_coord, mut tower, knight)| {
+            tower.cooldown = (tower.cooldown - step).max(0.0);
+            if tower.cooldown > 0.0 {
+                return;
+            }
+
+            let tower_position = Vec2::from(*tower_coord);
+            let stats = tower.stats_with_knight(knight);
+            // A knight or upgrade can bring the fire rate down to nothing, and there's no
+            // cooldown to wait out for a tower that can't fire.
+            if stats.fire_rate <= 0.0 {
+                return;
+            }
+
+            let candidates: Vec<Candidate> = enemy_query
+                .iter()
+                .filter(|(_, _, _, health)| health.current > 0.0)
+                .map(|(entity, coord, path, health)| Candidate {
+                    entity,
+                    position: path.map_position(*coord),
+                    tiles_remaining: path.tiles_remaining(),
+                    health: health.current,
+                })
+                .collect();
+
+            let target = select_target(
+                tower.targeting,
+                tower_position,
+                candidates
+                    .iter()
+                    .filter(|c| c.position.distance(tower_position) <= stats.range),
+            );
+
+            if let Some(target) = target {
+                shot_hits(&stats, tower_position, target, &candidates)
+                    .into_iter()
+                    .for_each(|e| {
+                        commands
+                            .spawn()
+                            .insert(Message)
+                            .insert(Target(e))
+                            .insert(Source(tower_entity))
+                            .insert(Damage {
+                                amount: stats.damage,
+                                element: Some(stats.element),
+                            });
+                        commands.spawn_bundle(ApplyElementMessage::single_element(
+                            stats.element,
+                            stats.element_amount,
+                            e,
+                        ));
+                    });
+
+                let target_coord = enemy_query
+                    .get(target.entity)
+                    .map_or(*tower_coord, |(_, coord, ..)| *coord);
+                let aim = target.position - tower_position;
+                if let Some(area) = shot_area(&stats, *tower_coord, target_coord, aim) {
+                    commands.spawn_bundle(ApplyAreaElementMessage::new(
+                        ElementalAffliction::single(stats.element, stats.element_amount),
+                        area,
+                    ));
+                }
+
+                tower.cooldown = 1.0 / stats.fire_rate;
+            }
+        });
+}
+
+/// Every enemy hit by a shot fired at `target`.
+fn shot_hits(
+    stats: &TowerStats,
+    tower_position: Vec2,
+    target: &Candidate,
+    candidates: &[Candidate],
+) -> Vec<Entity> {
+    match stats.attack {
+        Attack::Single => vec![target.entity],
+        Attack::Cone { half_angle } => {
+            let aim = target.position - tower_position;
+            candidates
+                .iter()
+                .filter(|c| c.position.distance(tower_position) <= stats.range)
+                .filter(|c| {
+                    c.entity == target.entity
+                        || aim.angle_between(c.position - tower_position).abs() <= half_angle
+                })
+                .map(|c| c.entity)
+                .collect()
+        }
+        Attack::Explosive { radius } => candidates
+            .iter()
+            .filter(|c| c.position.distance(target.position) <= radius)
+            .map(|c| c.entity)
+            .collect(),
+    }
+}
+
+/// The tiles a shot splashes its element over, if it splashes at all.
+fn shot_area(
+    stats: &TowerStats,
+    tower_coord: Coordinate,
+    target_coord: Coordinate,
+    aim: Vec2,
+) -> Option<TargetArea> {
+    match stats.attack {
+        Attack::Single => None,
+        Attack::Cone { half_angle } => Some(TargetArea {
+            origin: tower_coord,
+            shape: AreaShape::Cone {
+                direction: aim,
+                half_angle,
+                range: stats.range as u32,
+            },
+            falloff: Falloff::None,
+        }),
+        // Explosions are strongest where they land and fade out towards their edge.
+        Attack::Explosive { radius } => Some(TargetArea {
+            origin: target_coord,
+            shape: AreaShape::Radius(radius as u32),
+            falloff: Falloff::Linear(50),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::enemies::{
+        handle_died_messages, move_enemies, resolve_damage_messages, DamageTotals, EnemyBundle,
+        EnemyKind,
+    };
+    use super::super::knights::{KnightBehaviour, KnightId};
+    use super::*;
+
+    /// Walk a grunt past a medium tower, returning the world and the enemy once it's dead or
+    /// has had time to reach the exit.
+    fn walk_enemy_past_tower(roster: KnightRoster) -> (World, Entity) {
+        let tower_coord = Coordinate::from((4, 1));
+        let mut map = Map::new((9, 2));
+        map.wave_entry_coord = (0, 0).into();
+        map.wave_exit_coord = (8, 0).into();
+        map.set_tile(tower_coord, None, Some(Structure::Tower(TowerType::Medium)));
+
+        let mut flow_fields = FlowFields::default();
+        flow_fields.update(&map);
+
+        let mut world = World::new();
+        let enemy = world
+            .spawn()
+            .insert_bundle(EnemyBundle::at_wave_entry(
+                &map,
+                &flow_fields,
+                EnemyKind::Grunt,
+            ))
+            .id();
+        world
+            .spawn()
+            .insert(tower_coord)
+            .insert(Tower::new(TowerType::Medium));
+        world.insert_resource(map);
+        world.insert_resource(roster);
+        world.insert_resource(DamageTotals::default());
+
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(move_enemies);
+        stage.add_system(fire_towers.after(move_enemies));
+        stage.add_system(resolve_damage_messages.after(fire_towers));
+        stage.add_system(handle_died_messages.after(resolve_damage_messages));
+
+        // Walking the whole path takes 8 seconds.
+        for _ in 0..(8000 / FIXED_STEP_MS) {
+            stage.run(&mut world);
+            if world.get_entity(enemy).is_none() {
+                break;
+            }
+        }
+
+        (world, enemy)
+    }
+
+    #[test]
+    fn tower_kills_an_enemy_walking_past() {
+        let tower_coord = Coordinate::from((4, 1));
+        let mut roster = KnightRoster::new(vec![Knight {
+            id: KnightId(0),
+            name: "Normal Knight".to_string(),
+            affinity: Element::Water,
+            behaviours: [KnightBehaviour::default(); 3],
+            upgrades: Default::default(),
+        }]);
+        roster
+            .assign(KnightId(0), tower_coord, tower_coord)
+            .unwrap();
+
+        let (mut world, enemy) = walk_enemy_past_tower(roster);
+
+        assert!(world.get_entity(enemy).is_none());
+        let water_applied = world
+            .query_filtered::<&ElementalAffliction, With<ApplyElement>>()
+            .iter(&world)
+            .filter(|elements| elements.get_element_amount(Element::Water) > 0)
+            .count();
+        assert!(water_applied > 0);
+    }
+
+    #[test]
+    fn towers_without_a_knight_stay_inert() {
+        let (world, enemy) = walk_enemy_past_tower(KnightRoster::new(Vec::new()));
+
+        let health = world.get::<Health>(enemy).unwrap();
+        assert!((health.current - health.max).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn towers_with_no_fire_rate_hold_fire() {
+        let tower_coord = Coordinate::from((4, 1));
+        let behaviour = KnightBehaviour {
+            fire_rate: 0.0,
+            ..KnightBehaviour::default()
+        };
+        let mut roster = KnightRoster::new(vec![Knight {
+            id: KnightId(0),
+            name: "Idle Knight".to_string(),
+            affinity: Element::Water,
+            behaviours: [behaviour; 3],
+            upgrades: Default::default(),
+        }]);
+        roster
+            .assign(KnightId(0), tower_coord, tower_coord)
+            .unwrap();
+
+        let (world, enemy) = walk_enemy_past_tower(roster);
+
+        let health = world.get::<Health>(enemy).unwrap();
+        assert!((health.current - health.max).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn targeting_picks_the_right_enemy() {
+        let mut world = World::new();
+        let candidates: Vec<Candidate> = [(0.0, 5.0, 50.0), (2.0, 1.0, 20.0), (1.0, 3.0, 90.0)]
+            .into_iter()
+            .map(|(x, tiles_remaining, health)| Candidate {
+                entity: world.spawn().id(),
+                position: Vec2::new(x, 0.0),
+                tiles_remaining,
+                health,
+            })
+            .collect();
+
+        let pick = |targeting| {
+            select_target(targeting, Vec2::new(-1.0, 0.0), candidates.iter())
+                .unwrap()
+                .entity
+        };
+
+        assert_eq!(pick(Targeting::First), candidates[1].entity);
+        assert_eq!(pick(Targeting::Last), candidates[0].entity);
+        assert_eq!(pick(Targeting::Strongest), candidates[2].entity);
+        assert_eq!(pick(Targeting::Closest), candidates[0].entity);
+    }
+}
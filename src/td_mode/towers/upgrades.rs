@@ -0,0 +1,381 @@
+//! Tower upgrades
+//!
+//! Every tower has two upgrade lines. Both can be maxed out, but each tier bought in one line
+//! makes the tiers in the other line more expensive. Which upgrades are on offer depends on
+//! the tower type and the knight stationed at it, so a tower keeps the tiers bought with each
+//! knight separately.
+
+use super::*;
+
+/// How much more a tier costs, in percent of its base cost, for each tier owned in the other line.
+pub const CROSS_LINE_PENALTY_PERCENT: u32 = 50;
+
+/// How much gold the player starts with.
+pub const STARTING_GOLD: u32 = 500;
+
+/// Resource holding the player's currency.
+#[derive(Debug, PartialEq)]
+pub struct Gold(pub u32);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpgradeLine {
+    Power,
+    Speed,
+}
+
+impl UpgradeLine {
+    pub fn all() -> [Self; 2] {
+        [Self::Power, Self::Speed]
+    }
+
+    pub fn other(&self) -> Self {
+        match *self {
+            Self::Power => Self::Speed,
+            Self::Speed => Self::Power,
+        }
+    }
+
+    fn display_name(&self) -> &str {
+        match *self {
+            Self::Power => "Power",
+            Self::Speed => "Speed",
+        }
+    }
+
+    /// Look up an upgrade line by the name it is displayed with.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::all().into_iter().find(|l| l.display_name() == name)
+    }
+}
+
+impl std::fmt::Display for UpgradeLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.display_name())
+    }
+}
+
+/// Stats added to a tower when an upgrade is bought.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StatDelta {
+    pub range: f32,
+    pub fire_rate: f32,
+    pub damage: f32,
+    pub element_amount: u32,
+}
+
+impl StatDelta {
+    pub fn apply(&self, stats: &mut TowerStats) {
+        stats.range += self.range;
+        stats.fire_rate += self.fire_rate;
+        stats.damage += self.damage;
+        stats.element_amount += self.element_amount;
+    }
+}
+
+/// The upgrades bought for a tower while one knight was stationed at it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TowerUpgrades {
+    /// How many tiers have been bought in each line, indexed by `UpgradeLine`
+    pub tiers: [u32; 2],
+    /// The tower's stats with those tiers applied
+    pub stats: TowerStats,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct UpgradeTier {
+    pub name: String,
+    /// What the tier costs before the cross-line penalty
+    pub base_cost: u32,
+    pub delta: StatDelta,
+}
+
+/// The two upgrade lines offered for one tower/knight combo.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UpgradePath {
+    pub power: Vec<UpgradeTier>,
+    pub speed: Vec<UpgradeTier>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum UpgradeError {
+    /// Every tier in the line has already been bought.
+    MaxTier(UpgradeLine),
+    NotEnoughGold {
+        cost: u32,
+        available: u32,
+    },
+}
+
+impl std::fmt::Display for UpgradeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MaxTier(line) => write!(f, "The {line} line is already maxed out"),
+            Self::NotEnoughGold { cost, available } => {
+                write!(
+                    f,
+                    "The upgrade costs {cost} gold but only {available} is available"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for UpgradeError {}
+
+/// What a tier with the given base cost actually costs once the tiers owned in the other line
+/// are taken into account. Costs too large to count saturate at `u32::MAX`.
+pub fn upgrade_cost(base_cost: u32, other_line_tiers: u32) -> u32 {
+    let percent = u64::from(CROSS_LINE_PENALTY_PERCENT)
+        .saturating_mul(u64::from(other_line_tiers))
+        .saturating_add(100);
+    let cost = u64::from(base_cost).saturating_mul(percent) / 100;
+
+    u32::try_from(cost).unwrap_or(u32::MAX)
+}
+
+impl UpgradePath {
+    /// The upgrades offered when a knight doesn't have a set of their own for a tower type.
+    ///
+    /// Power tiers add damage and Speed tiers add fire rate, both scaled off the tower's base
+    /// stats. Taller towers cost more to upgrade.
+    pub fn default_for(tower_type: TowerType) -> Self {
+        let base = TowerStats::base(tower_type);
+        let cost_scale = match tower_type {
+            TowerType::Short => 80,
+            TowerType::Medium => 100,
+            TowerType::Tall => 150,
+        };
+
+        let tiers = |names: [&str; 3], delta: &dyn Fn(f32) -> StatDelta| {
+            names
+                .into_iter()
+                .zip([(1, 0.25), (2, 0.25), (4, 0.5)])
+                .map(|(name, (cost, fraction))| UpgradeTier {
+                    name: name.to_string(),
+                    base_cost: cost * cost_scale,
+                    delta: delta(fraction),
+                })
+                .collect()
+        };
+
+        Self {
+            power: tiers(["Sharpened", "Reinforced", "Masterwork"], &|f| StatDelta {
+                damage: base.damage * f,
+                ..default()
+            }),
+            speed: tiers(["Drilled", "Practiced", "Relentless"], &|f| StatDelta {
+                fire_rate: base.fire_rate * f,
+                ..default()
+            }),
+        }
+    }
+
+    pub fn line(&self, line: UpgradeLine) -> &[UpgradeTier] {
+        match line {
+            UpgradeLine::Power => &self.power,
+            UpgradeLine::Speed => &self.speed,
+        }
+    }
+
+    /// The next tier a tower can buy in `line` with `knight` stationed at it and what it would
+    /// cost, or None if the line is maxed out.
+    pub fn next_tier(
+        &self,
+        tower: &Tower,
+        knight: KnightId,
+        line: UpgradeLine,
+    ) -> Option<(&UpgradeTier, u32)> {
+        self.line(line)
+            .get(tower.upgrade_tiers(knight, line) as usize)
+            .map(|tier| {
+                let cost = upgrade_cost(tier.base_cost, tower.upgrade_tiers(knight, line.other()));
+                (tier, cost)
+            })
+    }
+
+    /// Buy the next tier in `line` for a tower with `knight` stationed at it, paying for it out
+    /// of `gold`.
+    ///
+    /// Returns what the upgrade cost. Nothing changes if the purchase fails.
+    pub fn purchase(
+        &self,
+        tower: &mut Tower,
+        knight: KnightId,
+        line: UpgradeLine,
+        gold: &mut Gold,
+    ) -> Result<u32, UpgradeError> {
+        let (tier, cost) = self
+            .next_tier(tower, knight, line)
+            .ok_or(UpgradeError::MaxTier(line))?;
+
+        if cost > gold.0 {
+            return Err(UpgradeError::NotEnoughGold {
+                cost,
+                available: gold.0,
+            });
+        }
+
+        gold.0 -= cost;
+        let base_stats = tower.stats;
+        let upgrades = tower.upgrades.entry(knight).or_insert(TowerUpgrades {
+            tiers: [0; 2],
+            stats: base_stats,
+        });
+        tier.delta.apply(&mut upgrades.stats);
+        upgrades.tiers[line as usize] += 1;
+
+        Ok(cost)
+    }
+}
+
+/// Resource holding the upgrades towers offer when their knight doesn't have their own.
+pub struct UpgradeTable {
+    defaults: [UpgradePath; 3],
+}
+
+impl UpgradeTable {
+    pub fn new() -> Self {
+        Self {
+            defaults: TowerType::all().map(UpgradePath::default_for),
+        }
+    }
+
+    pub fn default_for(&self, tower_type: TowerType) -> &UpgradePath {
+        &self.defaults[tower_type as usize]
+    }
+
+    /// The upgrades on offer for a tower type with the given knight stationed at it.
+    pub fn path_for<'a>(&'a self, knight: &'a Knight, tower_type: TowerType) -> &'a UpgradePath {
+        knight.upgrades[tower_type as usize]
+            .as_ref()
+            .unwrap_or_else(|| self.default_for(tower_type))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KNIGHT: KnightId = KnightId(0);
+
+    fn flat_path() -> UpgradePath {
+        let tiers = |costs: [u32; 3]| {
+            costs
+                .into_iter()
+                .map(|base_cost| UpgradeTier {
+                    name: format!("{base_cost}"),
+                    base_cost,
+                    delta: StatDelta {
+                        damage: 1.0,
+                        ..default()
+                    },
+                })
+                .collect()
+        };
+
+        UpgradePath {
+            power: tiers([100, 200, 400]),
+            speed: tiers([100, 200, 400]),
+        }
+    }
+
+    /// Buy upgrades in order, returning what each one cost.
+    fn buy_in_order(order: &[UpgradeLine]) -> Vec<u32> {
+        let path = flat_path();
+        let mut tower = Tower::new(TowerType::Medium);
+        let mut gold = Gold(u32::MAX);
+
+        order
+            .iter()
+            .map(|&line| path.purchase(&mut tower, KNIGHT, line, &mut gold).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn upgrades_get_pricier_as_the_other_line_grows() {
+        use UpgradeLine::{Power, Speed};
+
+        // Finishing one line first and then buying the other
+        assert_eq!(
+            buy_in_order(&[Power, Power, Power, Speed, Speed, Speed]),
+            vec![100, 200, 400, 250, 500, 1000]
+        );
+        assert_eq!(
+            buy_in_order(&[Speed, Speed, Speed, Power, Power, Power]),
+            vec![100, 200, 400, 250, 500, 1000]
+        );
+
+        // Alternating between lines
+        assert_eq!(
+            buy_in_order(&[Power, Speed, Power, Speed, Power, Speed]),
+            vec![100, 150, 300, 400, 800, 1000]
+        );
+
+        // Spreading purchases across both lines costs more overall than finishing one first
+        let total = |order: &[UpgradeLine]| buy_in_order(order).iter().sum::<u32>();
+        assert_eq!(total(&[Power, Power, Power, Speed, Speed, Speed]), 2450);
+        assert_eq!(total(&[Power, Speed, Speed, Power, Speed, Power]), 2750);
+    }
+
+    #[test]
+    fn purchases_check_gold_and_tiers() {
+        let path = flat_path();
+        let mut tower = Tower::new(TowerType::Medium);
+        let mut gold = Gold(150);
+
+        assert_eq!(
+            path.purchase(&mut tower, KNIGHT, UpgradeLine::Power, &mut gold),
+            Ok(100)
+        );
+        assert_eq!(gold, Gold(50));
+        assert!((tower.upgraded_stats(KNIGHT).damage - 16.0).abs() < f32::EPSILON);
+
+        assert_eq!(
+            path.purchase(&mut tower, KNIGHT, UpgradeLine::Speed, &mut gold),
+            Err(UpgradeError::NotEnoughGold {
+                cost: 150,
+                available: 50
+            })
+        );
+        assert_eq!(gold, Gold(50));
+        assert_eq!(tower.upgrade_tiers(KNIGHT, UpgradeLine::Speed), 0);
+
+        gold.0 = u32::MAX;
+        for _ in 0..2 {
+            path.purchase(&mut tower, KNIGHT, UpgradeLine::Power, &mut gold)
+                .unwrap();
+        }
+        assert_eq!(
+            path.purchase(&mut tower, KNIGHT, UpgradeLine::Power, &mut gold),
+            Err(UpgradeError::MaxTier(UpgradeLine::Power))
+        );
+    }
+
+    #[test]
+    fn each_knight_keeps_their_own_tiers() {
+        let path = flat_path();
+        let mut tower = Tower::new(TowerType::Medium);
+        let mut gold = Gold(u32::MAX);
+        path.purchase(&mut tower, KNIGHT, UpgradeLine::Power, &mut gold)
+            .unwrap();
+
+        // A different knight starts from the tower's base stats and the first tier.
+        let other = KnightId(1);
+        assert_eq!(tower.upgrade_tiers(other, UpgradeLine::Power), 0);
+        assert_eq!(tower.upgraded_stats(other), tower.stats);
+        assert_eq!(
+            path.purchase(&mut tower, other, UpgradeLine::Speed, &mut gold),
+            Ok(100)
+        );
+
+        assert_eq!(tower.upgrade_tiers(KNIGHT, UpgradeLine::Power), 1);
+        assert_eq!(tower.upgrade_tiers(KNIGHT, UpgradeLine::Speed), 0);
+    }
+
+    #[test]
+    fn huge_costs_saturate() {
+        assert_eq!(upgrade_cost(u32::MAX, 0), u32::MAX);
+        assert_eq!(upgrade_cost(u32::MAX, 3), u32::MAX);
+        assert_eq!(upgrade_cost(1_000_000, u32::MAX), u32::MAX);
+    }
+}